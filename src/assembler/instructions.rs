@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use ux::u5;
+
+use super::{
+    csr_operand, evaluate, expect_operands, immediate, memory_operand, register, split, target,
+    upper, AssemblerError, AssemblerErrorKind, Statement,
+};
+use crate::risc_machine::CPUInstruction;
+
+const ZERO: u5 = u5::new(0);
+const RA: u5 = u5::new(1);
+// the scratch register used by tail
+const T1: u5 = u5::new(6);
+
+impl Statement<'_> {
+    fn expect(&self, counts: &[usize]) -> Result<(), AssemblerError> {
+        expect_operands(&self.mnemonic, &self.operands, counts)
+    }
+
+    // The number of bytes the statement assembles to, where constants holds
+    // the symbols defined so far by .equ. A li whose value is not yet known
+    // always takes two instructions
+    pub(super) fn size(&self, constants: &HashMap<String, i64>) -> u32 {
+        match self.mnemonic.text.to_ascii_lowercase().as_str() {
+            "la" | "call" | "tail" => 8,
+            "li" => match self.operands.get(1).map(|value| evaluate(value, constants)) {
+                Some(Ok((value, _))) => {
+                    let (hi, lo) = split(value);
+                    if hi == 0 || lo == 0 {
+                        4
+                    } else {
+                        8
+                    }
+                }
+                _ => 8,
+            },
+            _ => 4,
+        }
+    }
+
+    pub(super) fn encode(
+        &self,
+        symbols: &HashMap<String, i64>,
+    ) -> Result<Vec<CPUInstruction>, AssemblerError> {
+        let operands = &self.operands;
+        let mnemonic = self.mnemonic.text.to_ascii_lowercase();
+        let instruction = match mnemonic.as_str() {
+            "add" | "sub" | "sltu" => {
+                self.expect(&[3])?;
+                let (rd, rs1, rs2) = (
+                    register(&operands[0])?,
+                    register(&operands[1])?,
+                    register(&operands[2])?,
+                );
+                match mnemonic.as_str() {
+                    "add" => CPUInstruction::ADD { rd, rs1, rs2 },
+                    "sub" => CPUInstruction::SUB { rd, rs1, rs2 },
+                    _ => CPUInstruction::SLTU { rd, rs1, rs2 },
+                }
+            }
+            "addi" | "sltiu" | "xori" => {
+                self.expect(&[3])?;
+                let (rd, rs1) = (register(&operands[0])?, register(&operands[1])?);
+                let imm = immediate(&operands[2], symbols)?;
+                match mnemonic.as_str() {
+                    "addi" => CPUInstruction::ADDI { rd, rs1, imm },
+                    "sltiu" => CPUInstruction::SLTIU { rd, rs1, imm },
+                    _ => CPUInstruction::XORI { rd, rs1, imm },
+                }
+            }
+            "lui" | "auipc" => {
+                self.expect(&[2])?;
+                let rd = register(&operands[0])?;
+                let imm = upper(&operands[1], symbols)?;
+                match mnemonic.as_str() {
+                    "lui" => CPUInstruction::LUI { rd, imm },
+                    _ => CPUInstruction::AUIPC { rd, imm },
+                }
+            }
+            "lw" | "lh" | "lhu" | "lb" | "lbu" => {
+                self.expect(&[2])?;
+                let rd = register(&operands[0])?;
+                let (imm, rs1) = memory_operand(&operands[1], symbols)?;
+                match mnemonic.as_str() {
+                    "lw" => CPUInstruction::LW { rd, rs1, imm },
+                    "lh" => CPUInstruction::LH { rd, rs1, imm },
+                    "lhu" => CPUInstruction::LHU { rd, rs1, imm },
+                    "lb" => CPUInstruction::LB { rd, rs1, imm },
+                    _ => CPUInstruction::LBU { rd, rs1, imm },
+                }
+            }
+            "sw" | "sh" | "sb" => {
+                self.expect(&[2])?;
+                let rs2 = register(&operands[0])?;
+                let (imm, rs1) = memory_operand(&operands[1], symbols)?;
+                match mnemonic.as_str() {
+                    "sw" => CPUInstruction::SW { rs1, rs2, imm },
+                    "sh" => CPUInstruction::SH { rs1, rs2, imm },
+                    _ => CPUInstruction::SB { rs1, rs2, imm },
+                }
+            }
+            "jal" => {
+                self.expect(&[2, 1])?;
+                // jal with only a target links to ra
+                let rd = match operands.len() {
+                    2 => register(&operands[0])?,
+                    _ => RA,
+                };
+                let imm = target(operands.last().unwrap(), self.address, 21, symbols)?;
+                CPUInstruction::JAL { rd, imm }
+            }
+            "jalr" => {
+                self.expect(&[3, 2, 1])?;
+                let (rd, rs1, imm) = match operands.len() {
+                    3 => (
+                        register(&operands[0])?,
+                        register(&operands[1])?,
+                        immediate(&operands[2], symbols)?,
+                    ),
+                    2 if operands[1].text.contains('(') => {
+                        let (imm, rs1) = memory_operand(&operands[1], symbols)?;
+                        (register(&operands[0])?, rs1, imm)
+                    }
+                    2 => (register(&operands[0])?, register(&operands[1])?, 0),
+                    _ => (RA, register(&operands[0])?, 0),
+                };
+                CPUInstruction::JALR { rd, rs1, imm }
+            }
+            "beq" | "bne" | "blt" | "bge" => {
+                self.expect(&[3])?;
+                let (rs1, rs2) = (register(&operands[0])?, register(&operands[1])?);
+                let imm = target(&operands[2], self.address, 13, symbols)?;
+                match mnemonic.as_str() {
+                    "beq" => CPUInstruction::BEQ { rs1, rs2, imm },
+                    "bne" => CPUInstruction::BNE { rs1, rs2, imm },
+                    "blt" => CPUInstruction::BLT { rs1, rs2, imm },
+                    _ => CPUInstruction::BGE { rs1, rs2, imm },
+                }
+            }
+            "csrrw" | "csrrs" | "csrrc" => {
+                self.expect(&[3])?;
+                let rd = register(&operands[0])?;
+                let csr = csr_operand(&operands[1], symbols)?;
+                let rs1 = register(&operands[2])?;
+                match mnemonic.as_str() {
+                    "csrrw" => CPUInstruction::CSRRW { rd, rs1, csr },
+                    "csrrs" => CPUInstruction::CSRRS { rd, rs1, csr },
+                    _ => CPUInstruction::CSRRC { rd, rs1, csr },
+                }
+            }
+            "mret" => {
+                self.expect(&[0])?;
+                CPUInstruction::MRET
+            }
+            "wfi" => {
+                self.expect(&[0])?;
+                CPUInstruction::WFI
+            }
+            _ => return self.encode_pseudo(&mnemonic, symbols),
+        };
+        Ok(vec![instruction])
+    }
+
+    // Pseudo-instructions, expanded in the same way as the GNU assembler
+    fn encode_pseudo(
+        &self,
+        mnemonic: &str,
+        symbols: &HashMap<String, i64>,
+    ) -> Result<Vec<CPUInstruction>, AssemblerError> {
+        let operands = &self.operands;
+        let instructions = match mnemonic {
+            "nop" => {
+                self.expect(&[0])?;
+                vec![CPUInstruction::ADDI {
+                    rd: ZERO,
+                    rs1: ZERO,
+                    imm: 0,
+                }]
+            }
+            "mv" | "not" | "neg" | "seqz" | "snez" => {
+                self.expect(&[2])?;
+                let (rd, rs) = (register(&operands[0])?, register(&operands[1])?);
+                vec![match mnemonic {
+                    "mv" => CPUInstruction::ADDI {
+                        rd,
+                        rs1: rs,
+                        imm: 0,
+                    },
+                    "not" => CPUInstruction::XORI {
+                        rd,
+                        rs1: rs,
+                        imm: u32::MAX,
+                    },
+                    "neg" => CPUInstruction::SUB {
+                        rd,
+                        rs1: ZERO,
+                        rs2: rs,
+                    },
+                    "seqz" => CPUInstruction::SLTIU {
+                        rd,
+                        rs1: rs,
+                        imm: 1,
+                    },
+                    _ => CPUInstruction::SLTU {
+                        rd,
+                        rs1: ZERO,
+                        rs2: rs,
+                    },
+                }]
+            }
+            "li" => {
+                self.expect(&[2])?;
+                let rd = register(&operands[0])?;
+                let (value, _) = evaluate(&operands[1], symbols)?;
+                if !(i64::from(i32::MIN)..=i64::from(u32::MAX)).contains(&value) {
+                    return Err(operands[1].error(AssemblerErrorKind::OutOfRange(value)));
+                }
+                let (hi, lo) = split(value);
+                let lui = CPUInstruction::LUI { rd, imm: hi };
+                let addi = |rs1| CPUInstruction::ADDI { rd, rs1, imm: lo };
+                // the size was chosen by the first pass, so a value which was
+                // not yet known keeps both instructions
+                match self.size {
+                    8 => vec![lui, addi(rd)],
+                    _ if hi == 0 => vec![addi(ZERO)],
+                    _ => vec![lui],
+                }
+            }
+            "la" | "call" | "tail" => {
+                let (rd, destination) = match mnemonic {
+                    "la" => {
+                        self.expect(&[2])?;
+                        (register(&operands[0])?, &operands[1])
+                    }
+                    "call" => {
+                        self.expect(&[1])?;
+                        (RA, &operands[0])
+                    }
+                    _ => {
+                        self.expect(&[1])?;
+                        (T1, &operands[0])
+                    }
+                };
+                // the address is reached relative to the auipc
+                let (value, _) = evaluate(destination, symbols)?;
+                let (hi, lo) = split(value - i64::from(self.address));
+                let second = match mnemonic {
+                    "la" => CPUInstruction::ADDI {
+                        rd,
+                        rs1: rd,
+                        imm: lo,
+                    },
+                    "call" => CPUInstruction::JALR {
+                        rd: RA,
+                        rs1: RA,
+                        imm: lo,
+                    },
+                    _ => CPUInstruction::JALR {
+                        rd: ZERO,
+                        rs1: T1,
+                        imm: lo,
+                    },
+                };
+                vec![CPUInstruction::AUIPC { rd, imm: hi }, second]
+            }
+            "j" => {
+                self.expect(&[1])?;
+                let imm = target(&operands[0], self.address, 21, symbols)?;
+                vec![CPUInstruction::JAL { rd: ZERO, imm }]
+            }
+            "jr" | "ret" => {
+                let rs1 = match mnemonic {
+                    "jr" => {
+                        self.expect(&[1])?;
+                        register(&operands[0])?
+                    }
+                    _ => {
+                        self.expect(&[0])?;
+                        RA
+                    }
+                };
+                vec![CPUInstruction::JALR {
+                    rd: ZERO,
+                    rs1,
+                    imm: 0,
+                }]
+            }
+            "beqz" | "bnez" => {
+                self.expect(&[2])?;
+                let rs1 = register(&operands[0])?;
+                let imm = target(&operands[1], self.address, 13, symbols)?;
+                vec![match mnemonic {
+                    "beqz" => CPUInstruction::BEQ {
+                        rs1,
+                        rs2: ZERO,
+                        imm,
+                    },
+                    _ => CPUInstruction::BNE {
+                        rs1,
+                        rs2: ZERO,
+                        imm,
+                    },
+                }]
+            }
+            // the comparisons are made with the operands swapped
+            "bgt" | "ble" => {
+                self.expect(&[3])?;
+                let (rs2, rs1) = (register(&operands[0])?, register(&operands[1])?);
+                let imm = target(&operands[2], self.address, 13, symbols)?;
+                vec![match mnemonic {
+                    "bgt" => CPUInstruction::BLT { rs1, rs2, imm },
+                    _ => CPUInstruction::BGE { rs1, rs2, imm },
+                }]
+            }
+            _ => {
+                return Err(self.mnemonic.error(AssemblerErrorKind::UnknownMnemonic(
+                    self.mnemonic.text.to_string(),
+                )))
+            }
+        };
+        Ok(instructions)
+    }
+}
@@ -0,0 +1,786 @@
+mod instructions;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use ux::{u12, u5};
+
+use crate::csr;
+use crate::elf::symbols::{Symbol, SymbolKind, SymbolMap};
+use crate::memory::Memory;
+use crate::risc_machine::CPUInstruction;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblerErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    UnknownRegister(String),
+    UnknownCSR(String),
+    // the operand is not a register, expression, string or memory reference
+    InvalidOperand(String),
+    OperandCount { expected: usize, found: usize },
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    // the value does not fit in the immediate of the instruction, or the
+    // width of the data
+    OutOfRange(i64),
+    // a branch or jump by an odd number of bytes
+    Misaligned(i64),
+    // instructions and data cannot be placed in the zero initialised .bss
+    DataInBss,
+}
+
+// The line and column start at one, with the column pointing at the start of
+// the token which caused the error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {:?}", self.line, self.column, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+// The assembled sections, each placed at its own address, with the labels as
+// symbols. The entry point is _start if it is defined, or else the start of
+// the .text section
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub sections: Vec<Section>,
+    pub symbols: SymbolMap,
+    pub entry: u32,
+}
+
+impl Program {
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    // The instructions of the .text section
+    pub fn words(&self) -> Vec<u32> {
+        self.section(".text")
+            .map(|section| {
+                section
+                    .data
+                    .chunks(4)
+                    .map(|word| u32::from_le_bytes(word.try_into().unwrap_or_default()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn load(&self, memory: &mut Memory) -> Result<(), ()> {
+        for section in self.sections.iter() {
+            memory.load_slice(section.address, &section.data)?;
+        }
+        Ok(())
+    }
+}
+
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const CSR_NAMES: [(&str, u16); 19] = [
+    ("mstatus", csr::MSTATUS),
+    ("misa", csr::MISA),
+    ("mie", csr::MIE),
+    ("mtvec", csr::MTVEC),
+    ("mtvt", csr::MTVT),
+    ("mscratch", csr::MSCRATCH),
+    ("mepc", csr::MEPC),
+    ("mcause", csr::MCAUSE),
+    ("mtval", csr::MTVAL),
+    ("mip", csr::MIP),
+    ("mnxti", csr::MNXTI),
+    ("mintthresh", csr::MINTTHRESH),
+    ("pmpcfg0", csr::PMPCFG0),
+    ("pmpaddr0", csr::PMPADDR0),
+    ("mvendorid", csr::MVENDORID),
+    ("marchid", csr::MARCHID),
+    ("mimpid", csr::MIMPID),
+    ("mhartid", csr::MHARTID),
+    ("mintstatus", csr::MINTSTATUS),
+];
+
+// The sections are aligned to at least a word, so that .text is executable
+const SECTION_ALIGNMENT: u32 = 4;
+
+// A piece of a line, with the column at which it starts
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, kind: AssemblerErrorKind) -> AssemblerError {
+        AssemblerError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    // The part of the token between the given byte offsets, without the
+    // surrounding whitespace
+    fn slice(&self, start: usize, end: usize) -> Self {
+        let text = &self.text[start..end];
+        let leading = text.len() - text.trim_start().len();
+        Token {
+            text: text.trim(),
+            line: self.line,
+            column: self.column + self.text[..start + leading].chars().count(),
+        }
+    }
+}
+
+// An instruction, where the address is the offset into its section until the
+// sections are placed
+#[derive(Debug, Clone)]
+struct Statement<'a> {
+    address: u32,
+    size: u32,
+    mnemonic: Token<'a>,
+    operands: Vec<Token<'a>>,
+}
+
+struct Line<'a> {
+    labels: Vec<Token<'a>>,
+    statement: Option<(Token<'a>, Vec<Token<'a>>)>,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+// Tracks whether each character of a line lies within a string
+#[derive(Default)]
+struct Quotes {
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Quotes {
+    fn is_quoted(&mut self, c: char) -> bool {
+        let quoted = self.in_string;
+        match c {
+            _ if self.escaped => self.escaped = false,
+            '\\' if self.in_string => self.escaped = true,
+            '"' => self.in_string = !self.in_string,
+            _ => {}
+        }
+        quoted || c == '"'
+    }
+}
+
+// Split a line into its labels, mnemonic and comma separated operands, ignoring
+// everything after a # which is not in a string
+fn parse_line(text: &str, line: usize) -> Line<'_> {
+    let whole = Token {
+        text,
+        line,
+        column: 1,
+    };
+    let mut quotes = Quotes::default();
+    let end = text
+        .char_indices()
+        .find(|(_, c)| !quotes.is_quoted(*c) && *c == '#')
+        .map_or(text.len(), |(i, _)| i);
+    let mut start = 0;
+    let mut labels = Vec::new();
+    loop {
+        let rest = &text[start..end];
+        let offset = start + rest.len() - rest.trim_start().len();
+        let length = text[offset..end]
+            .find(|c| !is_symbol_char(c))
+            .unwrap_or(end - offset);
+        if length == 0 || !text[offset + length..end].starts_with(':') {
+            start = offset;
+            break;
+        }
+        labels.push(whole.slice(offset, offset + length));
+        start = offset + length + 1;
+    }
+    if start == end {
+        return Line {
+            labels,
+            statement: None,
+        };
+    }
+
+    let length = text[start..end]
+        .find(char::is_whitespace)
+        .unwrap_or(end - start);
+    let mnemonic = whole.slice(start, start + length);
+    let mut operands = Vec::new();
+    let mut operand_start = start + length;
+    let mut depth = 0;
+    let mut quotes = Quotes::default();
+    for (i, c) in text[operand_start..end].char_indices() {
+        let i = i + start + length;
+        if quotes.is_quoted(c) {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(whole.slice(operand_start, i));
+                operand_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = whole.slice(operand_start, end);
+    if !last.text.is_empty() || !operands.is_empty() {
+        operands.push(last);
+    }
+    Line {
+        labels,
+        statement: Some((mnemonic, operands)),
+    }
+}
+
+fn expect_operands(
+    mnemonic: &Token,
+    operands: &[Token],
+    counts: &[usize],
+) -> Result<(), AssemblerError> {
+    if counts.contains(&operands.len()) {
+        return Ok(());
+    }
+    Err(mnemonic.error(AssemblerErrorKind::OperandCount {
+        expected: counts[0],
+        found: operands.len(),
+    }))
+}
+
+fn register(token: &Token) -> Result<u5, AssemblerError> {
+    let name = token.text;
+    let index = match name {
+        "fp" => Some(8),
+        _ => match name.strip_prefix('x') {
+            Some(number) if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => {
+                number.parse::<u8>().ok().filter(|index| *index < 32)
+            }
+            _ => ABI_NAMES
+                .iter()
+                .position(|abi| *abi == name)
+                .map(|index| index as u8),
+        },
+    };
+    index
+        .map(u5::new)
+        .ok_or_else(|| token.error(AssemblerErrorKind::UnknownRegister(name.to_string())))
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+    u32::from_str_radix(digits, radix).ok().map(i64::from)
+}
+
+// Evaluate a sum of numbers and symbols, returning whether any symbol was used
+fn evaluate(token: &Token, symbols: &HashMap<String, i64>) -> Result<(i64, bool), AssemblerError> {
+    let invalid = || token.error(AssemblerErrorKind::InvalidOperand(token.text.to_string()));
+    let mut value = 0i64;
+    let mut symbolic = false;
+    let mut sign = 1;
+    let mut start = 0;
+    // an expression must not be empty or end with an operator
+    let mut pending = true;
+    let text = token.text;
+    while start < text.len() {
+        let rest = &text[start..];
+        let trimmed = rest.trim_start();
+        let offset = start + rest.len() - trimmed.len();
+        if let Some(after) = trimmed.strip_prefix(['+', '-']) {
+            if trimmed.starts_with('-') {
+                sign = -sign;
+            }
+            pending = true;
+            start = text.len() - after.len();
+            continue;
+        }
+        let length = trimmed
+            .find(|c| !is_symbol_char(c))
+            .unwrap_or(trimmed.len());
+        if length == 0 {
+            return Err(invalid());
+        }
+        let term = token.slice(offset, offset + length);
+        let term_value = if term.text.starts_with(|c: char| c.is_ascii_digit()) {
+            parse_number(term.text).ok_or_else(invalid)?
+        } else {
+            symbolic = true;
+            symbols.get(term.text).copied().ok_or_else(|| {
+                term.error(AssemblerErrorKind::UndefinedSymbol(term.text.to_string()))
+            })?
+        };
+        value = term_value
+            .checked_mul(sign)
+            .and_then(|term_value| value.checked_add(term_value))
+            .ok_or_else(|| term.error(AssemblerErrorKind::OutOfRange(term_value)))?;
+        sign = 1;
+        pending = false;
+
+        // terms must be separated by an operator
+        let after = text[offset + length..].trim_start();
+        if !after.is_empty() && !after.starts_with(['+', '-']) {
+            return Err(invalid());
+        }
+        start = offset + length;
+    }
+    if pending {
+        return Err(invalid());
+    }
+    Ok((value, symbolic))
+}
+
+// Split a value into an upper immediate and a sign extended lower 12 bits,
+// which add back to the value
+fn split(value: i64) -> (u32, u32) {
+    let value = value as u32;
+    let hi = value.wrapping_add(0x800) & 0xffff_f000;
+    (hi, value.wrapping_sub(hi))
+}
+
+// The expression within a %hi(...) or %lo(...) operand
+fn relocation<'a>(token: &Token<'a>, name: &str) -> Option<Token<'a>> {
+    let inner = token
+        .text
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    let start = name.len() + 1;
+    Some(token.slice(start, start + inner.len()))
+}
+
+fn immediate(token: &Token, symbols: &HashMap<String, i64>) -> Result<u32, AssemblerError> {
+    if let Some(inner) = relocation(token, "%lo") {
+        let (value, _) = evaluate(&inner, symbols)?;
+        return Ok(split(value).1);
+    }
+    let (value, _) = evaluate(token, symbols)?;
+    if !(-2048..=2047).contains(&value) {
+        return Err(token.error(AssemblerErrorKind::OutOfRange(value)));
+    }
+    Ok(value as u32)
+}
+
+// The operand of lui and auipc, giving the upper 20 bits of the result
+fn upper(token: &Token, symbols: &HashMap<String, i64>) -> Result<u32, AssemblerError> {
+    if let Some(inner) = relocation(token, "%hi") {
+        let (value, _) = evaluate(&inner, symbols)?;
+        return Ok(split(value).0);
+    }
+    let (value, _) = evaluate(token, symbols)?;
+    if !(0..=0xfffff).contains(&value) {
+        return Err(token.error(AssemblerErrorKind::OutOfRange(value)));
+    }
+    Ok((value as u32) << 12)
+}
+
+// A branch or jump target, where a bare number is the offset from the
+// instruction and an expression using a symbol is an address
+fn target(
+    token: &Token,
+    address: u32,
+    bits: u32,
+    symbols: &HashMap<String, i64>,
+) -> Result<u32, AssemblerError> {
+    let (value, symbolic) = evaluate(token, symbols)?;
+    let offset = if symbolic {
+        value - i64::from(address)
+    } else {
+        value
+    };
+    let limit = 1i64 << (bits - 1);
+    if !(-limit..limit).contains(&offset) {
+        return Err(token.error(AssemblerErrorKind::OutOfRange(offset)));
+    }
+    if offset % 2 != 0 {
+        return Err(token.error(AssemblerErrorKind::Misaligned(offset)));
+    }
+    Ok(offset as u32)
+}
+
+// An offset(register) operand, where the offset may be omitted
+fn memory_operand(
+    token: &Token,
+    symbols: &HashMap<String, i64>,
+) -> Result<(u32, u5), AssemblerError> {
+    let invalid = || token.error(AssemblerErrorKind::InvalidOperand(token.text.to_string()));
+    let open = token.text.rfind('(').ok_or_else(invalid)?;
+    if !token.text.ends_with(')') {
+        return Err(invalid());
+    }
+    let base = register(&token.slice(open + 1, token.text.len() - 1))?;
+    let offset = token.slice(0, open);
+    if offset.text.is_empty() {
+        return Ok((0, base));
+    }
+    Ok((immediate(&offset, symbols)?, base))
+}
+
+fn csr_operand(token: &Token, symbols: &HashMap<String, i64>) -> Result<u12, AssemblerError> {
+    if let Some((_, number)) = CSR_NAMES.iter().find(|(name, _)| *name == token.text) {
+        return Ok(u12::new(*number));
+    }
+    if !token.text.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(token.error(AssemblerErrorKind::UnknownCSR(token.text.to_string())));
+    }
+    let (value, _) = evaluate(token, symbols)?;
+    if !(0..=0xfff).contains(&value) {
+        return Err(token.error(AssemblerErrorKind::OutOfRange(value)));
+    }
+    Ok(u12::new(value as u16))
+}
+
+// A quoted string, with the escapes \n, \t, \r, \0, \\, \" and \'
+fn string(token: &Token) -> Result<Vec<u8>, AssemblerError> {
+    let invalid = || token.error(AssemblerErrorKind::InvalidOperand(token.text.to_string()));
+    let inner = token
+        .text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => bytes.push(match chars.next() {
+                Some('n') => b'\n',
+                Some('t') => b'\t',
+                Some('r') => b'\r',
+                Some('0') => 0,
+                Some('\\') => b'\\',
+                Some('"') => b'"',
+                Some('\'') => b'\'',
+                _ => return Err(invalid()),
+            }),
+            '"' => return Err(invalid()),
+            _ => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok(bytes)
+}
+
+// The contents of a section, which are filled in once every symbol is known
+enum Item<'a> {
+    Instruction(Statement<'a>),
+    Data {
+        offset: u32,
+        width: u32,
+        values: Vec<Token<'a>>,
+    },
+    Bytes {
+        offset: u32,
+        bytes: Vec<u8>,
+    },
+}
+
+struct PendingSection<'a> {
+    name: String,
+    size: u32,
+    alignment: u32,
+    items: Vec<Item<'a>>,
+    // the token which last extended the section, or which opened it, where an
+    // error is reported if the section cannot be placed
+    token: Token<'a>,
+}
+
+impl<'a> PendingSection<'a> {
+    // Extend the section, returning the offset of the new bytes
+    fn reserve(&mut self, length: u64, token: &Token<'a>) -> Result<u32, AssemblerError> {
+        let offset = self.size;
+        self.size = u32::try_from(u64::from(offset) + length)
+            .map_err(|_| token.error(AssemblerErrorKind::OutOfRange(length as i64)))?;
+        self.token = *token;
+        Ok(offset)
+    }
+}
+
+// The index of the named section, which is added if it does not yet exist
+fn switch_section<'a>(
+    sections: &mut Vec<PendingSection<'a>>,
+    name: &str,
+    token: Token<'a>,
+) -> usize {
+    if let Some(index) = sections.iter().position(|section| section.name == name) {
+        return index;
+    }
+    sections.push(PendingSection {
+        name: name.to_string(),
+        size: 0,
+        alignment: SECTION_ALIGNMENT,
+        items: Vec::new(),
+        token,
+    });
+    sections.len() - 1
+}
+
+// Assemble GNU style assembly into a program. Each line holds any number of
+// labels followed by an optional instruction or directive, with registers given
+// by their number (x10) or ABI name (a0). The sections are placed one after
+// another from the base address, in the order they first appear
+pub fn assemble(source: &str, base: u32) -> Result<Program, AssemblerError> {
+    let mut sections = Vec::new();
+    let start = Token {
+        text: "",
+        line: 1,
+        column: 1,
+    };
+    let mut current = switch_section(&mut sections, ".text", start);
+    let mut labels: HashMap<String, (usize, u32)> = HashMap::new();
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut globals = Vec::new();
+
+    for (i, text) in source.lines().enumerate() {
+        let line = parse_line(text, i + 1);
+        for label in line.labels {
+            if labels.contains_key(label.text) || constants.contains_key(label.text) {
+                return Err(
+                    label.error(AssemblerErrorKind::DuplicateSymbol(label.text.to_string()))
+                );
+            }
+            labels.insert(label.text.to_string(), (current, sections[current].size));
+        }
+        let Some((mnemonic, operands)) = line.statement else {
+            continue;
+        };
+        let directive = mnemonic.text.to_ascii_lowercase();
+        let is_bss = sections[current].name == ".bss";
+        if !directive.starts_with('.') {
+            if is_bss {
+                return Err(mnemonic.error(AssemblerErrorKind::DataInBss));
+            }
+            let mut statement = Statement {
+                address: 0,
+                size: 0,
+                mnemonic,
+                operands,
+            };
+            statement.size = statement.size(&constants);
+            statement.address = sections[current].reserve(statement.size.into(), &mnemonic)?;
+            sections[current].items.push(Item::Instruction(statement));
+            continue;
+        }
+
+        let section = &mut sections[current];
+        match directive.as_str() {
+            ".text" | ".data" | ".bss" => {
+                expect_operands(&mnemonic, &operands, &[0])?;
+                current = switch_section(&mut sections, &directive, mnemonic);
+            }
+            // any flags and type following the name are ignored
+            ".section" => {
+                if operands.is_empty() {
+                    expect_operands(&mnemonic, &operands, &[1])?;
+                }
+                current = switch_section(&mut sections, operands[0].text, operands[0]);
+            }
+            ".word" | ".half" | ".byte" => {
+                if is_bss {
+                    return Err(mnemonic.error(AssemblerErrorKind::DataInBss));
+                }
+                if operands.is_empty() {
+                    expect_operands(&mnemonic, &operands, &[1])?;
+                }
+                let width = match directive.as_str() {
+                    ".word" => 4,
+                    ".half" => 2,
+                    _ => 1,
+                };
+                let offset =
+                    section.reserve(u64::from(width) * operands.len() as u64, &mnemonic)?;
+                section.items.push(Item::Data {
+                    offset,
+                    width,
+                    values: operands,
+                });
+            }
+            ".string" | ".asciz" => {
+                if is_bss {
+                    return Err(mnemonic.error(AssemblerErrorKind::DataInBss));
+                }
+                for operand in operands.iter() {
+                    let mut bytes = string(operand)?;
+                    bytes.push(0);
+                    let offset = section.reserve(bytes.len() as u64, operand)?;
+                    section.items.push(Item::Bytes { offset, bytes });
+                }
+            }
+            // .align n aligns to 2^n bytes, padding .text with nops
+            ".align" => {
+                expect_operands(&mnemonic, &operands, &[1])?;
+                let (power, _) = evaluate(&operands[0], &constants)?;
+                if !(0..32).contains(&power) {
+                    return Err(operands[0].error(AssemblerErrorKind::OutOfRange(power)));
+                }
+                let alignment = 1u64 << power;
+                let padding =
+                    u64::from(section.size).next_multiple_of(alignment) - u64::from(section.size);
+                let offset = section.reserve(padding, &operands[0])?;
+                if section.name == ".text" && offset.is_multiple_of(4) {
+                    let nop = u32::from(CPUInstruction::ADDI {
+                        rd: u5::new(0),
+                        rs1: u5::new(0),
+                        imm: 0,
+                    });
+                    let bytes = (0..padding / 4).flat_map(|_| nop.to_le_bytes()).collect();
+                    section.items.push(Item::Bytes { offset, bytes });
+                }
+                section.alignment = section.alignment.max(alignment as u32);
+            }
+            ".space" | ".zero" => {
+                expect_operands(&mnemonic, &operands, &[1])?;
+                let (length, _) = evaluate(&operands[0], &constants)?;
+                let length = u64::try_from(length)
+                    .map_err(|_| operands[0].error(AssemblerErrorKind::OutOfRange(length)))?;
+                section.reserve(length, &operands[0])?;
+            }
+            // the value may only use constants defined on earlier lines
+            ".equ" => {
+                expect_operands(&mnemonic, &operands, &[2])?;
+                let name = &operands[0];
+                if name.text.is_empty()
+                    || name.text.starts_with(|c: char| c.is_ascii_digit())
+                    || !name.text.chars().all(is_symbol_char)
+                {
+                    return Err(
+                        name.error(AssemblerErrorKind::InvalidOperand(name.text.to_string()))
+                    );
+                }
+                if labels.contains_key(name.text) || constants.contains_key(name.text) {
+                    return Err(
+                        name.error(AssemblerErrorKind::DuplicateSymbol(name.text.to_string()))
+                    );
+                }
+                let (value, _) = evaluate(&operands[1], &constants)?;
+                constants.insert(name.text.to_string(), value);
+            }
+            ".globl" | ".global" => {
+                if operands.is_empty() {
+                    expect_operands(&mnemonic, &operands, &[1])?;
+                }
+                globals.extend(operands);
+            }
+            _ => {
+                return Err(mnemonic.error(AssemblerErrorKind::UnknownDirective(
+                    mnemonic.text.to_string(),
+                )))
+            }
+        }
+    }
+
+    // a section may end at the top of the address space, but not wrap past it
+    let mut address = u64::from(base);
+    let mut bases = Vec::new();
+    for section in sections.iter() {
+        let start = address.next_multiple_of(section.alignment.into());
+        address = start + u64::from(section.size);
+        let start = u32::try_from(start)
+            .ok()
+            .filter(|_| address <= 1 << 32)
+            .ok_or(
+                section
+                    .token
+                    .error(AssemblerErrorKind::OutOfRange(address as i64)),
+            )?;
+        bases.push(start);
+    }
+    let mut symbols = constants;
+    for (name, (section, offset)) in labels.iter() {
+        symbols.insert(
+            name.clone(),
+            i64::from(bases[*section]) + i64::from(*offset),
+        );
+    }
+    for global in globals {
+        if !symbols.contains_key(global.text) {
+            return Err(global.error(AssemblerErrorKind::UndefinedSymbol(global.text.to_string())));
+        }
+    }
+
+    let mut program = Program::default();
+    for (section, base) in sections.into_iter().zip(bases.iter()) {
+        let mut data = vec![0; section.size as usize];
+        let mut write = |offset: u32, bytes: &[u8]| {
+            data[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes)
+        };
+        for item in section.items {
+            match item {
+                Item::Instruction(mut statement) => {
+                    let offset = statement.address;
+                    statement.address = base.wrapping_add(offset);
+                    let bytes: Vec<u8> = statement
+                        .encode(&symbols)?
+                        .into_iter()
+                        .flat_map(|instruction| u32::from(instruction).to_le_bytes())
+                        .collect();
+                    write(offset, &bytes);
+                }
+                Item::Data {
+                    offset,
+                    width,
+                    values,
+                } => {
+                    let bits = 8 * width;
+                    for (i, token) in values.iter().enumerate() {
+                        let (value, _) = evaluate(token, &symbols)?;
+                        if !(-(1i64 << (bits - 1))..(1i64 << bits)).contains(&value) {
+                            return Err(token.error(AssemblerErrorKind::OutOfRange(value)));
+                        }
+                        let bytes = &value.to_le_bytes()[..width as usize];
+                        write(offset + width * i as u32, bytes);
+                    }
+                }
+                Item::Bytes { offset, bytes } => write(offset, &bytes),
+            }
+        }
+        if !data.is_empty() {
+            program.sections.push(Section {
+                name: section.name,
+                address: *base,
+                data,
+            });
+        }
+    }
+
+    for name in labels.into_keys() {
+        let address = symbols[&name] as u64;
+        program.symbols.insert(Symbol {
+            name,
+            address,
+            size: 0,
+            kind: SymbolKind::NoType,
+        });
+    }
+    program.entry = match program.symbols.address_of("_start") {
+        Some(start) => start as u32,
+        None => bases[0],
+    };
+    Ok(program)
+}
@@ -0,0 +1,271 @@
+use std::any::Any;
+use std::fmt::Debug;
+
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Width {
+    Byte = 1,
+    Half = 2,
+    Word = 4,
+    Double = 8,
+}
+
+impl Width {
+    pub fn bytes(self) -> u32 {
+        self as u32
+    }
+
+    pub fn mask(self) -> u64 {
+        u64::MAX >> (64 - 8 * self.bytes())
+    }
+}
+
+// A memory mapped device, accessed with the offset from the base of its region.
+// Multi-byte values are little endian
+pub trait Device: Any + Debug {
+    #[allow(clippy::result_unit_err)]
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()>;
+    #[allow(clippy::result_unit_err)]
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()>;
+
+    // Called once per machine tick, with access to main memory for DMA
+    fn tick(&mut self, _memory: &mut Memory) {}
+
+    // The level of the interrupt output of the device
+    fn interrupt(&self) -> bool {
+        false
+    }
+
+    // Set once the guest has requested the machine to exit
+    fn exit_code(&self) -> Option<u32> {
+        None
+    }
+
+    // The state saved in a snapshot of the machine. Devices without state, or
+    // whose state is held outside of the machine, save nothing
+    fn save_state(&self, _encoder: &mut Encoder) {}
+
+    fn restore_state(&mut self, _decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Region {
+    base: u32,
+    size: u32,
+    source: Option<usize>,
+    device: Box<dyn Device>,
+}
+
+impl Region {
+    fn contains(&self, address: u32, width: Width) -> bool {
+        address >= self.base
+            && u64::from(address) + u64::from(width.bytes())
+                <= u64::from(self.base) + u64::from(self.size)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Bus {
+    regions: Vec<Region>,
+}
+
+impl Bus {
+    #[allow(clippy::result_unit_err)]
+    pub fn attach(&mut self, base: u32, size: u32, device: impl Device) -> Result<(), ()> {
+        let end = u64::from(base) + u64::from(size);
+        if size == 0 || end > 1 << 32 {
+            return Err(());
+        }
+        let overlaps = self.regions.iter().any(|region| {
+            u64::from(base) < u64::from(region.base) + u64::from(region.size)
+                && u64::from(region.base) < end
+        });
+        if overlaps {
+            return Err(());
+        }
+        self.regions.push(Region {
+            base,
+            size,
+            source: None,
+            device: Box::new(device),
+        });
+        Ok(())
+    }
+
+    // Route the interrupt output of the device at the address to an interrupt
+    // source of the platform interrupt controller
+    #[allow(clippy::result_unit_err)]
+    pub fn connect_interrupt(&mut self, address: u32, source: usize) -> Result<(), ()> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| region.contains(address, Width::Byte))
+            .ok_or(())?;
+        region.source = Some(source);
+        Ok(())
+    }
+
+    pub fn tick(&mut self, memory: &mut Memory) {
+        for region in self.regions.iter_mut() {
+            region.device.tick(memory);
+        }
+    }
+
+    // The level of each connected interrupt source
+    pub fn interrupts(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.regions.iter().filter_map(|region| {
+            region
+                .source
+                .map(|source| (source, region.device.interrupt()))
+        })
+    }
+
+    pub fn detach(&mut self, address: u32) -> Option<Box<dyn Device>> {
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.contains(address, Width::Byte))?;
+        Some(self.regions.remove(index).device)
+    }
+
+    pub fn is_mapped(&self, address: u32) -> bool {
+        self.regions
+            .iter()
+            .any(|region| region.contains(address, Width::Byte))
+    }
+
+    pub fn device<T: Device>(&self, address: u32) -> Option<&T> {
+        let region = self
+            .regions
+            .iter()
+            .find(|region| region.contains(address, Width::Byte))?;
+        (region.device.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub fn device_mut<T: Device>(&mut self, address: u32) -> Option<&mut T> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| region.contains(address, Width::Byte))?;
+        (region.device.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    pub fn exit_code(&self) -> Option<u32> {
+        self.regions
+            .iter()
+            .find_map(|region| region.device.exit_code())
+    }
+
+    // Returns None if no device is mapped at the address, accesses which
+    // straddle the end of a region fail
+    pub fn read(&mut self, address: u32, width: Width) -> Option<Result<u64, ()>> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| region.contains(address, Width::Byte))?;
+        if !region.contains(address, width) {
+            return Some(Err(()));
+        }
+        Some(region.device.read(address - region.base, width))
+    }
+
+    pub fn write(&mut self, address: u32, width: Width, value: u64) -> Option<Result<(), ()>> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| region.contains(address, Width::Byte))?;
+        if !region.contains(address, width) {
+            return Some(Err(()));
+        }
+        Some(
+            region
+                .device
+                .write(address - region.base, width, value & width.mask()),
+        )
+    }
+}
+
+pub(crate) fn read_bytes(bytes: &[u8], offset: u32, width: Width) -> Result<u64, ()> {
+    let start = offset as usize;
+    let slice = bytes.get(start..start + width.bytes() as usize).ok_or(())?;
+    Ok(slice
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | u64::from(*byte)))
+}
+
+pub(crate) fn write_bytes(
+    bytes: &mut [u8],
+    offset: u32,
+    width: Width,
+    value: u64,
+) -> Result<(), ()> {
+    let start = offset as usize;
+    let slice = bytes
+        .get_mut(start..start + width.bytes() as usize)
+        .ok_or(())?;
+    for (i, byte) in slice.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+    Ok(())
+}
+
+// The devices themselves are not saved, so a snapshot can only be restored onto
+// a bus with the same devices attached
+impl Snapshot for Bus {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.length(self.regions.len());
+        for region in self.regions.iter() {
+            let mut state = Encoder::new();
+            region.device.save_state(&mut state);
+            encoder.u32(region.base);
+            encoder.bytes(&state.into_bytes());
+        }
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for _ in 0..decoder.length()? {
+            let base = decoder.u32()?;
+            let mut state = Decoder::new(decoder.bytes()?);
+            let region = self
+                .regions
+                .iter_mut()
+                .find(|region| region.base == base)
+                .ok_or(SnapshotError::MissingDevice(base))?;
+            region.device.restore_state(&mut state)?;
+            // the state was saved by a different kind of device
+            if !state.is_empty() {
+                return Err(SnapshotError::Invalid);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read only memory, writes raise an access fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ROM(Vec<u8>);
+
+impl ROM {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        ROM(bytes)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Device for ROM {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        read_bytes(&self.0, offset, width)
+    }
+
+    fn write(&mut self, _offset: u32, _width: Width, _value: u64) -> Result<(), ()> {
+        Err(())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::pmp::{PMP, PMP_ENTRIES};
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+use crate::trap::Interrupt;
+use ux::u12;
+
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MTVT: u16 = 0x307;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MNXTI: u16 = 0x345;
+pub const MINTTHRESH: u16 = 0x347;
+pub const PMPCFG0: u16 = 0x3a0;
+pub const PMPADDR0: u16 = 0x3b0;
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+pub const MINTSTATUS: u16 = 0xfb1;
+
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+
+pub const MTVEC_VECTORED: u32 = 0b01;
+pub const MTVEC_CLIC: u32 = 0b11;
+
+// RV32 with the I base and user mode
+const MISA_VALUE: u32 = (1 << 30) | (1 << 8) | (1 << 20);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord, Default)]
+pub enum PrivilegeMode {
+    User = 0,
+    #[default]
+    Machine = 3,
+}
+
+impl PrivilegeMode {
+    fn from_mpp(mstatus: u32) -> Self {
+        if mstatus & MSTATUS_MPP == MSTATUS_MPP {
+            PrivilegeMode::Machine
+        } else {
+            PrivilegeMode::User
+        }
+    }
+}
+
+// Bits 9:8 of the address encode the lowest privilege that may access the csr
+pub fn is_accessible(address: u12, privilege: PrivilegeMode) -> bool {
+    (u16::from(address) >> 8) & 0b11 <= privilege as u16
+}
+
+// Bits 11:10 of the address are set for read only csrs
+pub fn is_read_only(address: u12) -> bool {
+    (u16::from(address) >> 10) & 0b11 == 0b11
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CSRFile {
+    pub mstatus: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mie: u32,
+    pub mip: u32,
+    pub pmp: PMP,
+}
+
+impl CSRFile {
+    #[allow(clippy::result_unit_err)]
+    pub fn get(&self, address: u12) -> Result<u32, ()> {
+        let address = u16::from(address);
+        match address {
+            MSTATUS => Ok(self.mstatus),
+            MISA => Ok(MISA_VALUE),
+            MTVEC => Ok(self.mtvec),
+            MSCRATCH => Ok(self.mscratch),
+            MEPC => Ok(self.mepc),
+            MCAUSE => Ok(self.mcause),
+            MTVAL => Ok(self.mtval),
+            MIE => Ok(self.mie),
+            MIP => Ok(self.mip),
+            MVENDORID | MARCHID | MIMPID | MHARTID => Ok(0),
+            _ if (PMPCFG0..PMPCFG0 + (PMP_ENTRIES / 4) as u16).contains(&address) => {
+                Ok(self.pmp.config_register((address - PMPCFG0) as usize))
+            }
+            _ if (PMPADDR0..PMPADDR0 + PMP_ENTRIES as u16).contains(&address) => {
+                Ok(self.pmp.address((address - PMPADDR0) as usize))
+            }
+            _ => Err(()),
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn set(&mut self, address: u12, value: u32) -> Result<(), ()> {
+        let address = u16::from(address);
+        match address {
+            MSTATUS => {
+                let mut writable = MSTATUS_MIE | MSTATUS_MPIE;
+                // MPP is WARL, only user and machine mode are legal
+                if value & MSTATUS_MPP == 0 || value & MSTATUS_MPP == MSTATUS_MPP {
+                    writable |= MSTATUS_MPP;
+                }
+                self.mstatus = (self.mstatus & !writable) | (value & writable);
+            }
+            // misa is WARL, writes are ignored as extensions cannot be disabled
+            MISA => {}
+            // only direct and vectored modes are legal
+            MTVEC => self.mtvec = value & !0b10,
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b11,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIE => {
+                let writable = Interrupt::ALL.iter().fold(0, |acc, i| acc | i.mask());
+                self.mie = value & writable;
+            }
+            // the machine level pending bits are driven by the interrupt lines
+            MIP => {}
+            _ if (PMPCFG0..PMPCFG0 + (PMP_ENTRIES / 4) as u16).contains(&address) => self
+                .pmp
+                .set_config_register((address - PMPCFG0) as usize, value),
+            _ if (PMPADDR0..PMPADDR0 + PMP_ENTRIES as u16).contains(&address) => {
+                self.pmp.set_address((address - PMPADDR0) as usize, value)
+            }
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    pub fn trap_vector(&self, cause: u32) -> u32 {
+        if self.mtvec & 0b11 == MTVEC_CLIC {
+            return self.mtvec & !0b111111;
+        }
+        let base = self.mtvec & !0b11;
+        let is_interrupt = cause & (1 << 31) != 0;
+        if is_interrupt && self.mtvec & 0b11 == MTVEC_VECTORED {
+            return base + 4 * (cause & !(1 << 31));
+        }
+        base
+    }
+
+    // The highest priority interrupt which is both pending and enabled
+    pub fn pending_interrupt(&self, privilege: PrivilegeMode) -> Option<Interrupt> {
+        let globally_enabled =
+            privilege < PrivilegeMode::Machine || self.mstatus & MSTATUS_MIE != 0;
+        if !globally_enabled {
+            return None;
+        }
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| self.mip & self.mie & interrupt.mask() != 0)
+    }
+
+    pub fn previous_privilege(&self) -> PrivilegeMode {
+        PrivilegeMode::from_mpp(self.mstatus)
+    }
+
+    pub fn enter_trap(&mut self, privilege: PrivilegeMode, pc: u32, cause: u32, tval: u32) {
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = tval;
+
+        let mut mstatus = self.mstatus & !(MSTATUS_MPIE | MSTATUS_MPP | MSTATUS_MIE);
+        if self.mstatus & MSTATUS_MIE != 0 {
+            mstatus |= MSTATUS_MPIE;
+        }
+        if privilege == PrivilegeMode::Machine {
+            mstatus |= MSTATUS_MPP;
+        }
+        self.mstatus = mstatus;
+    }
+
+    pub fn return_from_trap(&mut self) -> PrivilegeMode {
+        let privilege = self.previous_privilege();
+        let mut mstatus = self.mstatus & !(MSTATUS_MPP | MSTATUS_MIE);
+        if self.mstatus & MSTATUS_MPIE != 0 {
+            mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus = mstatus | MSTATUS_MPIE;
+        privilege
+    }
+}
+
+impl Snapshot for CSRFile {
+    fn save(&self, encoder: &mut Encoder) {
+        for value in [
+            self.mstatus,
+            self.mtvec,
+            self.mscratch,
+            self.mepc,
+            self.mcause,
+            self.mtval,
+            self.mie,
+            self.mip,
+        ] {
+            encoder.u32(value);
+        }
+        self.pmp.save(encoder);
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for value in [
+            &mut self.mstatus,
+            &mut self.mtvec,
+            &mut self.mscratch,
+            &mut self.mepc,
+            &mut self.mcause,
+            &mut self.mtval,
+            &mut self.mie,
+            &mut self.mip,
+        ] {
+            *value = decoder.u32()?;
+        }
+        self.pmp.restore(decoder)
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::bus::{Device, Width};
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const BLOCK_BASE: u32 = 0x1000_1000;
+pub const BLOCK_SIZE: u32 = 0x100;
+pub const SECTOR_SIZE: usize = 512;
+
+const SECTOR_OFFSET: u32 = 0x00;
+const BUFFER_OFFSET: u32 = 0x04;
+const COUNT_OFFSET: u32 = 0x08;
+const COMMAND_OFFSET: u32 = 0x0c;
+const STATUS_OFFSET: u32 = 0x10;
+const CAPACITY_OFFSET: u32 = 0x14;
+const INTERRUPT_ENABLE_OFFSET: u32 = 0x18;
+
+pub const BLOCK_COMMAND_READ: u32 = 1;
+pub const BLOCK_COMMAND_WRITE: u32 = 2;
+pub const BLOCK_COMMAND_FLUSH: u32 = 3;
+
+pub const BLOCK_STATUS_BUSY: u32 = 1 << 0;
+pub const BLOCK_STATUS_DONE: u32 = 1 << 1;
+pub const BLOCK_STATUS_ERROR: u32 = 1 << 2;
+
+#[derive(Debug)]
+enum Image {
+    Buffer(Vec<u8>),
+    File(File),
+}
+
+// A block device transferring whole sectors between its image and main memory.
+// The guest sets the sector, buffer address and count then writes a command,
+// which is carried out by DMA on the next tick. Completion sets DONE or ERROR
+// in the status register, raising an interrupt if enabled until it is
+// acknowledged by writing to the status register
+#[derive(Debug)]
+pub struct BlockDevice {
+    image: Image,
+    sectors: u32,
+    sector: u32,
+    buffer: u32,
+    count: u32,
+    command: Option<u32>,
+    status: u32,
+    interrupt_enable: bool,
+}
+
+impl BlockDevice {
+    // The image is truncated to a whole number of sectors
+    pub fn new(mut bytes: Vec<u8>) -> Self {
+        bytes.truncate(bytes.len() - bytes.len() % SECTOR_SIZE);
+        let sectors = (bytes.len() / SECTOR_SIZE) as u32;
+        BlockDevice::with_image(Image::Buffer(bytes), sectors)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let sectors = (file.metadata()?.len() / SECTOR_SIZE as u64) as u32;
+        Ok(BlockDevice::with_image(Image::File(file), sectors))
+    }
+
+    fn with_image(image: Image, sectors: u32) -> Self {
+        BlockDevice {
+            image,
+            sectors,
+            sector: 0,
+            buffer: 0,
+            count: 0,
+            command: None,
+            status: 0,
+            interrupt_enable: false,
+        }
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    // The contents of an in-memory image
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        match &self.image {
+            Image::Buffer(bytes) => Some(bytes),
+            Image::File(_) => None,
+        }
+    }
+
+    fn read_sectors(&mut self, sector: u32, buffer: &mut [u8]) -> io::Result<()> {
+        let start = sector as usize * SECTOR_SIZE;
+        match &mut self.image {
+            Image::Buffer(bytes) => buffer.copy_from_slice(&bytes[start..start + buffer.len()]),
+            Image::File(file) => {
+                file.seek(SeekFrom::Start(start as u64))?;
+                file.read_exact(buffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, sector: u32, buffer: &[u8]) -> io::Result<()> {
+        let start = sector as usize * SECTOR_SIZE;
+        match &mut self.image {
+            Image::Buffer(bytes) => bytes[start..start + buffer.len()].copy_from_slice(buffer),
+            Image::File(file) => {
+                file.seek(SeekFrom::Start(start as u64))?;
+                file.write_all(buffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.image {
+            Image::Buffer(_) => Ok(()),
+            Image::File(file) => file.sync_data(),
+        }
+    }
+
+    fn execute(&mut self, command: u32, memory: &mut Memory) -> Result<(), ()> {
+        if command == BLOCK_COMMAND_FLUSH {
+            return self.flush().map_err(|_| ());
+        }
+        let end = u64::from(self.sector) + u64::from(self.count);
+        if end > u64::from(self.sectors) {
+            return Err(());
+        }
+        let length = self.count as usize * SECTOR_SIZE;
+        match command {
+            BLOCK_COMMAND_READ => {
+                let mut buffer = vec![0; length];
+                self.read_sectors(self.sector, &mut buffer)
+                    .map_err(|_| ())?;
+                memory.load_slice(self.buffer, &buffer)
+            }
+            BLOCK_COMMAND_WRITE => {
+                let buffer = memory.read_range(self.buffer, length)?;
+                self.write_sectors(self.sector, &buffer).map_err(|_| ())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+impl Device for BlockDevice {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        if width != Width::Word {
+            return Err(());
+        }
+        let value = match offset {
+            SECTOR_OFFSET => self.sector,
+            BUFFER_OFFSET => self.buffer,
+            COUNT_OFFSET => self.count,
+            COMMAND_OFFSET => self.command.unwrap_or(0),
+            STATUS_OFFSET => self.status,
+            CAPACITY_OFFSET => self.sectors,
+            INTERRUPT_ENABLE_OFFSET => u32::from(self.interrupt_enable),
+            _ => return Err(()),
+        };
+        Ok(value.into())
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        if width != Width::Word {
+            return Err(());
+        }
+        let value = value as u32;
+        match offset {
+            SECTOR_OFFSET => self.sector = value,
+            BUFFER_OFFSET => self.buffer = value,
+            COUNT_OFFSET => self.count = value,
+            // commands issued while busy are ignored
+            COMMAND_OFFSET if self.status & BLOCK_STATUS_BUSY == 0 => {
+                self.command = Some(value);
+                self.status = BLOCK_STATUS_BUSY;
+            }
+            COMMAND_OFFSET => {}
+            STATUS_OFFSET => self.status &= !(BLOCK_STATUS_DONE | BLOCK_STATUS_ERROR),
+            CAPACITY_OFFSET => {}
+            INTERRUPT_ENABLE_OFFSET => self.interrupt_enable = value & 1 != 0,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, memory: &mut Memory) {
+        let Some(command) = self.command.take() else {
+            return;
+        };
+        self.status = match self.execute(command, memory) {
+            Ok(()) => BLOCK_STATUS_DONE,
+            Err(()) => BLOCK_STATUS_ERROR,
+        };
+    }
+
+    fn interrupt(&self) -> bool {
+        self.interrupt_enable && self.status & (BLOCK_STATUS_DONE | BLOCK_STATUS_ERROR) != 0
+    }
+
+    // The contents of an image held in memory are saved, while a file backed
+    // image is left as it is on disk
+    fn save_state(&self, encoder: &mut Encoder) {
+        for value in [self.sector, self.buffer, self.count, self.status] {
+            encoder.u32(value);
+        }
+        encoder.bool(self.command.is_some());
+        encoder.u32(self.command.unwrap_or_default());
+        encoder.bool(self.interrupt_enable);
+        match &self.image {
+            Image::Buffer(bytes) => {
+                encoder.bool(true);
+                encoder.bytes(bytes);
+            }
+            Image::File(_) => encoder.bool(false),
+        }
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for value in [
+            &mut self.sector,
+            &mut self.buffer,
+            &mut self.count,
+            &mut self.status,
+        ] {
+            *value = decoder.u32()?;
+        }
+        let (pending, command) = (decoder.bool()?, decoder.u32()?);
+        self.command = pending.then_some(command);
+        self.interrupt_enable = decoder.bool()?;
+        match (&mut self.image, decoder.bool()?) {
+            (Image::Buffer(image), true) => {
+                let bytes = decoder.bytes()?;
+                if bytes.len() != image.len() {
+                    return Err(SnapshotError::Invalid);
+                }
+                image.copy_from_slice(bytes);
+            }
+            (Image::File(_), false) => {}
+            _ => return Err(SnapshotError::Invalid),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const CLIC_BASE: u32 = 0x0280_0000;
+pub const CLIC_SIZE: u32 = 0x0001_0000;
+
+// Number of implemented bits in clicintctl
+pub const CLIC_INTCTLBITS: u32 = 8;
+
+const CLICCFG_OFFSET: u32 = 0x0000;
+const CLICINFO_OFFSET: u32 = 0x0004;
+const CLICINT_OFFSET: u32 = 0x1000;
+
+pub const CLICINTATTR_SHV: u8 = 1 << 0;
+pub const CLICINTATTR_EDGE: u8 = 1 << 1;
+pub const CLICINTATTR_NEGATIVE: u8 = 1 << 2;
+// only machine mode interrupts are supported
+const CLICINTATTR_MODE: u8 = 0b11 << 6;
+
+// Interrupts 0 to 15 are the local interrupts (software, timer and external),
+// with platform interrupts numbered from 16
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CLIC {
+    pub cliccfg: u8,
+    ip: Vec<bool>,
+    ie: Vec<bool>,
+    attr: Vec<u8>,
+    ctl: Vec<u8>,
+    input: Vec<bool>,
+    pub mtvt: u32,
+    pub mintthresh: u8,
+    // current interrupt level, reported in mintstatus
+    pub mil: u8,
+}
+
+impl Default for CLIC {
+    fn default() -> Self {
+        CLIC::new(64)
+    }
+}
+
+impl CLIC {
+    pub fn new(interrupts: usize) -> Self {
+        let interrupts = interrupts.clamp(16, 4096);
+        CLIC {
+            cliccfg: 0,
+            ip: vec![false; interrupts],
+            ie: vec![false; interrupts],
+            attr: vec![CLICINTATTR_MODE; interrupts],
+            ctl: vec![0; interrupts],
+            input: vec![false; interrupts],
+            mtvt: 0,
+            mintthresh: 0,
+            mil: 0,
+        }
+    }
+
+    pub fn interrupts(&self) -> usize {
+        self.ip.len()
+    }
+
+    // Drive the input line of an interrupt, level triggered interrupts follow
+    // the line while edge triggered interrupts latch on the active edge
+    pub fn set_level(&mut self, id: usize, line: bool) {
+        if id >= self.interrupts() {
+            return;
+        }
+        let active = line != (self.attr[id] & CLICINTATTR_NEGATIVE != 0);
+        if self.attr[id] & CLICINTATTR_EDGE != 0 {
+            if active && !self.input[id] {
+                self.ip[id] = true;
+            }
+        } else {
+            self.ip[id] = active;
+        }
+        self.input[id] = active;
+    }
+
+    // Interrupts which do not exist read as zero, and writes to them are
+    // ignored
+    pub fn is_pending(&self, id: usize) -> bool {
+        self.ip.get(id).copied().unwrap_or(false)
+    }
+
+    pub fn set_pending(&mut self, id: usize, pending: bool) {
+        // the pending bit of a level triggered interrupt follows the line
+        if self.attributes(id) & CLICINTATTR_EDGE != 0 {
+            self.ip[id] = pending;
+        }
+    }
+
+    pub fn is_enabled(&self, id: usize) -> bool {
+        self.ie.get(id).copied().unwrap_or(false)
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) {
+        if id >= self.interrupts() {
+            return;
+        }
+        self.ie[id] = enabled;
+    }
+
+    pub fn attributes(&self, id: usize) -> u8 {
+        self.attr.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn set_attributes(&mut self, id: usize, attr: u8) {
+        if id >= self.interrupts() {
+            return;
+        }
+        self.attr[id] =
+            (attr & (CLICINTATTR_SHV | CLICINTATTR_EDGE | CLICINTATTR_NEGATIVE)) | CLICINTATTR_MODE;
+    }
+
+    pub fn control(&self, id: usize) -> u8 {
+        self.ctl.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn set_control(&mut self, id: usize, ctl: u8) {
+        if id >= self.interrupts() {
+            return;
+        }
+        self.ctl[id] = ctl;
+    }
+
+    fn level_bits(&self) -> u32 {
+        u32::from(self.cliccfg & 0xf).min(CLIC_INTCTLBITS)
+    }
+
+    // The upper mnlbits of clicintctl select the level, with the remaining
+    // bits filled with ones
+    pub fn level(&self, id: usize) -> u8 {
+        let mask = (0xff00_u16 >> self.level_bits()) as u8;
+        (self.control(id) & mask) | !mask
+    }
+
+    pub fn is_vectored(&self, id: usize) -> bool {
+        self.attributes(id) & CLICINTATTR_SHV != 0
+    }
+
+    // The interrupt with the highest level, then priority, then id which is
+    // pending, enabled and above the threshold level
+    pub fn pending_interrupt(&self, threshold: u8) -> Option<usize> {
+        (0..self.interrupts())
+            .filter(|id| self.ip[*id] && self.ie[*id] && self.level(*id) > threshold)
+            .max_by_key(|id| (self.ctl[*id], *id))
+    }
+
+    // Edge triggered interrupts are cleared once they are serviced
+    pub fn acknowledge(&mut self, id: usize) {
+        if self.attributes(id) & CLICINTATTR_EDGE != 0 {
+            self.ip[id] = false;
+        }
+    }
+
+    // The mnxti read: claim the next non vectored interrupt above the previous
+    // level, returning its entry in the vector table or zero
+    pub fn next_interrupt(&mut self, previous_level: u8) -> Option<usize> {
+        let id = self.pending_interrupt(previous_level.max(self.mintthresh))?;
+        if self.is_vectored(id) {
+            return None;
+        }
+        self.mil = self.level(id);
+        self.acknowledge(id);
+        Some(id)
+    }
+
+    fn info(&self) -> u32 {
+        (CLIC_INTCTLBITS << 21) | self.interrupts() as u32
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read(&self, offset: u32) -> Result<u32, ()> {
+        if !offset.is_multiple_of(4) {
+            return Err(());
+        }
+        match offset {
+            CLICCFG_OFFSET => Ok(u32::from(self.cliccfg)),
+            CLICINFO_OFFSET => Ok(self.info()),
+            _ => {
+                let id = self.interrupt_id(offset)?;
+                Ok(u32::from(self.ip[id])
+                    | (u32::from(self.ie[id]) << 8)
+                    | (u32::from(self.attr[id]) << 16)
+                    | (u32::from(self.ctl[id]) << 24))
+            }
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write(&mut self, offset: u32, value: u32) -> Result<(), ()> {
+        if !offset.is_multiple_of(4) {
+            return Err(());
+        }
+        match offset {
+            // nmbits is fixed to zero as all interrupts are machine mode
+            CLICCFG_OFFSET => self.cliccfg = (value & 0xf) as u8,
+            CLICINFO_OFFSET => {}
+            _ => {
+                let id = self.interrupt_id(offset)?;
+                self.set_attributes(id, (value >> 16) as u8);
+                self.set_pending(id, value & 1 != 0);
+                self.set_enabled(id, (value >> 8) & 1 != 0);
+                self.set_control(id, (value >> 24) as u8);
+            }
+        }
+        Ok(())
+    }
+
+    fn interrupt_id(&self, offset: u32) -> Result<usize, ()> {
+        let id = (offset.checked_sub(CLICINT_OFFSET).ok_or(())? / 4) as usize;
+        if id >= self.interrupts() {
+            return Err(());
+        }
+        Ok(id)
+    }
+}
+
+impl Snapshot for CLIC {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.u8(self.cliccfg);
+        encoder.bools(&self.ip);
+        encoder.bools(&self.ie);
+        encoder.bytes(&self.attr);
+        encoder.bytes(&self.ctl);
+        encoder.bools(&self.input);
+        encoder.u32(self.mtvt);
+        encoder.u8(self.mintthresh);
+        encoder.u8(self.mil);
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let clic = CLIC {
+            cliccfg: decoder.u8()?,
+            ip: decoder.bools()?,
+            ie: decoder.bools()?,
+            attr: decoder.bytes()?.to_vec(),
+            ctl: decoder.bytes()?.to_vec(),
+            input: decoder.bools()?,
+            mtvt: decoder.u32()?,
+            mintthresh: decoder.u8()?,
+            mil: decoder.u8()?,
+        };
+        let interrupts = clic.ip.len();
+        let valid = (16..=4096).contains(&interrupts)
+            && [
+                clic.ie.len(),
+                clic.attr.len(),
+                clic.ctl.len(),
+                clic.input.len(),
+            ]
+            .iter()
+            .all(|length| *length == interrupts);
+        if !valid {
+            return Err(SnapshotError::Invalid);
+        }
+        *self = clic;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const CLINT_BASE: u32 = 0x0200_0000;
+pub const CLINT_SIZE: u32 = 0x1_0000;
+
+const MSIP_OFFSET: u32 = 0x0000;
+const MTIMECMP_OFFSET: u32 = 0x4000;
+const MTIME_OFFSET: u32 = 0xbff8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CLINT {
+    pub mtime: u64,
+    pub mtimecmp: Vec<u64>,
+    pub msip: Vec<bool>,
+    // number of simulated cycles for each increment of mtime
+    pub cycles_per_increment: u32,
+    cycles: u32,
+}
+
+impl Default for CLINT {
+    fn default() -> Self {
+        CLINT::new(1)
+    }
+}
+
+impl CLINT {
+    pub fn new(harts: usize) -> Self {
+        CLINT {
+            mtime: 0,
+            mtimecmp: vec![u64::MAX; harts],
+            msip: vec![false; harts],
+            cycles_per_increment: 1,
+            cycles: 0,
+        }
+    }
+
+    pub fn advance(&mut self, cycles: u32) {
+        let total = u64::from(self.cycles) + u64::from(cycles);
+        let divider = u64::from(self.cycles_per_increment.max(1));
+        self.mtime = self.mtime.wrapping_add(total / divider);
+        self.cycles = (total % divider) as u32;
+    }
+
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    pub fn software_pending(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    fn harts(&self) -> u32 {
+        self.msip.len() as u32
+    }
+
+    fn register(&self, offset: u32) -> Option<Register> {
+        if !offset.is_multiple_of(4) {
+            return None;
+        }
+        if (MTIME_OFFSET..MTIME_OFFSET + 8).contains(&offset) {
+            return Some(Register::Mtime(offset - MTIME_OFFSET));
+        }
+        if (MTIMECMP_OFFSET..MTIMECMP_OFFSET + 8 * self.harts()).contains(&offset) {
+            let relative = offset - MTIMECMP_OFFSET;
+            return Some(Register::Mtimecmp((relative / 8) as usize, relative % 8));
+        }
+        if (MSIP_OFFSET..MSIP_OFFSET + 4 * self.harts()).contains(&offset) {
+            return Some(Register::Msip(((offset - MSIP_OFFSET) / 4) as usize));
+        }
+        None
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read(&self, offset: u32) -> Result<u32, ()> {
+        match self.register(offset).ok_or(())? {
+            Register::Mtime(half_offset) => Ok(half(self.mtime, half_offset)),
+            Register::Mtimecmp(hart, half_offset) => Ok(half(self.mtimecmp[hart], half_offset)),
+            Register::Msip(hart) => Ok(u32::from(self.msip[hart])),
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write(&mut self, offset: u32, value: u32) -> Result<(), ()> {
+        match self.register(offset).ok_or(())? {
+            Register::Mtime(half_offset) => {
+                self.mtime = with_half(self.mtime, half_offset, value);
+            }
+            Register::Mtimecmp(hart, half_offset) => {
+                self.mtimecmp[hart] = with_half(self.mtimecmp[hart], half_offset, value);
+            }
+            // only the lowest bit of msip is writable
+            Register::Msip(hart) => self.msip[hart] = value & 1 != 0,
+        }
+        Ok(())
+    }
+}
+
+enum Register {
+    Mtime(u32),
+    Mtimecmp(usize, u32),
+    Msip(usize),
+}
+
+// 64 bit registers are accessed as two little endian words
+fn half(value: u64, offset: u32) -> u32 {
+    (value >> (8 * offset)) as u32
+}
+
+fn with_half(value: u64, offset: u32, half: u32) -> u64 {
+    let shift = 8 * offset;
+    (value & !(0xffffffff << shift)) | (u64::from(half) << shift)
+}
+
+impl Snapshot for CLINT {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.u64(self.mtime);
+        encoder.u64s(&self.mtimecmp);
+        encoder.bools(&self.msip);
+        encoder.u32(self.cycles_per_increment);
+        encoder.u32(self.cycles);
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let clint = CLINT {
+            mtime: decoder.u64()?,
+            mtimecmp: decoder.u64s()?,
+            msip: decoder.bools()?,
+            cycles_per_increment: decoder.u32()?,
+            cycles: decoder.u32()?,
+        };
+        if clint.mtimecmp.len() != clint.msip.len() {
+            return Err(SnapshotError::Invalid);
+        }
+        *self = clint;
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::bus::{read_bytes, write_bytes, Device, Width};
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const FRAMEBUFFER_BASE: u32 = 0x2000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    // 8 bit grayscale
+    Gray8,
+    // 16 bit little endian, red in the top 5 bits
+    RGB565,
+    // bytes in the order red, green, blue
+    RGB888,
+    // 32 bit little endian, blue in the lowest byte and the top byte unused
+    XRGB8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::RGB565 => 2,
+            PixelFormat::RGB888 => 3,
+            PixelFormat::XRGB8888 => 4,
+        }
+    }
+
+    fn to_rgb(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Gray8 => [pixel[0]; 3],
+            PixelFormat::RGB565 => {
+                let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let red = (value >> 11) & 0x1f;
+                let green = (value >> 5) & 0x3f;
+                let blue = value & 0x1f;
+                // replicate the high bits so that full intensity maps to 255
+                [
+                    ((red << 3) | (red >> 2)) as u8,
+                    ((green << 2) | (green >> 4)) as u8,
+                    ((blue << 3) | (blue >> 2)) as u8,
+                ]
+            }
+            PixelFormat::RGB888 => [pixel[0], pixel[1], pixel[2]],
+            PixelFormat::XRGB8888 => [pixel[2], pixel[1], pixel[0]],
+        }
+    }
+}
+
+// A linear framebuffer, with rows stored top to bottom without padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        let size = width as usize * height as usize * format.bytes_per_pixel();
+        Framebuffer {
+            width,
+            height,
+            format,
+            pixels: vec![0; size],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    // The size in bytes of the mapped region
+    pub fn size(&self) -> u32 {
+        self.pixels.len() as u32
+    }
+
+    // The raw contents in the pixel format of the framebuffer
+    pub fn as_slice(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // Pixels outside of the framebuffer are None
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 3]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let bytes = self.format.bytes_per_pixel();
+        let start = (y as usize * self.width as usize + x as usize) * bytes;
+        Some(self.format.to_rgb(&self.pixels[start..start + bytes]))
+    }
+
+    // The contents converted to 8 bit RGB
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .chunks(self.format.bytes_per_pixel())
+            .flat_map(|pixel| self.format.to_rgb(pixel))
+            .collect()
+    }
+
+    // A binary (P6) portable pixmap
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        ppm.extend(self.to_rgb());
+        ppm
+    }
+
+    // An 8 bit RGB PNG, using uncompressed deflate blocks
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut header = Vec::new();
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // bit depth 8, colour type RGB, default compression, filter and interlace
+        header.extend([8, 2, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+
+        let row = self.width as usize * 3;
+        let mut scanlines = Vec::new();
+        for line in self.to_rgb().chunks(row.max(1)) {
+            // each scanline starts with its filter type, none
+            scanlines.push(0);
+            scanlines.extend(line);
+        }
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    // Export to a file, as a PNG or PPM image according to the extension or
+    // otherwise the raw contents
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let bytes = match extension {
+            Some("png") => self.to_png(),
+            Some("ppm") => self.to_ppm(),
+            _ => self.pixels.clone(),
+        };
+        fs::write(path, bytes)
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        read_bytes(&self.pixels, offset, width)
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        write_bytes(&mut self.pixels, offset, width, value)
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.pixels);
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let pixels = decoder.bytes()?;
+        if pixels.len() != self.pixels.len() {
+            return Err(SnapshotError::Invalid);
+        }
+        self.pixels.copy_from_slice(pixels);
+        Ok(())
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// A zlib stream holding the data in stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(u8::from(last));
+        stream.extend(length.to_le_bytes());
+        stream.extend((!length).to_le_bytes());
+        stream.extend(block);
+    }
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+
+use crate::bus::{Device, Width};
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const GPIO_BASE: u32 = 0x1001_0000;
+pub const GPIO_SIZE: u32 = 0x100;
+pub const GPIO_PINS: usize = 32;
+
+const INPUT_OFFSET: u32 = 0x00;
+const OUTPUT_OFFSET: u32 = 0x04;
+const DIRECTION_OFFSET: u32 = 0x08;
+const INTERRUPT_ENABLE_OFFSET: u32 = 0x0c;
+const INTERRUPT_PENDING_OFFSET: u32 = 0x10;
+
+type OutputCallback = Box<dyn FnMut(usize, bool)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    time: u64,
+    pin: usize,
+    level: bool,
+}
+
+// A bank of 32 pins, each either an input driven by the host or an output
+// driven by the guest. A change in the level of an input pin sets its pending
+// bit, raising an interrupt if enabled until cleared by writing a one
+#[derive(Default)]
+pub struct GPIO {
+    input: u32,
+    output: u32,
+    // set for output pins
+    direction: u32,
+    interrupt_enable: u32,
+    interrupt_pending: u32,
+    time: u64,
+    events: Vec<Event>,
+    callbacks: Vec<OutputCallback>,
+}
+
+impl fmt::Debug for GPIO {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GPIO")
+            .field("input", &self.input)
+            .field("output", &self.output)
+            .field("direction", &self.direction)
+            .field("interrupt_enable", &self.interrupt_enable)
+            .field("interrupt_pending", &self.interrupt_pending)
+            .field("time", &self.time)
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GPIO {
+    // The callback is called with the pin and its new level whenever the
+    // level driven on an output pin changes
+    pub fn on_output_change(&mut self, callback: impl FnMut(usize, bool) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    // The number of ticks since the device was attached
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    // Pins which do not exist read as low, and driving them is ignored
+    pub fn set_input(&mut self, pin: usize, level: bool) {
+        if pin >= GPIO_PINS {
+            return;
+        }
+        let mask = 1 << pin;
+        let previous = self.levels();
+        self.input = (self.input & !mask) | (u32::from(level) << pin);
+        if (previous ^ self.levels()) & mask & !self.direction != 0 {
+            self.interrupt_pending |= mask;
+        }
+    }
+
+    // Drive an input pin once the device has been ticked until the given time
+    pub fn schedule_input(&mut self, time: u64, pin: usize, level: bool) {
+        if pin >= GPIO_PINS {
+            return;
+        }
+        self.events.push(Event { time, pin, level });
+    }
+
+    // The level of every pin, outputs read back the level they drive
+    pub fn levels(&self) -> u32 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+
+    pub fn level(&self, pin: usize) -> bool {
+        pin < GPIO_PINS && self.levels() & (1 << pin) != 0
+    }
+
+    fn driven(&self) -> u32 {
+        self.output & self.direction
+    }
+
+    fn update_outputs(&mut self, previous: u32) {
+        let changed = previous ^ self.driven();
+        for pin in (0..GPIO_PINS).filter(|pin| changed & (1 << pin) != 0) {
+            let level = self.driven() & (1 << pin) != 0;
+            for callback in self.callbacks.iter_mut() {
+                callback(pin, level);
+            }
+        }
+    }
+}
+
+impl Device for GPIO {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        if width != Width::Word {
+            return Err(());
+        }
+        let value = match offset {
+            INPUT_OFFSET => self.levels(),
+            OUTPUT_OFFSET => self.output,
+            DIRECTION_OFFSET => self.direction,
+            INTERRUPT_ENABLE_OFFSET => self.interrupt_enable,
+            INTERRUPT_PENDING_OFFSET => self.interrupt_pending,
+            _ => return Err(()),
+        };
+        Ok(value.into())
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        if width != Width::Word {
+            return Err(());
+        }
+        let value = value as u32;
+        let previous = self.driven();
+        match offset {
+            INPUT_OFFSET => {}
+            OUTPUT_OFFSET => self.output = value,
+            DIRECTION_OFFSET => self.direction = value,
+            INTERRUPT_ENABLE_OFFSET => self.interrupt_enable = value,
+            INTERRUPT_PENDING_OFFSET => self.interrupt_pending &= !value,
+            _ => return Err(()),
+        }
+        self.update_outputs(previous);
+        Ok(())
+    }
+
+    fn tick(&mut self, _memory: &mut Memory) {
+        self.time += 1;
+        let time = self.time;
+        let (mut due, pending): (Vec<Event>, Vec<Event>) =
+            self.events.drain(..).partition(|event| event.time <= time);
+        self.events = pending;
+        due.sort_by_key(|event| event.time);
+        for event in due {
+            self.set_input(event.pin, event.level);
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.interrupt_pending & self.interrupt_enable != 0
+    }
+
+    // The callbacks belong to the host and are kept
+    fn save_state(&self, encoder: &mut Encoder) {
+        for value in [
+            self.input,
+            self.output,
+            self.direction,
+            self.interrupt_enable,
+            self.interrupt_pending,
+        ] {
+            encoder.u32(value);
+        }
+        encoder.u64(self.time);
+        encoder.length(self.events.len());
+        for event in self.events.iter() {
+            encoder.u64(event.time);
+            encoder.u8(event.pin as u8);
+            encoder.bool(event.level);
+        }
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for value in [
+            &mut self.input,
+            &mut self.output,
+            &mut self.direction,
+            &mut self.interrupt_enable,
+            &mut self.interrupt_pending,
+        ] {
+            *value = decoder.u32()?;
+        }
+        self.time = decoder.u64()?;
+        self.events = (0..decoder.length()?)
+            .map(|_| {
+                let (time, pin, level) = (decoder.u64()?, decoder.u8()?, decoder.bool()?);
+                if usize::from(pin) >= GPIO_PINS {
+                    return Err(SnapshotError::Invalid);
+                }
+                Ok(Event {
+                    time,
+                    pin: pin.into(),
+                    level,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::bus::{Device, Width};
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const HTIF_SIZE: u32 = 0x10;
+
+const TOHOST_OFFSET: u32 = 0x0;
+const FROMHOST_OFFSET: u32 = 0x8;
+
+// The host target interface used by riscv-tests, mapped wherever the tohost
+// symbol is placed with fromhost directly after it. Writing tohost with the
+// lowest bit set exits with the code in the remaining bits, zero for a pass
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HTIF {
+    pub tohost: u64,
+    pub fromhost: u64,
+    exit_code: Option<u32>,
+}
+
+impl HTIF {
+    fn handle_command(&mut self) {
+        // only the exit command of the syscall device is supported
+        if self.tohost & 1 == 1 && self.tohost >> 48 == 0 {
+            self.exit_code = Some((self.tohost >> 1) as u32);
+        }
+    }
+}
+
+impl Device for HTIF {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        let (register, shift) = match offset {
+            TOHOST_OFFSET..FROMHOST_OFFSET => (self.tohost, offset - TOHOST_OFFSET),
+            FROMHOST_OFFSET..HTIF_SIZE => (self.fromhost, offset - FROMHOST_OFFSET),
+            _ => return Err(()),
+        };
+        Ok((register >> (8 * shift)) & width.mask())
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        let (register, shift) = match offset {
+            TOHOST_OFFSET..FROMHOST_OFFSET => (&mut self.tohost, offset - TOHOST_OFFSET),
+            FROMHOST_OFFSET..HTIF_SIZE => (&mut self.fromhost, offset - FROMHOST_OFFSET),
+            _ => return Err(()),
+        };
+        let mask = width.mask() << (8 * shift);
+        *register = (*register & !mask) | ((value << (8 * shift)) & mask);
+        // RV32 writes the low word of tohost last
+        if offset == TOHOST_OFFSET {
+            self.handle_command();
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        encoder.u64(self.tohost);
+        encoder.u64(self.fromhost);
+        encoder.optional_u32(self.exit_code);
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        self.tohost = decoder.u64()?;
+        self.fromhost = decoder.u64()?;
+        self.exit_code = decoder.optional_u32()?;
+        Ok(())
+    }
+}
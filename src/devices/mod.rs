@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub mod block;
+pub mod clic;
+pub mod clint;
+pub mod framebuffer;
+pub mod gpio;
+pub mod htif;
+pub mod plic;
+pub mod test_finisher;
+pub mod uart;
+pub mod virtio;
+
+// Where the bytes transmitted by a serial device are sent
+pub enum Output {
+    Buffer(Vec<u8>),
+    Stdout,
+    Writer(Box<dyn Write>),
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Buffer(bytes) => f.debug_tuple("Buffer").field(bytes).finish(),
+            Output::Stdout => f.write_str("Stdout"),
+            Output::Writer(_) => f.write_str("Writer"),
+        }
+    }
+}
+
+impl Output {
+    pub fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Output::Buffer(buffer) => buffer.extend_from_slice(bytes),
+            Output::Stdout => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(bytes);
+                let _ = stdout.flush();
+            }
+            Output::Writer(writer) => {
+                let _ = writer.write_all(bytes);
+            }
+        }
+    }
+
+    // Bytes written to an in-memory buffer
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Output::Buffer(bytes) => bytes,
+            _ => &[],
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<u8> {
+        match self {
+            Output::Buffer(bytes) => std::mem::take(bytes),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Stdin is read on a separate thread so that the machine never blocks
+pub fn stdin() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            let Ok(byte) = byte else { break };
+            if sender.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
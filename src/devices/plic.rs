@@ -0,0 +1,274 @@
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const PLIC_BASE: u32 = 0x0c00_0000;
+pub const PLIC_SIZE: u32 = 0x0400_0000;
+
+const PRIORITY_OFFSET: u32 = 0x0000;
+const PENDING_OFFSET: u32 = 0x1000;
+const ENABLE_OFFSET: u32 = 0x2000;
+const ENABLE_STRIDE: u32 = 0x80;
+const CONTEXT_OFFSET: u32 = 0x20_0000;
+const CONTEXT_STRIDE: u32 = 0x1000;
+
+pub const MAX_PRIORITY: u32 = 7;
+
+// Source 0 is reserved to mean no interrupt, so sources are numbered from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PLIC {
+    priority: Vec<u32>,
+    pending: Vec<bool>,
+    claimed: Vec<bool>,
+    level: Vec<bool>,
+    enable: Vec<Vec<bool>>,
+    threshold: Vec<u32>,
+}
+
+impl Default for PLIC {
+    fn default() -> Self {
+        PLIC::new(31, 1)
+    }
+}
+
+impl PLIC {
+    pub fn new(sources: usize, contexts: usize) -> Self {
+        PLIC {
+            priority: vec![0; sources + 1],
+            pending: vec![false; sources + 1],
+            claimed: vec![false; sources + 1],
+            level: vec![false; sources + 1],
+            enable: vec![vec![false; sources + 1]; contexts],
+            threshold: vec![0; contexts],
+        }
+    }
+
+    pub fn sources(&self) -> usize {
+        self.priority.len() - 1
+    }
+
+    pub fn contexts(&self) -> usize {
+        self.threshold.len()
+    }
+
+    // Drive the interrupt line of a source, a high level is forwarded as a
+    // request unless the source has already been claimed
+    pub fn set_level(&mut self, source: usize, level: bool) {
+        if source == 0 || source > self.sources() {
+            return;
+        }
+        self.level[source] = level;
+        if level && !self.claimed[source] {
+            self.pending[source] = true;
+        }
+    }
+
+    // Sources and contexts which do not exist read as zero, and writes to
+    // them are ignored
+    pub fn is_pending(&self, source: usize) -> bool {
+        self.pending.get(source).copied().unwrap_or(false)
+    }
+
+    pub fn priority(&self, source: usize) -> u32 {
+        self.priority.get(source).copied().unwrap_or(0)
+    }
+
+    pub fn set_priority(&mut self, source: usize, priority: u32) {
+        if source == 0 || source > self.sources() {
+            return;
+        }
+        self.priority[source] = priority.min(MAX_PRIORITY);
+    }
+
+    pub fn is_enabled(&self, context: usize, source: usize) -> bool {
+        self.enable
+            .get(context)
+            .and_then(|enable| enable.get(source))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    pub fn set_enabled(&mut self, context: usize, source: usize, enabled: bool) {
+        if source == 0 || source > self.sources() || context >= self.contexts() {
+            return;
+        }
+        self.enable[context][source] = enabled;
+    }
+
+    pub fn threshold(&self, context: usize) -> u32 {
+        self.threshold.get(context).copied().unwrap_or(0)
+    }
+
+    pub fn set_threshold(&mut self, context: usize, threshold: u32) {
+        if context >= self.contexts() {
+            return;
+        }
+        self.threshold[context] = threshold.min(MAX_PRIORITY);
+    }
+
+    // The highest priority source eligible for the context, ties are broken
+    // by the lowest source id
+    fn best_source(&self, context: usize) -> Option<usize> {
+        if context >= self.contexts() {
+            return None;
+        }
+        let mut best: Option<usize> = None;
+        for source in 1..=self.sources() {
+            if !self.pending[source]
+                || !self.enable[context][source]
+                || self.priority[source] <= self.threshold[context]
+            {
+                continue;
+            }
+            if best.is_none_or(|b| self.priority[source] > self.priority[b]) {
+                best = Some(source);
+            }
+        }
+        best
+    }
+
+    pub fn interrupt_pending(&self, context: usize) -> bool {
+        self.best_source(context).is_some()
+    }
+
+    pub fn claim(&mut self, context: usize) -> u32 {
+        match self.best_source(context) {
+            Some(source) => {
+                self.pending[source] = false;
+                self.claimed[source] = true;
+                source as u32
+            }
+            None => 0,
+        }
+    }
+
+    pub fn complete(&mut self, context: usize, source: u32) {
+        let source = source as usize;
+        if source == 0 || source > self.sources() || !self.is_enabled(context, source) {
+            return;
+        }
+        self.claimed[source] = false;
+        if self.level[source] {
+            self.pending[source] = true;
+        }
+    }
+
+    fn bits(&self, bits: &[bool], word: usize) -> u32 {
+        (0..32).fold(0, |acc, i| match bits.get(32 * word + i) {
+            Some(true) => acc | (1 << i),
+            _ => acc,
+        })
+    }
+
+    fn register(&self, offset: u32) -> Option<Register> {
+        if !offset.is_multiple_of(4) {
+            return None;
+        }
+        let words = self.sources() as u32 / 32 + 1;
+        let contexts = self.contexts() as u32;
+        if (PRIORITY_OFFSET..PRIORITY_OFFSET + 4 * (self.sources() as u32 + 1)).contains(&offset) {
+            return Some(Register::Priority(
+                ((offset - PRIORITY_OFFSET) / 4) as usize,
+            ));
+        }
+        if (PENDING_OFFSET..PENDING_OFFSET + 4 * words).contains(&offset) {
+            return Some(Register::Pending(((offset - PENDING_OFFSET) / 4) as usize));
+        }
+        if (ENABLE_OFFSET..ENABLE_OFFSET + ENABLE_STRIDE * contexts).contains(&offset) {
+            let relative = offset - ENABLE_OFFSET;
+            let word = (relative % ENABLE_STRIDE) / 4;
+            if word < words {
+                return Some(Register::Enable(
+                    (relative / ENABLE_STRIDE) as usize,
+                    word as usize,
+                ));
+            }
+            return None;
+        }
+        if (CONTEXT_OFFSET..CONTEXT_OFFSET + CONTEXT_STRIDE * contexts).contains(&offset) {
+            let relative = offset - CONTEXT_OFFSET;
+            let context = (relative / CONTEXT_STRIDE) as usize;
+            return match relative % CONTEXT_STRIDE {
+                0 => Some(Register::Threshold(context)),
+                4 => Some(Register::Claim(context)),
+                _ => None,
+            };
+        }
+        None
+    }
+
+    // Reading the claim register claims the interrupt, so reads take &mut
+    #[allow(clippy::result_unit_err)]
+    pub fn read(&mut self, offset: u32) -> Result<u32, ()> {
+        match self.register(offset).ok_or(())? {
+            Register::Priority(source) => Ok(self.priority[source]),
+            Register::Pending(word) => Ok(self.bits(&self.pending, word)),
+            Register::Enable(context, word) => Ok(self.bits(&self.enable[context], word)),
+            Register::Threshold(context) => Ok(self.threshold[context]),
+            Register::Claim(context) => Ok(self.claim(context)),
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write(&mut self, offset: u32, value: u32) -> Result<(), ()> {
+        match self.register(offset).ok_or(())? {
+            Register::Priority(source) => self.set_priority(source, value),
+            // pending bits are read only
+            Register::Pending(_) => {}
+            Register::Enable(context, word) => {
+                for i in 0..32 {
+                    let source = 32 * word + i;
+                    if source <= self.sources() {
+                        self.set_enabled(context, source, value & (1 << i) != 0);
+                    }
+                }
+            }
+            Register::Threshold(context) => self.set_threshold(context, value),
+            Register::Claim(context) => self.complete(context, value),
+        }
+        Ok(())
+    }
+}
+
+enum Register {
+    Priority(usize),
+    Pending(usize),
+    Enable(usize, usize),
+    Threshold(usize),
+    Claim(usize),
+}
+
+impl Snapshot for PLIC {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.u32s(&self.priority);
+        encoder.bools(&self.pending);
+        encoder.bools(&self.claimed);
+        encoder.bools(&self.level);
+        encoder.length(self.enable.len());
+        self.enable.iter().for_each(|enable| encoder.bools(enable));
+        encoder.u32s(&self.threshold);
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let plic = PLIC {
+            priority: decoder.u32s()?,
+            pending: decoder.bools()?,
+            claimed: decoder.bools()?,
+            level: decoder.bools()?,
+            enable: (0..decoder.length()?)
+                .map(|_| decoder.bools())
+                .collect::<Result<_, _>>()?,
+            threshold: decoder.u32s()?,
+        };
+        let sources = plic.priority.len();
+        let valid = sources > 0
+            && [&plic.pending, &plic.claimed, &plic.level]
+                .into_iter()
+                .chain(plic.enable.iter())
+                .all(|bits| bits.len() == sources)
+            && plic.enable.len() == plic.threshold.len();
+        if !valid {
+            return Err(SnapshotError::Invalid);
+        }
+        *self = plic;
+        Ok(())
+    }
+}
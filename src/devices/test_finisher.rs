@@ -0,0 +1,47 @@
+use crate::bus::{Device, Width};
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const TEST_FINISHER_BASE: u32 = 0x10_0000;
+pub const TEST_FINISHER_SIZE: u32 = 0x1000;
+
+pub const FINISHER_FAIL: u32 = 0x3333;
+pub const FINISHER_PASS: u32 = 0x5555;
+
+// The SiFive test finisher, writing FINISHER_PASS exits with code 0 while
+// FINISHER_FAIL exits with the code held in the upper 16 bits
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TestFinisher {
+    exit_code: Option<u32>,
+}
+
+impl Device for TestFinisher {
+    fn read(&mut self, _offset: u32, _width: Width) -> Result<u64, ()> {
+        Ok(0)
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        if offset != 0 || width != Width::Word {
+            return Err(());
+        }
+        let value = value as u32;
+        match value & 0xffff {
+            FINISHER_PASS => self.exit_code = Some(0),
+            FINISHER_FAIL => self.exit_code = Some(value >> 16),
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        encoder.optional_u32(self.exit_code);
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        self.exit_code = decoder.optional_u32()?;
+        Ok(())
+    }
+}
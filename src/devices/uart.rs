@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+
+use super::{stdin, Output};
+use crate::bus::{Device, Width};
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const UART_SIZE: u32 = 0x100;
+
+const RBR: u32 = 0;
+const IER: u32 = 1;
+const IIR: u32 = 2;
+const LCR: u32 = 3;
+const MCR: u32 = 4;
+const LSR: u32 = 5;
+const MSR: u32 = 6;
+const SCR: u32 = 7;
+
+pub const IER_ERBFI: u8 = 1 << 0;
+pub const IER_ETBEI: u8 = 1 << 1;
+
+pub const LSR_DR: u8 = 1 << 0;
+pub const LSR_THRE: u8 = 1 << 5;
+pub const LSR_TEMT: u8 = 1 << 6;
+
+const IIR_NONE: u8 = 0b0001;
+const IIR_THRE: u8 = 0b0010;
+const IIR_RDA: u8 = 0b0100;
+const IIR_FIFO: u8 = 0b1100_0000;
+
+const LCR_DLAB: u8 = 1 << 7;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+
+// An NS16550A compatible UART, with byte wide registers. Transmitted bytes are
+// sent to the output immediately, so the transmitter is always empty
+#[derive(Debug)]
+pub struct UART {
+    output: Output,
+    input: Option<Receiver<u8>>,
+    receive: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    fcr: u8,
+    scr: u8,
+    divisor: u16,
+    // set once the transmitter empties, cleared by reading IIR or writing THR
+    thre_pending: bool,
+}
+
+impl Default for UART {
+    fn default() -> Self {
+        UART::new(Output::Buffer(Vec::new()))
+    }
+}
+
+impl UART {
+    pub fn new(output: Output) -> Self {
+        UART {
+            output,
+            input: None,
+            receive: VecDeque::new(),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            fcr: 0,
+            scr: 0,
+            divisor: 0,
+            thre_pending: false,
+        }
+    }
+
+    // Transmit to stdout and receive from stdin
+    pub fn stdio() -> Self {
+        UART::new(Output::Stdout).with_input(stdin())
+    }
+
+    // Bytes sent on the channel are received by the uart
+    pub fn with_input(mut self, input: Receiver<u8>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.receive.extend(bytes);
+    }
+
+    pub fn transmitted(&self) -> &[u8] {
+        self.output.as_slice()
+    }
+
+    pub fn take_transmitted(&mut self) -> Vec<u8> {
+        self.output.take()
+    }
+
+    pub fn line_status(&self) -> u8 {
+        let mut lsr = LSR_THRE | LSR_TEMT;
+        if !self.receive.is_empty() {
+            lsr |= LSR_DR;
+        }
+        lsr
+    }
+
+    fn interrupt_identification(&self) -> u8 {
+        let fifo = if self.fcr & FCR_ENABLE != 0 {
+            IIR_FIFO
+        } else {
+            0
+        };
+        if self.ier & IER_ERBFI != 0 && !self.receive.is_empty() {
+            return fifo | IIR_RDA;
+        }
+        if self.ier & IER_ETBEI != 0 && self.thre_pending {
+            return fifo | IIR_THRE;
+        }
+        fifo | IIR_NONE
+    }
+
+    fn is_latched(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl Device for UART {
+    fn read(&mut self, offset: u32, _width: Width) -> Result<u64, ()> {
+        let value = match offset {
+            RBR if self.is_latched() => self.divisor as u8,
+            RBR => self.receive.pop_front().unwrap_or(0),
+            IER if self.is_latched() => (self.divisor >> 8) as u8,
+            IER => self.ier,
+            IIR => {
+                let iir = self.interrupt_identification();
+                if iir & 0b1111 == IIR_THRE {
+                    self.thre_pending = false;
+                }
+                iir
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => self.line_status(),
+            MSR => 0,
+            SCR => self.scr,
+            _ => return Err(()),
+        };
+        Ok(value.into())
+    }
+
+    fn write(&mut self, offset: u32, _width: Width, value: u64) -> Result<(), ()> {
+        let value = value as u8;
+        match offset {
+            RBR if self.is_latched() => self.divisor = (self.divisor & 0xff00) | u16::from(value),
+            RBR => {
+                self.output.write(&[value]);
+                self.thre_pending = true;
+            }
+            IER if self.is_latched() => {
+                self.divisor = (self.divisor & 0x00ff) | (u16::from(value) << 8)
+            }
+            IER => {
+                // enabling the transmitter interrupt while empty raises it
+                if value & IER_ETBEI != 0 && self.ier & IER_ETBEI == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = value & 0b1111;
+            }
+            IIR => {
+                if value & FCR_CLEAR_RX != 0 {
+                    self.receive.clear();
+                }
+                self.fcr = value & FCR_ENABLE;
+            }
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0b1_1111,
+            // the line and modem status registers are read only
+            LSR | MSR => {}
+            SCR => self.scr = value,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, _memory: &mut Memory) {
+        if let Some(input) = &self.input {
+            self.receive.extend(input.try_iter());
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.interrupt_identification() & IIR_NONE == 0
+    }
+
+    // The transmitted bytes have already left the machine and are not saved
+    fn save_state(&self, encoder: &mut Encoder) {
+        for value in [self.ier, self.lcr, self.mcr, self.fcr, self.scr] {
+            encoder.u8(value);
+        }
+        encoder.u16(self.divisor);
+        encoder.bool(self.thre_pending);
+        encoder.bytes(&self.receive.iter().copied().collect::<Vec<u8>>());
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for value in [
+            &mut self.ier,
+            &mut self.lcr,
+            &mut self.mcr,
+            &mut self.fcr,
+            &mut self.scr,
+        ] {
+            *value = decoder.u8()?;
+        }
+        self.divisor = decoder.u16()?;
+        self.thre_pending = decoder.bool()?;
+        self.receive = decoder.bytes()?.iter().copied().collect();
+        Ok(())
+    }
+}
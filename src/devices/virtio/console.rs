@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+
+use super::queue::Queue;
+use super::VirtioDevice;
+use crate::devices::{stdin, Output};
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, SnapshotError};
+
+pub const VIRTIO_ID_CONSOLE: u32 = 3;
+
+pub const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 1 << 2;
+
+const RECEIVE_QUEUE: usize = 0;
+const TRANSMIT_QUEUE: usize = 1;
+
+const EMERG_WR_OFFSET: u32 = 8;
+
+// A single port virtio console
+#[derive(Debug)]
+pub struct Console {
+    output: Output,
+    input: Option<Receiver<u8>>,
+    receive: VecDeque<u8>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console::new(Output::Buffer(Vec::new()))
+    }
+}
+
+impl Console {
+    pub fn new(output: Output) -> Self {
+        Console {
+            output,
+            input: None,
+            receive: VecDeque::new(),
+        }
+    }
+
+    pub fn stdio() -> Self {
+        Console::new(Output::Stdout).with_input(stdin())
+    }
+
+    pub fn with_input(mut self, input: Receiver<u8>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.receive.extend(bytes);
+    }
+
+    pub fn transmitted(&self) -> &[u8] {
+        self.output.as_slice()
+    }
+
+    pub fn take_transmitted(&mut self) -> Vec<u8> {
+        self.output.take()
+    }
+}
+
+impl VirtioDevice for Console {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_CONSOLE
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_CONSOLE_F_EMERG_WRITE
+    }
+
+    fn queues(&self) -> usize {
+        2
+    }
+
+    fn write_config(&mut self, offset: u32, value: u8) {
+        if offset == EMERG_WR_OFFSET {
+            self.output.write(&[value]);
+        }
+    }
+
+    fn process(&mut self, queues: &mut [Queue], memory: &mut Memory) -> Result<bool, ()> {
+        if let Some(input) = &self.input {
+            self.receive.extend(input.try_iter());
+        }
+        let mut used = false;
+
+        let transmit = &mut queues[TRANSMIT_QUEUE];
+        while let Some(chain) = transmit.pop(memory)? {
+            self.output.write(&chain.read(memory)?);
+            transmit.push(memory, chain.head, 0)?;
+            used = true;
+        }
+
+        let receive = &mut queues[RECEIVE_QUEUE];
+        while !self.receive.is_empty() {
+            let Some(chain) = receive.pop(memory)? else {
+                break;
+            };
+            let length = (chain.writable_length() as usize).min(self.receive.len());
+            let bytes: Vec<u8> = self.receive.drain(..length).collect();
+            let written = chain.write(memory, &bytes)?;
+            receive.push(memory, chain.head, written)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    fn reset(&mut self) {
+        self.receive.clear();
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.receive.iter().copied().collect::<Vec<u8>>());
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        self.receive = decoder.bytes()?.iter().copied().collect();
+        Ok(())
+    }
+}
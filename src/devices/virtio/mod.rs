@@ -0,0 +1,333 @@
+use std::fmt::Debug;
+
+use crate::bus::{Device, Width};
+use crate::memory::Memory;
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub mod console;
+pub mod queue;
+
+use queue::{Queue, QUEUE_SIZE_MAX};
+
+pub const VIRTIO_BASE: u32 = 0x1000_8000;
+pub const VIRTIO_SIZE: u32 = 0x1000;
+
+pub const VIRTIO_MAGIC: u32 = 0x7472_6976;
+pub const VIRTIO_VERSION: u32 = 2;
+pub const VIRTIO_VENDOR: u32 = 0x554d_4551;
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+pub const STATUS_ACKNOWLEDGE: u32 = 1;
+pub const STATUS_DRIVER: u32 = 2;
+pub const STATUS_DRIVER_OK: u32 = 4;
+pub const STATUS_FEATURES_OK: u32 = 8;
+pub const STATUS_DEVICE_NEEDS_RESET: u32 = 64;
+pub const STATUS_FAILED: u32 = 128;
+
+pub const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+pub const INTERRUPT_CONFIG_CHANGE: u32 = 1 << 1;
+
+const MAGIC_VALUE: u32 = 0x000;
+const VERSION: u32 = 0x004;
+const DEVICE_ID: u32 = 0x008;
+const VENDOR_ID: u32 = 0x00c;
+const DEVICE_FEATURES: u32 = 0x010;
+const DEVICE_FEATURES_SEL: u32 = 0x014;
+const DRIVER_FEATURES: u32 = 0x020;
+const DRIVER_FEATURES_SEL: u32 = 0x024;
+const QUEUE_SEL: u32 = 0x030;
+const QUEUE_NUM_MAX: u32 = 0x034;
+const QUEUE_NUM: u32 = 0x038;
+const QUEUE_READY: u32 = 0x044;
+const QUEUE_NOTIFY: u32 = 0x050;
+const INTERRUPT_STATUS: u32 = 0x060;
+const INTERRUPT_ACK: u32 = 0x064;
+const STATUS: u32 = 0x070;
+const QUEUE_DESC_LOW: u32 = 0x080;
+const QUEUE_DESC_HIGH: u32 = 0x084;
+const QUEUE_DRIVER_LOW: u32 = 0x090;
+const QUEUE_DRIVER_HIGH: u32 = 0x094;
+const QUEUE_DEVICE_LOW: u32 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u32 = 0x0a4;
+const CONFIG_GENERATION: u32 = 0x0fc;
+const CONFIG: u32 = 0x100;
+
+// A device sitting behind the virtio-mmio transport
+pub trait VirtioDevice: Debug + 'static {
+    fn device_id(&self) -> u32;
+
+    // Device specific feature bits, VIRTIO_F_VERSION_1 is always offered
+    fn features(&self) -> u64;
+
+    fn queues(&self) -> usize;
+
+    fn read_config(&mut self, _offset: u32) -> u8 {
+        0
+    }
+
+    fn write_config(&mut self, _offset: u32, _value: u8) {}
+
+    // Service the available buffers of each queue, returning if any were used.
+    // A malformed queue is an error
+    #[allow(clippy::result_unit_err)]
+    fn process(&mut self, queues: &mut [Queue], memory: &mut Memory) -> Result<bool, ()>;
+
+    fn reset(&mut self) {}
+
+    fn save_state(&self, _encoder: &mut Encoder) {}
+
+    fn restore_state(&mut self, _decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        Ok(())
+    }
+}
+
+// The virtio-mmio (version 2) register interface with split virtqueues
+#[derive(Debug)]
+pub struct VirtioMMIO<D: VirtioDevice> {
+    device: D,
+    queues: Vec<Queue>,
+    queue_select: u32,
+    device_features_select: u32,
+    driver_features_select: u32,
+    driver_features: u64,
+    status: u32,
+    interrupt_status: u32,
+}
+
+impl<D: VirtioDevice> VirtioMMIO<D> {
+    pub fn new(device: D) -> Self {
+        let queues = vec![Queue::default(); device.queues()];
+        VirtioMMIO {
+            device,
+            queues,
+            queue_select: 0,
+            device_features_select: 0,
+            driver_features_select: 0,
+            driver_features: 0,
+            status: 0,
+            interrupt_status: 0,
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn driver_features(&self) -> u64 {
+        self.driver_features
+    }
+
+    fn device_features(&self) -> u64 {
+        self.device.features() | VIRTIO_F_VERSION_1
+    }
+
+    fn reset(&mut self) {
+        self.device.reset();
+        for queue in self.queues.iter_mut() {
+            queue.reset();
+        }
+        self.queue_select = 0;
+        self.device_features_select = 0;
+        self.driver_features_select = 0;
+        self.driver_features = 0;
+        self.status = 0;
+        self.interrupt_status = 0;
+    }
+
+    fn set_status(&mut self, value: u32) {
+        if value == 0 {
+            self.reset();
+            return;
+        }
+        let mut status = value;
+        // features are only accepted if the driver selected a subset of the
+        // offered features including VIRTIO_F_VERSION_1
+        if status & STATUS_FEATURES_OK != 0 && self.status & STATUS_FEATURES_OK == 0 {
+            let accepted = self.driver_features & !self.device_features() == 0
+                && self.driver_features & VIRTIO_F_VERSION_1 != 0;
+            if !accepted {
+                status &= !STATUS_FEATURES_OK;
+            }
+        }
+        self.status = status;
+    }
+
+    fn selected_queue(&mut self) -> Option<&mut Queue> {
+        // queues can not be reconfigured once the driver is running
+        if self.status & STATUS_DRIVER_OK != 0 {
+            return None;
+        }
+        self.queues.get_mut(self.queue_select as usize)
+    }
+
+    fn read_register(&mut self, offset: u32) -> Result<u32, ()> {
+        let queue = self.queues.get(self.queue_select as usize);
+        let value = match offset {
+            MAGIC_VALUE => VIRTIO_MAGIC,
+            VERSION => VIRTIO_VERSION,
+            DEVICE_ID => self.device.device_id(),
+            VENDOR_ID => VIRTIO_VENDOR,
+            DEVICE_FEATURES => match self.device_features_select {
+                0 => self.device_features() as u32,
+                1 => (self.device_features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX => queue.map_or(0, |_| u32::from(QUEUE_SIZE_MAX)),
+            QUEUE_NUM => queue.map_or(0, |queue| u32::from(queue.size)),
+            QUEUE_READY => queue.map_or(0, |queue| u32::from(queue.ready)),
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            QUEUE_DESC_LOW => queue.map_or(0, |queue| queue.descriptor_table as u32),
+            QUEUE_DESC_HIGH => queue.map_or(0, |queue| (queue.descriptor_table >> 32) as u32),
+            QUEUE_DRIVER_LOW => queue.map_or(0, |queue| queue.driver_area as u32),
+            QUEUE_DRIVER_HIGH => queue.map_or(0, |queue| (queue.driver_area >> 32) as u32),
+            QUEUE_DEVICE_LOW => queue.map_or(0, |queue| queue.device_area as u32),
+            QUEUE_DEVICE_HIGH => queue.map_or(0, |queue| (queue.device_area >> 32) as u32),
+            CONFIG_GENERATION => 0,
+            // the remaining registers are write only
+            DEVICE_FEATURES_SEL | DRIVER_FEATURES | DRIVER_FEATURES_SEL | QUEUE_SEL
+            | QUEUE_NOTIFY | INTERRUPT_ACK => 0,
+            _ => return Err(()),
+        };
+        Ok(value)
+    }
+
+    fn write_register(&mut self, offset: u32, value: u32) -> Result<(), ()> {
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_select = value,
+            DRIVER_FEATURES => {
+                let shift = match self.driver_features_select {
+                    0 => 0,
+                    1 => 32,
+                    _ => return Ok(()),
+                };
+                self.driver_features =
+                    (self.driver_features & !(0xffff_ffff << shift)) | (u64::from(value) << shift);
+            }
+            DRIVER_FEATURES_SEL => self.driver_features_select = value,
+            QUEUE_SEL => self.queue_select = value,
+            QUEUE_NUM => {
+                // split queues must have a power of two size
+                if let Some(queue) = self.selected_queue() {
+                    if value.is_power_of_two() && value <= u32::from(QUEUE_SIZE_MAX) {
+                        queue.size = value as u16;
+                    }
+                }
+            }
+            QUEUE_READY => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.ready = value & 1 != 0 && queue.size != 0;
+                }
+            }
+            // buffers are serviced every tick, so notifications need no action
+            QUEUE_NOTIFY => {}
+            INTERRUPT_ACK => self.interrupt_status &= !value,
+            STATUS => self.set_status(value),
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH | QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH
+            | QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(queue) = self.selected_queue() {
+                    let address = match offset {
+                        QUEUE_DESC_LOW | QUEUE_DESC_HIGH => &mut queue.descriptor_table,
+                        QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => &mut queue.driver_area,
+                        _ => &mut queue.device_area,
+                    };
+                    let shift = if offset & 0x4 == 0 { 0 } else { 32 };
+                    *address = (*address & !(0xffff_ffff << shift)) | (u64::from(value) << shift);
+                }
+            }
+            MAGIC_VALUE | VERSION | DEVICE_ID | VENDOR_ID | DEVICE_FEATURES | QUEUE_NUM_MAX
+            | INTERRUPT_STATUS | CONFIG_GENERATION => {}
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+}
+
+impl<D: VirtioDevice> Device for VirtioMMIO<D> {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        if offset >= CONFIG {
+            return Ok((0..width.bytes()).fold(0, |acc, i| {
+                acc | (u64::from(self.device.read_config(offset - CONFIG + i)) << (8 * i))
+            }));
+        }
+        if width != Width::Word {
+            return Err(());
+        }
+        self.read_register(offset).map(u64::from)
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        if offset >= CONFIG {
+            for i in 0..width.bytes() {
+                self.device
+                    .write_config(offset - CONFIG + i, (value >> (8 * i)) as u8);
+            }
+            return Ok(());
+        }
+        if width != Width::Word {
+            return Err(());
+        }
+        self.write_register(offset, value as u32)
+    }
+
+    fn tick(&mut self, memory: &mut Memory) {
+        let running = self.status & STATUS_DRIVER_OK != 0
+            && self.status & (STATUS_DEVICE_NEEDS_RESET | STATUS_FAILED) == 0;
+        if !running {
+            return;
+        }
+        match self.device.process(&mut self.queues, memory) {
+            Ok(true) => self.interrupt_status |= INTERRUPT_USED_BUFFER,
+            Ok(false) => {}
+            Err(()) => {
+                self.status |= STATUS_DEVICE_NEEDS_RESET;
+                self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+            }
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        for value in [
+            self.queue_select,
+            self.device_features_select,
+            self.driver_features_select,
+            self.status,
+            self.interrupt_status,
+        ] {
+            encoder.u32(value);
+        }
+        encoder.u64(self.driver_features);
+        self.queues.iter().for_each(|queue| queue.save(encoder));
+        self.device.save_state(encoder);
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        for value in [
+            &mut self.queue_select,
+            &mut self.device_features_select,
+            &mut self.driver_features_select,
+            &mut self.status,
+            &mut self.interrupt_status,
+        ] {
+            *value = decoder.u32()?;
+        }
+        self.driver_features = decoder.u64()?;
+        for queue in self.queues.iter_mut() {
+            queue.restore(decoder)?;
+        }
+        self.device.restore_state(decoder)
+    }
+}
@@ -0,0 +1,184 @@
+use crate::memory::{Endianness, Memory};
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const QUEUE_SIZE_MAX: u16 = 256;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1 << 0;
+const VIRTQ_DESC_F_WRITE: u16 = 1 << 1;
+
+const DESCRIPTOR_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    pub address: u64,
+    pub length: u32,
+    pub writable: bool,
+}
+
+// A chain of descriptors made available by the driver, returned to the driver
+// by pushing its head onto the used ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub head: u16,
+    pub descriptors: Vec<Descriptor>,
+}
+
+impl Chain {
+    // The contents of the device readable buffers
+    #[allow(clippy::result_unit_err)]
+    pub fn read(&self, memory: &Memory) -> Result<Vec<u8>, ()> {
+        let mut bytes = Vec::new();
+        for descriptor in self.descriptors.iter().filter(|d| !d.writable) {
+            let address = to_address(descriptor.address)?;
+            bytes.extend(memory.read_range(address, descriptor.length as usize)?);
+        }
+        Ok(bytes)
+    }
+
+    pub fn writable_length(&self) -> u32 {
+        self.descriptors
+            .iter()
+            .filter(|d| d.writable)
+            .map(|d| d.length)
+            .sum()
+    }
+
+    // Fill the device writable buffers in order, returning the number of bytes
+    // written
+    #[allow(clippy::result_unit_err)]
+    pub fn write(&self, memory: &mut Memory, bytes: &[u8]) -> Result<u32, ()> {
+        let mut remaining = bytes;
+        for descriptor in self.descriptors.iter().filter(|d| d.writable) {
+            let length = remaining.len().min(descriptor.length as usize);
+            let (head, tail) = remaining.split_at(length);
+            memory.load_slice(to_address(descriptor.address)?, head)?;
+            remaining = tail;
+        }
+        Ok((bytes.len() - remaining.len()) as u32)
+    }
+}
+
+// A split virtqueue, with the descriptor table, driver (available) ring and
+// device (used) ring placed in main memory by the driver
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Queue {
+    pub size: u16,
+    pub ready: bool,
+    pub descriptor_table: u64,
+    pub driver_area: u64,
+    pub device_area: u64,
+    last_available: u16,
+}
+
+impl Queue {
+    pub fn reset(&mut self) {
+        *self = Queue::default();
+    }
+
+    fn available_index(&self, memory: &Memory) -> Result<u16, ()> {
+        memory.read_u16(offset_address(self.driver_area, 2)?, Endianness::Little)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn has_available(&self, memory: &Memory) -> Result<bool, ()> {
+        Ok(self.ready && self.available_index(memory)? != self.last_available)
+    }
+
+    // Take the next chain made available by the driver
+    #[allow(clippy::result_unit_err)]
+    pub fn pop(&mut self, memory: &Memory) -> Result<Option<Chain>, ()> {
+        if !self.has_available(memory)? {
+            return Ok(None);
+        }
+        let slot = u64::from(self.last_available % self.size);
+        let head = memory.read_u16(
+            offset_address(self.driver_area, 4 + 2 * slot)?,
+            Endianness::Little,
+        )?;
+
+        let mut descriptors = Vec::new();
+        let mut index = head;
+        loop {
+            // a chain longer than the queue must contain a loop
+            if index >= self.size || descriptors.len() >= usize::from(self.size) {
+                return Err(());
+            }
+            let address =
+                offset_address(self.descriptor_table, DESCRIPTOR_SIZE * u64::from(index))?;
+            let field = |offset| offset_address(address.into(), offset);
+            let flags = memory.read_u16(field(12)?, Endianness::Little)?;
+            descriptors.push(Descriptor {
+                address: memory.read_u64(address, Endianness::Little)?,
+                length: memory.read_u32(field(8)?, Endianness::Little)?,
+                writable: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            index = memory.read_u16(field(14)?, Endianness::Little)?;
+        }
+
+        self.last_available = self.last_available.wrapping_add(1);
+        Ok(Some(Chain { head, descriptors }))
+    }
+
+    // Return a chain to the driver, with the number of bytes written into it
+    #[allow(clippy::result_unit_err)]
+    pub fn push(&mut self, memory: &mut Memory, head: u16, length: u32) -> Result<(), ()> {
+        let used_index =
+            memory.read_u16(offset_address(self.device_area, 2)?, Endianness::Little)?;
+        let element = offset_address(self.device_area, 4 + 8 * u64::from(used_index % self.size))?;
+        memory.write_u32(element, head.into(), Endianness::Little)?;
+        memory.write_u32(
+            offset_address(element.into(), 4)?,
+            length,
+            Endianness::Little,
+        )?;
+        memory.write_u16(
+            offset_address(self.device_area, 2)?,
+            used_index.wrapping_add(1),
+            Endianness::Little,
+        )
+    }
+}
+
+impl Snapshot for Queue {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.u16(self.size);
+        encoder.bool(self.ready);
+        encoder.u64(self.descriptor_table);
+        encoder.u64(self.driver_area);
+        encoder.u64(self.device_area);
+        encoder.u16(self.last_available);
+    }
+
+    // The size is checked in the same way as a write from the driver, as a
+    // ready queue is indexed modulo its size
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let queue = Queue {
+            size: decoder.u16()?,
+            ready: decoder.bool()?,
+            descriptor_table: decoder.u64()?,
+            driver_area: decoder.u64()?,
+            device_area: decoder.u64()?,
+            last_available: decoder.u16()?,
+        };
+        let valid_size =
+            queue.size == 0 || (queue.size.is_power_of_two() && queue.size <= QUEUE_SIZE_MAX);
+        if !valid_size || (queue.ready && queue.size == 0) {
+            return Err(SnapshotError::Invalid);
+        }
+        *self = queue;
+        Ok(())
+    }
+}
+
+// Only the lower 4 GiB of the 64 bit guest addresses can be reached
+fn to_address(address: u64) -> Result<u32, ()> {
+    u32::try_from(address).map_err(|_| ())
+}
+
+// The ring addresses are written by the guest, so the sum may overflow
+fn offset_address(address: u64, offset: u64) -> Result<u32, ()> {
+    to_address(address.checked_add(offset).ok_or(())?)
+}
@@ -0,0 +1,116 @@
+use super::{EM_RISCV, PT_LOAD};
+use crate::memory::{Memory, PAGE_SIZE};
+
+pub const ET_CORE: u16 = 4;
+pub const PT_NOTE: u32 = 4;
+pub const NT_PRSTATUS: u32 = 1;
+
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGSEGV: u32 = 11;
+
+const HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+
+// The layout of struct elf_prstatus on RV32, with the general purpose
+// registers following the signal information, process ids and times
+pub const PRSTATUS_SIZE: usize = 204;
+pub const PRSTATUS_REGISTERS: usize = 72;
+
+// The registers are stored in the order of elf_gregset_t, with the program
+// counter in place of the hardwired zero register
+pub fn prstatus(registers: &[u32; 32], program_counter: u32, signal: u32) -> Vec<u8> {
+    let mut status = vec![0; PRSTATUS_SIZE];
+    // pr_info.si_signo and pr_cursig
+    status[0..4].copy_from_slice(&signal.to_le_bytes());
+    status[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+    // pr_pid
+    status[24..28].copy_from_slice(&1u32.to_le_bytes());
+    for (i, register) in registers.iter().enumerate() {
+        let value = if i == 0 { program_counter } else { *register };
+        let offset = PRSTATUS_REGISTERS + 4 * i;
+        status[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    status
+}
+
+// An ELF32 core file, with a PT_NOTE segment holding the prstatus of the hart
+// followed by a PT_LOAD segment for each run of non-zero pages of memory
+pub fn write(memory: &Memory, registers: &[u32; 32], program_counter: u32, signal: u32) -> Vec<u8> {
+    let mut segments: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (page, bytes) in memory.pages() {
+        let address = page * PAGE_SIZE;
+        match segments.last_mut() {
+            Some((start, data)) if *start + data.len() == address => data.extend(bytes),
+            _ => segments.push((address, bytes)),
+        }
+    }
+
+    let mut note = Vec::new();
+    note.extend(5u32.to_le_bytes());
+    note.extend((PRSTATUS_SIZE as u32).to_le_bytes());
+    note.extend(NT_PRSTATUS.to_le_bytes());
+    // the name and description are each padded to a multiple of four bytes
+    note.extend(b"CORE\0\0\0\0");
+    note.extend(prstatus(registers, program_counter, signal));
+
+    let program_headers = 1 + segments.len();
+    let mut core = b"\x7fELF".to_vec();
+    core.extend([1, 1, 1]);
+    core.resize(16, 0);
+    core.extend(ET_CORE.to_le_bytes());
+    core.extend(EM_RISCV.to_le_bytes());
+    core.extend(1u32.to_le_bytes());
+    // the entry point, program and section header offsets and the flags
+    for value in [0, HEADER_SIZE as u32, 0, 0] {
+        core.extend(value.to_le_bytes());
+    }
+    for value in [
+        HEADER_SIZE as u16,
+        PROGRAM_HEADER_SIZE as u16,
+        program_headers as u16,
+        0,
+        0,
+        0,
+    ] {
+        core.extend(value.to_le_bytes());
+    }
+
+    let mut offset = HEADER_SIZE + program_headers * PROGRAM_HEADER_SIZE;
+    push_program_header(&mut core, PT_NOTE, offset, 0, note.len(), 0);
+    offset += note.len();
+    for (address, data) in segments.iter() {
+        // readable, writable and executable
+        push_program_header(&mut core, PT_LOAD, offset, *address, data.len(), 7);
+        offset += data.len();
+    }
+    core.extend(note);
+    for (_, data) in segments {
+        core.extend(data);
+    }
+    core
+}
+
+fn push_program_header(
+    core: &mut Vec<u8>,
+    kind: u32,
+    offset: usize,
+    address: usize,
+    size: usize,
+    flags: u32,
+) {
+    // the segment data is packed, so the loadable segments are not page aligned
+    let alignment = if kind == PT_NOTE { 4 } else { 1 };
+    for value in [
+        kind,
+        offset as u32,
+        address as u32,
+        address as u32,
+        size as u32,
+        size as u32,
+        flags,
+        alignment as u32,
+    ] {
+        core.extend(value.to_le_bytes());
+    }
+}
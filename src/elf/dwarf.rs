@@ -0,0 +1,464 @@
+use std::fmt;
+
+use super::{ELFError, ELF};
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+// A position in the source of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Location {
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+// Shown as the file name and line, such as main.c:42
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file_name(), self.line)
+    }
+}
+
+// The source location of each range of addresses, from the line number
+// programs in the .debug_line section
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineTable {
+    // the start and end of each range, sorted by start
+    ranges: Vec<(u64, u64, Location)>,
+}
+
+impl LineTable {
+    // An empty table if the file has no line information
+    pub fn from_elf(elf: &ELF) -> Result<LineTable, ELFError> {
+        let section = |name: &str| match elf.section(name) {
+            Some(section) => elf.section_data(section),
+            None => Ok(&[][..]),
+        };
+        LineTable::parse(
+            section(".debug_line")?,
+            section(".debug_line_str")?,
+            section(".debug_str")?,
+        )
+    }
+
+    // Parse each line number program in the .debug_line section, where the
+    // string sections hold the paths of DWARF 5 programs
+    pub fn parse(
+        debug_line: &[u8],
+        debug_line_str: &[u8],
+        debug_str: &[u8],
+    ) -> Result<LineTable, ELFError> {
+        let strings = Strings {
+            debug_line_str,
+            debug_str,
+        };
+        let mut table = LineTable::default();
+        let mut cursor = Cursor::new(debug_line);
+        while !cursor.is_empty() {
+            let (length, dwarf64) = match cursor.u32()? {
+                0xffff_ffff => (cursor.u64()?, true),
+                length => (length.into(), false),
+            };
+            let unit = cursor.take(length)?;
+            table.parse_unit(&mut Cursor { dwarf64, ..unit }, &strings)?;
+        }
+        table.ranges.sort_by_key(|(start, _, _)| *start);
+        Ok(table)
+    }
+
+    fn parse_unit(&mut self, unit: &mut Cursor, strings: &Strings) -> Result<(), ELFError> {
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(ELFError::UnsupportedDWARFVersion(version));
+        }
+        if version >= 5 {
+            // the address and segment selector sizes
+            unit.u8()?;
+            unit.u8()?;
+        }
+        // the program follows the header
+        let header_length = unit.offset()?;
+        let mut program = unit.clone();
+        program.skip(header_length)?;
+
+        let minimum_instruction_length = u64::from(unit.u8()?);
+        if version >= 4 {
+            // the maximum operations per instruction, only used by VLIW
+            unit.u8()?;
+        }
+        // whether rows start a statement by default, which is not recorded
+        unit.u8()?;
+        let line_base = unit.u8()? as i8;
+        let line_range = unit.u8()?;
+        let opcode_base = unit.u8()?;
+        if line_range == 0 {
+            return Err(ELFError::Truncated);
+        }
+        let opcode_lengths = (1..opcode_base)
+            .map(|_| unit.u8())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (directories, mut files) = match version {
+            5 => {
+                let directories = unit.entries(strings)?;
+                let directories = directories.into_iter().map(|(path, _)| path).collect();
+                (directories, unit.entries(strings)?)
+            }
+            _ => {
+                // directory zero is the unknown compilation directory, and
+                // file zero is unused
+                let mut directories = vec![String::new()];
+                loop {
+                    let directory = unit.string()?;
+                    if directory.is_empty() {
+                        break;
+                    }
+                    directories.push(directory);
+                }
+                let mut files = vec![(String::new(), 0)];
+                loop {
+                    let name = unit.string()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    files.push((name, unit.file_attributes()?));
+                }
+                (directories, files)
+            }
+        };
+
+        let mut state = Row::default();
+        let mut sequence: Vec<Row> = Vec::new();
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = opcode - opcode_base;
+                state.advance(u64::from(adjusted / line_range) * minimum_instruction_length);
+                state.line = state
+                    .line
+                    .wrapping_add_signed(i64::from(line_base) + i64::from(adjusted % line_range));
+                sequence.push(state.clone());
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = program.uleb()?;
+                    let mut instruction = program.take(length)?;
+                    match instruction.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            sequence.push(state.clone());
+                            self.add_sequence(&sequence, &directories, &files);
+                            sequence.clear();
+                            state = Row::default();
+                        }
+                        DW_LNE_SET_ADDRESS => state.address = instruction.sized(length - 1)?,
+                        DW_LNE_DEFINE_FILE => {
+                            let name = instruction.string()?;
+                            files.push((name, instruction.file_attributes()?));
+                        }
+                        // such as the discriminator
+                        _ => {}
+                    }
+                }
+                DW_LNS_COPY => sequence.push(state.clone()),
+                DW_LNS_ADVANCE_PC => {
+                    state.advance(program.uleb()?.wrapping_mul(minimum_instruction_length))
+                }
+                DW_LNS_ADVANCE_LINE => state.line = state.line.wrapping_add_signed(program.sleb()?),
+                DW_LNS_SET_FILE => state.file = program.uleb()?,
+                DW_LNS_SET_COLUMN => state.column = program.uleb()?,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = 255 - opcode_base;
+                    state.advance(u64::from(adjusted / line_range) * minimum_instruction_length);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => state.advance(program.u16()?.into()),
+                // the remaining standard opcodes only change flags which are
+                // not recorded, skip their operands
+                _ => {
+                    for _ in 0..opcode_lengths[usize::from(opcode) - 1] {
+                        program.uleb()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_sequence(&mut self, sequence: &[Row], directories: &[String], files: &[(String, u64)]) {
+        for rows in sequence.windows(2) {
+            let (row, next) = (&rows[0], &rows[1]);
+            if row.address >= next.address {
+                continue;
+            }
+            let Some((name, directory)) = files.get(row.file as usize) else {
+                continue;
+            };
+            let path = match directories.get(*directory as usize) {
+                Some(directory) if !directory.is_empty() && !name.starts_with('/') => {
+                    format!("{}/{}", directory, name)
+                }
+                _ => name.clone(),
+            };
+            let location = Location {
+                path,
+                line: row.line as u32,
+                column: row.column as u32,
+            };
+            self.ranges.push((row.address, next.address, location));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn lookup(&self, address: u64) -> Option<&Location> {
+        let index = self
+            .ranges
+            .partition_point(|(start, _, _)| *start <= address)
+            .checked_sub(1)?;
+        let (_, end, location) = &self.ranges[index];
+        (address < *end).then_some(location)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    address: u64,
+    file: u64,
+    line: u64,
+    column: u64,
+}
+
+impl Row {
+    fn advance(&mut self, length: u64) {
+        self.address = self.address.wrapping_add(length);
+    }
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Row {
+            address: 0,
+            file: 1,
+            line: 1,
+            column: 0,
+        }
+    }
+}
+
+struct Strings<'a> {
+    debug_line_str: &'a [u8],
+    debug_str: &'a [u8],
+}
+
+#[derive(Debug, Clone)]
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    // offsets into other sections are 8 bytes in the 64 bit format
+    dwarf64: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Cursor {
+            bytes,
+            position: 0,
+            dwarf64: false,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn skip(&mut self, length: u64) -> Result<(), ELFError> {
+        self.take(length).map(|_| ())
+    }
+
+    fn take(&mut self, length: u64) -> Result<Cursor<'a>, ELFError> {
+        let length = usize::try_from(length).map_err(|_| ELFError::Truncated)?;
+        let end = self
+            .position
+            .checked_add(length)
+            .ok_or(ELFError::Truncated)?;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(ELFError::Truncated)?;
+        self.position = end;
+        Ok(Cursor {
+            bytes,
+            position: 0,
+            dwarf64: self.dwarf64,
+        })
+    }
+
+    fn u8(&mut self) -> Result<u8, ELFError> {
+        Ok(self.take(1)?.bytes[0])
+    }
+
+    // A little endian value of up to 8 bytes
+    fn sized(&mut self, length: u64) -> Result<u64, ELFError> {
+        if length > 8 {
+            return Err(ELFError::Truncated);
+        }
+        let bytes = self.take(length)?.bytes;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+    }
+
+    fn u16(&mut self) -> Result<u16, ELFError> {
+        self.sized(2).map(|value| value as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, ELFError> {
+        self.sized(4).map(|value| value as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, ELFError> {
+        self.sized(8)
+    }
+
+    fn offset(&mut self) -> Result<u64, ELFError> {
+        self.sized(if self.dwarf64 { 8 } else { 4 })
+    }
+
+    fn uleb(&mut self) -> Result<u64, ELFError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ELFError::Truncated)
+    }
+
+    fn sleb(&mut self) -> Result<i64, ELFError> {
+        let mut value = 0i64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= i64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                // sign extend from the last bit read
+                if shift < 57 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Ok(value);
+            }
+        }
+        Err(ELFError::Truncated)
+    }
+
+    fn string(&mut self) -> Result<String, ELFError> {
+        let rest = self.bytes.get(self.position..).ok_or(ELFError::Truncated)?;
+        let length = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(ELFError::Truncated)?;
+        self.position += length + 1;
+        Ok(String::from_utf8_lossy(&rest[..length]).into_owned())
+    }
+
+    // The directory index of a DWARF 2 to 4 file entry, skipping its
+    // modification time and length
+    fn file_attributes(&mut self) -> Result<u64, ELFError> {
+        let directory = self.uleb()?;
+        self.uleb()?;
+        self.uleb()?;
+        Ok(directory)
+    }
+
+    // The path and directory index of each DWARF 5 directory or file entry,
+    // described by a list of content types and their forms
+    fn entries(&mut self, strings: &Strings) -> Result<Vec<(String, u64)>, ELFError> {
+        let formats = (0..self.u8()?)
+            .map(|_| Ok((self.uleb()?, self.uleb()?)))
+            .collect::<Result<Vec<_>, ELFError>>()?;
+        (0..self.uleb()?)
+            .map(|_| {
+                let (mut path, mut directory) = (String::new(), 0);
+                for (content, form) in formats.iter() {
+                    let value = self.form(*form, strings)?;
+                    match (*content, value) {
+                        (DW_LNCT_PATH, Value::String(value)) => path = value,
+                        (DW_LNCT_DIRECTORY_INDEX, Value::Number(value)) => directory = value,
+                        _ => {}
+                    }
+                }
+                Ok((path, directory))
+            })
+            .collect()
+    }
+
+    fn form(&mut self, form: u64, strings: &Strings) -> Result<Value, ELFError> {
+        let value = match form {
+            DW_FORM_STRING => Value::String(self.string()?),
+            DW_FORM_LINE_STRP => Value::String(string_at(strings.debug_line_str, self.offset()?)?),
+            DW_FORM_STRP => Value::String(string_at(strings.debug_str, self.offset()?)?),
+            DW_FORM_UDATA => Value::Number(self.uleb()?),
+            DW_FORM_DATA1 => Value::Number(self.sized(1)?),
+            DW_FORM_DATA2 => Value::Number(self.sized(2)?),
+            DW_FORM_DATA4 => Value::Number(self.sized(4)?),
+            DW_FORM_DATA8 => Value::Number(self.sized(8)?),
+            DW_FORM_DATA16 => {
+                self.skip(16)?;
+                Value::Other
+            }
+            DW_FORM_BLOCK => {
+                let length = self.uleb()?;
+                self.skip(length)?;
+                Value::Other
+            }
+            _ => return Err(ELFError::UnsupportedDWARFForm(form)),
+        };
+        Ok(value)
+    }
+}
+
+enum Value {
+    String(String),
+    Number(u64),
+    Other,
+}
+
+fn string_at(section: &[u8], offset: u64) -> Result<String, ELFError> {
+    let mut cursor = Cursor::new(section);
+    cursor.skip(offset)?;
+    cursor.string()
+}
@@ -0,0 +1,331 @@
+pub mod coredump;
+pub mod dwarf;
+pub mod symbols;
+
+pub const EM_RISCV: u16 = 243;
+
+pub const ET_EXEC: u16 = 2;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const EF_RISCV_RVC: u32 = 0x1;
+pub const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+pub const EF_RISCV_FLOAT_ABI_SOFT: u32 = 0x0;
+pub const EF_RISCV_FLOAT_ABI_SINGLE: u32 = 0x2;
+pub const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+pub const EF_RISCV_FLOAT_ABI_QUAD: u32 = 0x6;
+pub const EF_RISCV_RVE: u32 = 0x8;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFDATA2LSB: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ELFError {
+    Truncated,
+    BadMagic,
+    UnsupportedClass,
+    UnsupportedEncoding,
+    UnsupportedType(u16),
+    UnsupportedMachine(u16),
+    // e_flags require an extension which is not enabled
+    UnsupportedFlags(u32),
+    // a segment holds more of the file than it occupies in memory
+    InvalidSegment,
+    // a segment or the entry point lies outside of memory
+    OutOfRange,
+    UnsupportedDWARFVersion(u16),
+    UnsupportedDWARFForm(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    ELF32,
+    ELF64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub entry_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+}
+
+// A parsed little endian ELF file, holding on to its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ELF {
+    pub class: Class,
+    pub kind: u16,
+    pub machine: u16,
+    pub flags: u32,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    bytes: Vec<u8>,
+}
+
+// Reads the fields of an ELF file, whose size depends on the class
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    class: Class,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], class: Class) -> Self {
+        Reader { bytes, class }
+    }
+
+    pub(crate) fn bytes(&self, offset: u64, length: u64) -> Result<&'a [u8], ELFError> {
+        let start = usize::try_from(offset).map_err(|_| ELFError::Truncated)?;
+        let length = usize::try_from(length).map_err(|_| ELFError::Truncated)?;
+        let end = start.checked_add(length).ok_or(ELFError::Truncated)?;
+        self.bytes.get(start..end).ok_or(ELFError::Truncated)
+    }
+
+    fn array<const N: usize>(&self, offset: u64) -> Result<[u8; N], ELFError> {
+        let bytes = self.bytes(offset, N as u64)?;
+        bytes.try_into().map_err(|_| ELFError::Truncated)
+    }
+
+    pub(crate) fn u8(&self, offset: u64) -> Result<u8, ELFError> {
+        Ok(self.array::<1>(offset)?[0])
+    }
+
+    pub(crate) fn u16(&self, offset: u64) -> Result<u16, ELFError> {
+        Ok(u16::from_le_bytes(self.array(offset)?))
+    }
+
+    pub(crate) fn u32(&self, offset: u64) -> Result<u32, ELFError> {
+        Ok(u32::from_le_bytes(self.array(offset)?))
+    }
+
+    pub(crate) fn u64(&self, offset: u64) -> Result<u64, ELFError> {
+        Ok(u64::from_le_bytes(self.array(offset)?))
+    }
+
+    // An address or offset sized field
+    pub(crate) fn word(&self, offset: u64) -> Result<u64, ELFError> {
+        match self.class {
+            Class::ELF32 => self.u32(offset).map(u64::from),
+            Class::ELF64 => self.u64(offset),
+        }
+    }
+
+    // A null terminated string
+    pub(crate) fn string(&self, offset: u64) -> Result<String, ELFError> {
+        let start = usize::try_from(offset).map_err(|_| ELFError::Truncated)?;
+        let bytes = self.bytes.get(start..).ok_or(ELFError::Truncated)?;
+        let length = bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(ELFError::Truncated)?;
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+}
+
+// Offsets are read from the file, so adding to them may overflow
+pub(crate) fn add_offset(base: u64, offset: u64) -> Result<u64, ELFError> {
+    base.checked_add(offset).ok_or(ELFError::Truncated)
+}
+
+impl ELF {
+    pub fn parse(bytes: &[u8]) -> Result<ELF, ELFError> {
+        if bytes.get(..4) != Some(ELF_MAGIC) {
+            return Err(ELFError::BadMagic);
+        }
+        let class = match Reader::new(bytes, Class::ELF32).u8(4)? {
+            1 => Class::ELF32,
+            2 => Class::ELF64,
+            _ => return Err(ELFError::UnsupportedClass),
+        };
+        let reader = Reader::new(bytes, class);
+        if reader.u8(5)? != ELFDATA2LSB {
+            return Err(ELFError::UnsupportedEncoding);
+        }
+
+        // the fields following the entry point are shifted by the wider
+        // addresses in ELF64
+        let (program_header_offset, section_header_offset, shift) = match class {
+            Class::ELF32 => (28, 32, 0),
+            Class::ELF64 => (32, 40, 12),
+        };
+        let kind = reader.u16(16)?;
+        let machine = reader.u16(18)?;
+        let entry = reader.word(24)?;
+        let program_headers = reader.word(program_header_offset)?;
+        let flags = reader.u32(36 + shift)?;
+        let program_header_size = reader.u16(42 + shift)?;
+        let program_header_count = reader.u16(44 + shift)?;
+        let section_headers = reader.word(section_header_offset)?;
+        let section_header_size = reader.u16(46 + shift)?;
+        let section_count = reader.u16(48 + shift)?;
+        let section_names = reader.u16(50 + shift)?;
+
+        let segments = (0..u64::from(program_header_count))
+            .map(|i| {
+                let header = add_offset(program_headers, i * u64::from(program_header_size))?;
+                Segment::parse(&reader, header)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let headers = (0..u64::from(section_count))
+            .map(|i| add_offset(section_headers, i * u64::from(section_header_size)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut sections = headers
+            .iter()
+            .map(|header| Section::parse(&reader, *header))
+            .collect::<Result<Vec<_>, _>>()?;
+        // names are offsets into the section name string table
+        if let Some(names) = sections.get(usize::from(section_names)).map(|s| s.offset) {
+            for (section, header) in sections.iter_mut().zip(headers) {
+                section.name = reader.string(add_offset(names, u64::from(reader.u32(header)?))?)?;
+            }
+        }
+
+        let elf = ELF {
+            class,
+            kind,
+            machine,
+            flags,
+            entry,
+            segments,
+            sections,
+            bytes: bytes.to_vec(),
+        };
+        for segment in elf.segments.iter() {
+            elf.reader().bytes(segment.offset, segment.file_size)?;
+        }
+        Ok(elf)
+    }
+
+    pub(crate) fn reader(&self) -> Reader<'_> {
+        Reader::new(&self.bytes, self.class)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // The contents of the segment held in the file
+    pub fn segment_data(&self, segment: &Segment) -> &[u8] {
+        let start = segment.offset as usize;
+        &self.bytes[start..start + segment.file_size as usize]
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    // The contents of the section held in the file, empty for sections such
+    // as .bss which occupy no space in the file
+    pub fn section_data(&self, section: &Section) -> Result<&[u8], ELFError> {
+        if section.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        self.reader().bytes(section.offset, section.size)
+    }
+
+    pub fn loadable_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+    }
+
+    // Check the file is a RISC-V executable which can run on a hart with the
+    // given misa
+    pub fn validate(&self, misa: u32) -> Result<(), ELFError> {
+        if self.machine != EM_RISCV {
+            return Err(ELFError::UnsupportedMachine(self.machine));
+        }
+        if self.kind != ET_EXEC {
+            return Err(ELFError::UnsupportedType(self.kind));
+        }
+        let xlen = match misa >> 30 {
+            1 => Class::ELF32,
+            _ => Class::ELF64,
+        };
+        if self.class != xlen {
+            return Err(ELFError::UnsupportedClass);
+        }
+
+        let has_extension = |extension: u8| misa & (1 << (extension - b'A')) != 0;
+        let float_abi = match self.flags & EF_RISCV_FLOAT_ABI {
+            EF_RISCV_FLOAT_ABI_SOFT => true,
+            EF_RISCV_FLOAT_ABI_SINGLE => has_extension(b'F'),
+            EF_RISCV_FLOAT_ABI_DOUBLE => has_extension(b'D'),
+            _ => has_extension(b'Q'),
+        };
+        let compressed = self.flags & EF_RISCV_RVC == 0 || has_extension(b'C');
+        if !float_abi || !compressed {
+            return Err(ELFError::UnsupportedFlags(self.flags));
+        }
+        Ok(())
+    }
+}
+
+impl Section {
+    // The name is filled in once the section name table has been parsed
+    fn parse(reader: &Reader, header: u64) -> Result<Section, ELFError> {
+        let word = match reader.class {
+            Class::ELF32 => 4,
+            Class::ELF64 => 8,
+        };
+        let field = |offset| add_offset(header, offset);
+        Ok(Section {
+            name: String::new(),
+            kind: reader.u32(field(4)?)?,
+            flags: reader.word(field(8)?)?,
+            address: reader.word(field(8 + word)?)?,
+            offset: reader.word(field(8 + 2 * word)?)?,
+            size: reader.word(field(8 + 3 * word)?)?,
+            link: reader.u32(field(8 + 4 * word)?)?,
+            entry_size: reader.word(field(16 + 5 * word)?)?,
+        })
+    }
+}
+
+impl Segment {
+    fn parse(reader: &Reader, header: u64) -> Result<Segment, ELFError> {
+        let field = |offset| add_offset(header, offset);
+        match reader.class {
+            Class::ELF32 => Ok(Segment {
+                kind: reader.u32(header)?,
+                offset: reader.word(field(4)?)?,
+                virtual_address: reader.word(field(8)?)?,
+                physical_address: reader.word(field(12)?)?,
+                file_size: reader.word(field(16)?)?,
+                memory_size: reader.word(field(20)?)?,
+                flags: reader.u32(field(24)?)?,
+            }),
+            Class::ELF64 => Ok(Segment {
+                kind: reader.u32(header)?,
+                flags: reader.u32(field(4)?)?,
+                offset: reader.word(field(8)?)?,
+                virtual_address: reader.word(field(16)?)?,
+                physical_address: reader.word(field(24)?)?,
+                file_size: reader.word(field(32)?)?,
+                memory_size: reader.word(field(40)?)?,
+            }),
+        }
+    }
+}
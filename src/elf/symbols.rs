@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use super::{add_offset, Class, ELFError, ELF, SHT_SYMTAB};
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    // labels without a type, such as those defined in assembly
+    NoType,
+    Object,
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    pub kind: SymbolKind,
+}
+
+impl Symbol {
+    // The size is read from the file, so the end saturates rather than
+    // overflowing
+    fn contains(&self, address: u64) -> bool {
+        (self.address..self.address.saturating_add(self.size)).contains(&address)
+    }
+}
+
+// The named symbols of a program, searchable by name or by address
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolMap {
+    // sorted by address
+    symbols: Vec<Symbol>,
+    names: HashMap<String, usize>,
+}
+
+impl SymbolMap {
+    // The defined function, object and untyped symbols in the .symtab section
+    pub fn from_elf(elf: &ELF) -> Result<SymbolMap, ELFError> {
+        let mut map = SymbolMap::default();
+        let Some(table) = elf.sections.iter().find(|s| s.kind == SHT_SYMTAB) else {
+            return Ok(map);
+        };
+        let strings = elf
+            .sections
+            .get(table.link as usize)
+            .ok_or(ELFError::Truncated)?;
+
+        let reader = elf.reader();
+        let entry_size = match elf.class {
+            Class::ELF32 => 16,
+            Class::ELF64 => 24,
+        };
+        let count = elf.section_data(table)?.len() as u64 / entry_size;
+        // the first entry is always the null symbol
+        for i in 1..count {
+            let entry = table.offset + i * entry_size;
+            let (name, address, size, info, index) = match elf.class {
+                Class::ELF32 => (
+                    reader.u32(entry)?,
+                    reader.word(entry + 4)?,
+                    reader.word(entry + 8)?,
+                    reader.u8(entry + 12)?,
+                    reader.u16(entry + 14)?,
+                ),
+                Class::ELF64 => (
+                    reader.u32(entry)?,
+                    reader.word(entry + 8)?,
+                    reader.word(entry + 16)?,
+                    reader.u8(entry + 4)?,
+                    reader.u16(entry + 6)?,
+                ),
+            };
+            let kind = match info & 0xf {
+                STT_NOTYPE => SymbolKind::NoType,
+                STT_OBJECT => SymbolKind::Object,
+                STT_FUNC => SymbolKind::Function,
+                _ => continue,
+            };
+            if index == SHN_UNDEF || name == 0 {
+                continue;
+            }
+            let name = reader.string(add_offset(strings.offset, u64::from(name))?)?;
+            map.insert(Symbol {
+                name,
+                address,
+                size,
+                kind,
+            });
+        }
+        Ok(map)
+    }
+
+    // Add a symbol, replacing any existing symbol with the same name
+    pub fn insert(&mut self, symbol: Symbol) {
+        self.symbols.retain(|existing| existing.name != symbol.name);
+        let index = self
+            .symbols
+            .partition_point(|existing| existing.address <= symbol.address);
+        self.symbols.insert(index, symbol);
+        self.names = self
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(i, symbol)| (symbol.name.clone(), i))
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.names.get(name).map(|i| &self.symbols[*i])
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u64> {
+        self.lookup(name).map(|symbol| symbol.address)
+    }
+
+    // The symbol containing the address, with the offset of the address into
+    // it. Symbols without a size, such as assembly labels, extend up to the
+    // next symbol
+    pub fn resolve(&self, address: u64) -> Option<(&Symbol, u64)> {
+        let preceding = &self.symbols[..self.symbols.partition_point(|s| s.address <= address)];
+        let symbol = preceding
+            .iter()
+            .rev()
+            .find(|symbol| symbol.contains(address))
+            .or_else(|| preceding.last().filter(|symbol| symbol.size == 0))?;
+        Some((symbol, address - symbol.address))
+    }
+
+    // A readable name for the address, such as main+0x10
+    pub fn describe(&self, address: u64) -> String {
+        match self.resolve(address) {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}+{:#x}", symbol.name, offset),
+            None => format!("{:#x}", address),
+        }
+    }
+}
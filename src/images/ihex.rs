@@ -0,0 +1,117 @@
+use super::{big_endian, checksum, parse_hex, push_hex, ImageError};
+use crate::memory::Memory;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+const RECORD_LENGTH: usize = 16;
+
+// Write the data records of an Intel HEX file into memory, returning the start
+// address if the file has one
+pub fn load(memory: &mut Memory, text: &str) -> Result<Option<u32>, ImageError> {
+    let mut base = 0u32;
+    let mut start = None;
+    for (i, record) in text.lines().enumerate() {
+        let line = i + 1;
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let digits = record.strip_prefix(':').ok_or(ImageError::Syntax(line))?;
+        // length, two byte offset, kind, data and checksum
+        let bytes = parse_hex(digits, line)?;
+        if bytes.len() < 5 || bytes.len() != 5 + usize::from(bytes[0]) {
+            return Err(ImageError::Syntax(line));
+        }
+        if checksum(&bytes) != 0 {
+            return Err(ImageError::Checksum(line));
+        }
+
+        let kind = bytes[3];
+        let offset = big_endian(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        let expected_length = match kind {
+            END_OF_FILE => Some(0),
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => Some(2),
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => Some(4),
+            _ => None,
+        };
+        if expected_length.is_some_and(|length| length != data.len()) {
+            return Err(ImageError::Syntax(line));
+        }
+        match kind {
+            DATA => memory
+                .load_slice(base.wrapping_add(offset), data)
+                .map_err(|_| ImageError::OutOfRange(line))?,
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS => base = big_endian(data) << 4,
+            EXTENDED_LINEAR_ADDRESS => base = big_endian(data) << 16,
+            // the CS:IP of an 8086
+            START_SEGMENT_ADDRESS => {
+                start = Some((big_endian(&data[..2]) << 4) + big_endian(&data[2..]))
+            }
+            START_LINEAR_ADDRESS => start = Some(big_endian(data)),
+            _ => return Err(ImageError::UnsupportedRecord(line)),
+        }
+    }
+    Ok(start)
+}
+
+// Export a range of memory as an Intel HEX file, using extended linear address
+// records to reach beyond the first 64 KiB
+#[allow(clippy::result_unit_err)]
+pub fn write(
+    memory: &Memory,
+    address: u32,
+    length: usize,
+    start: Option<u32>,
+) -> Result<String, ()> {
+    let data = memory.read_range(address, length)?;
+    let mut text = String::new();
+    let mut base = 0;
+    let mut offset = 0;
+    while offset < data.len() {
+        let current = address.wrapping_add(offset as u32);
+        if current >> 16 != base {
+            base = current >> 16;
+            push_record(
+                &mut text,
+                EXTENDED_LINEAR_ADDRESS,
+                0,
+                &(base as u16).to_be_bytes(),
+            );
+        }
+        // a record cannot cross into the next 64 KiB
+        let length = RECORD_LENGTH
+            .min(data.len() - offset)
+            .min(0x1_0000 - (current & 0xffff) as usize);
+        push_record(
+            &mut text,
+            DATA,
+            current as u16,
+            &data[offset..offset + length],
+        );
+        offset += length;
+    }
+    if let Some(start) = start {
+        push_record(&mut text, START_LINEAR_ADDRESS, 0, &start.to_be_bytes());
+    }
+    push_record(&mut text, END_OF_FILE, 0, &[]);
+    Ok(text)
+}
+
+fn push_record(text: &mut String, kind: u8, offset: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    // the bytes of a record sum to zero
+    bytes.push(checksum(&bytes).wrapping_neg());
+    text.push(':');
+    push_hex(text, &bytes);
+    text.push('\n');
+}
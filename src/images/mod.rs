@@ -0,0 +1,47 @@
+pub mod ihex;
+pub mod readmem;
+pub mod srec;
+
+// The line numbers start at one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    // the line is not a well formed record
+    Syntax(usize),
+    Checksum(usize),
+    UnsupportedRecord(usize),
+    // the record writes outside of memory
+    OutOfRange(usize),
+}
+
+// Decode a string of hexadecimal digit pairs
+pub(crate) fn parse_hex(digits: &str, line: usize) -> Result<Vec<u8>, ImageError> {
+    if !digits.len().is_multiple_of(2) {
+        return Err(ImageError::Syntax(line));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(ImageError::Syntax(line))
+        })
+        .collect()
+}
+
+pub(crate) fn push_hex(text: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        text.push_str(&format!("{:02X}", byte));
+    }
+}
+
+// The contents of records which are read from the most significant byte
+pub(crate) fn big_endian(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u32::from(*byte))
+}
+
+pub(crate) fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
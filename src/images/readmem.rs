@@ -0,0 +1,156 @@
+use super::ImageError;
+use crate::bus::Width;
+use crate::memory::{Endianness, Memory};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    // $readmemh
+    Hex,
+    // $readmemb
+    Binary,
+}
+
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Hex => 16,
+            Radix::Binary => 2,
+        }
+    }
+
+    fn digits(self, width: Width) -> usize {
+        match self {
+            Radix::Hex => 2 * width.bytes() as usize,
+            Radix::Binary => 8 * width.bytes() as usize,
+        }
+    }
+}
+
+// The layout of a Verilog memory, as an array of words each stored in main
+// memory in the given byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub radix: Radix,
+    pub width: Width,
+    pub endianness: Endianness,
+}
+
+impl Format {
+    pub fn hex(width: Width, endianness: Endianness) -> Self {
+        Format {
+            radix: Radix::Hex,
+            width,
+            endianness,
+        }
+    }
+
+    pub fn binary(width: Width, endianness: Endianness) -> Self {
+        Format {
+            radix: Radix::Binary,
+            width,
+            endianness,
+        }
+    }
+
+    fn encode(self, value: u64) -> Vec<u8> {
+        let mut bytes = value.to_le_bytes()[..self.width.bytes() as usize].to_vec();
+        if self.endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    fn decode(self, bytes: &[u8]) -> u64 {
+        let mut value = [0; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        if self.endianness == Endianness::Big {
+            value[..bytes.len()].reverse();
+        }
+        u64::from_le_bytes(value)
+    }
+}
+
+// Write the words of a $readmemh or $readmemb file into memory, where word zero
+// is stored at the base address. An @ followed by a word address moves to that
+// word, and words which are not given are left unchanged
+pub fn load(memory: &mut Memory, text: &str, base: u32, format: Format) -> Result<(), ImageError> {
+    let mut index = 0u64;
+    let mut in_comment = false;
+    for (i, mut line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let mut code = String::new();
+        // strip both // and /* */ comments, which may span lines
+        while !line.is_empty() {
+            if in_comment {
+                match line.find("*/") {
+                    Some(end) => {
+                        in_comment = false;
+                        line = &line[end + 2..];
+                    }
+                    None => line = "",
+                }
+            } else {
+                let start = [line.find("//"), line.find("/*")]
+                    .into_iter()
+                    .flatten()
+                    .min();
+                match start {
+                    Some(start) => {
+                        code.push_str(&line[..start]);
+                        code.push(' ');
+                        in_comment = line[start..].starts_with("/*");
+                        line = if in_comment { &line[start + 2..] } else { "" };
+                    }
+                    None => {
+                        code.push_str(line);
+                        line = "";
+                    }
+                }
+            }
+        }
+
+        for token in code.split_whitespace() {
+            let token = token.replace('_', "");
+            if let Some(address) = token.strip_prefix('@') {
+                index = u64::from_str_radix(address, 16)
+                    .map_err(|_| ImageError::Syntax(line_number))?;
+                continue;
+            }
+            let value = u64::from_str_radix(&token, format.radix.base())
+                .ok()
+                .filter(|value| *value <= format.width.mask())
+                .ok_or(ImageError::Syntax(line_number))?;
+            let address = index
+                .checked_mul(u64::from(format.width.bytes()))
+                .and_then(|offset| offset.checked_add(u64::from(base)))
+                .and_then(|address| u32::try_from(address).ok())
+                .ok_or(ImageError::OutOfRange(line_number))?;
+            memory
+                .load_slice(address, &format.encode(value))
+                .map_err(|_| ImageError::OutOfRange(line_number))?;
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+// Export a range of memory in the format read by $readmemh or $readmemb, one
+// zero padded word per line. The length must be a whole number of words
+#[allow(clippy::result_unit_err)]
+pub fn write(memory: &Memory, base: u32, length: usize, format: Format) -> Result<String, ()> {
+    let word = format.width.bytes() as usize;
+    if !length.is_multiple_of(word) {
+        return Err(());
+    }
+    let digits = format.radix.digits(format.width);
+    let mut text = String::new();
+    for bytes in memory.read_range(base, length)?.chunks(word) {
+        let value = format.decode(bytes);
+        let line = match format.radix {
+            Radix::Hex => format!("{:0digits$x}\n", value),
+            Radix::Binary => format!("{:0digits$b}\n", value),
+        };
+        text.push_str(&line);
+    }
+    Ok(text)
+}
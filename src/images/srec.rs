@@ -0,0 +1,112 @@
+use super::{big_endian, checksum, parse_hex, push_hex, ImageError};
+use crate::memory::Memory;
+
+const RECORD_LENGTH: usize = 16;
+
+// Write the data records of a Motorola S-record file into memory, returning
+// the start address given by the termination record
+pub fn load(memory: &mut Memory, text: &str) -> Result<Option<u32>, ImageError> {
+    let mut data_records = 0;
+    for (i, record) in text.lines().enumerate() {
+        let line = i + 1;
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let mut characters = record
+            .strip_prefix('S')
+            .ok_or(ImageError::Syntax(line))?
+            .chars();
+        let kind = characters.next().ok_or(ImageError::Syntax(line))?;
+        // count, address, data and checksum, where the count includes every
+        // byte which follows it
+        let bytes = parse_hex(characters.as_str(), line)?;
+        if bytes.is_empty() || bytes.len() != 1 + usize::from(bytes[0]) {
+            return Err(ImageError::Syntax(line));
+        }
+        if checksum(&bytes) != 0xff {
+            return Err(ImageError::Checksum(line));
+        }
+
+        let address_length = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(ImageError::UnsupportedRecord(line)),
+        };
+        if bytes.len() < 2 + address_length {
+            return Err(ImageError::Syntax(line));
+        }
+        let address = big_endian(&bytes[1..1 + address_length]);
+        let data = &bytes[1 + address_length..bytes.len() - 1];
+        match kind {
+            // the header holds a description of the file
+            '0' => {}
+            '1' | '2' | '3' => {
+                memory
+                    .load_slice(address, data)
+                    .map_err(|_| ImageError::OutOfRange(line))?;
+                data_records += 1;
+            }
+            // the number of data records so far
+            '5' | '6' if address != data_records => return Err(ImageError::Syntax(line)),
+            '5' | '6' => {}
+            _ => return Ok(Some(address)),
+        }
+    }
+    Ok(None)
+}
+
+// Export a range of memory as a Motorola S-record file, using the shortest
+// addresses able to reach the end of the range and the start address
+#[allow(clippy::result_unit_err)]
+pub fn write(
+    memory: &Memory,
+    address: u32,
+    length: usize,
+    start: Option<u32>,
+) -> Result<String, ()> {
+    let data = memory.read_range(address, length)?;
+    let end = (u64::from(address) + length as u64)
+        .saturating_sub(1)
+        .max(start.unwrap_or_default().into());
+    let (data_kind, termination_kind, address_length) = match end {
+        0..=0xffff => ('1', '9', 2),
+        0x1_0000..=0xff_ffff => ('2', '8', 3),
+        _ => ('3', '7', 4),
+    };
+
+    let mut text = String::new();
+    push_record(&mut text, '0', 2, 0, &[]);
+    let mut records = 0;
+    for (i, chunk) in data.chunks(RECORD_LENGTH).enumerate() {
+        let record_address = address.wrapping_add((i * RECORD_LENGTH) as u32);
+        push_record(&mut text, data_kind, address_length, record_address, chunk);
+        records += 1;
+    }
+    if records <= 0xffff {
+        push_record(&mut text, '5', 2, records, &[]);
+    } else {
+        push_record(&mut text, '6', 3, records, &[]);
+    }
+    push_record(
+        &mut text,
+        termination_kind,
+        address_length,
+        start.unwrap_or_default(),
+        &[],
+    );
+    Ok(text)
+}
+
+fn push_record(text: &mut String, kind: char, address_length: usize, address: u32, data: &[u8]) {
+    let mut bytes = vec![(address_length + data.len() + 1) as u8];
+    bytes.extend(&address.to_be_bytes()[4 - address_length..]);
+    bytes.extend(data);
+    // the ones' complement of the sum of the preceding bytes
+    bytes.push(!checksum(&bytes));
+    text.push('S');
+    text.push(kind);
+    push_hex(text, &bytes);
+    text.push('\n');
+}
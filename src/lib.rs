@@ -1,14 +1,63 @@
+pub mod assembler;
+pub mod bus;
+pub mod csr;
+pub mod devices;
+pub mod elf;
+pub mod images;
+pub mod memory;
+pub mod pma;
+pub mod pmp;
 pub mod risc_machine;
 pub mod riscv_instruction;
+pub mod snapshot;
+pub mod trap;
 
 #[cfg(test)]
 mod tests {
 
     use rand::Rng;
-    use ux::{u20, u5};
+    use ux::{u12, u20, u5};
 
-    use crate::risc_machine::{CPUInstruction, Memory, RISCMachine, Registry};
+    use crate::assembler::{self, AssemblerError, AssemblerErrorKind};
+    use crate::bus::{Device, Width, ROM};
+    use crate::csr::{self, PrivilegeMode};
+    use crate::devices::block::{
+        BlockDevice, BLOCK_BASE, BLOCK_COMMAND_READ, BLOCK_COMMAND_WRITE, BLOCK_SIZE,
+        BLOCK_STATUS_DONE, BLOCK_STATUS_ERROR, SECTOR_SIZE,
+    };
+    use crate::devices::clic::{CLIC, CLICINTATTR_EDGE, CLICINTATTR_SHV};
+    use crate::devices::clint::{CLINT, CLINT_BASE};
+    use crate::devices::framebuffer::{Framebuffer, PixelFormat, FRAMEBUFFER_BASE};
+    use crate::devices::gpio::{GPIO, GPIO_BASE, GPIO_PINS, GPIO_SIZE};
+    use crate::devices::htif::{HTIF, HTIF_SIZE};
+    use crate::devices::plic::{PLIC, PLIC_BASE};
+    use crate::devices::test_finisher::{
+        TestFinisher, FINISHER_FAIL, FINISHER_PASS, TEST_FINISHER_BASE, TEST_FINISHER_SIZE,
+    };
+    use crate::devices::uart::{IER_ERBFI, LSR_DR, LSR_THRE, UART, UART_BASE, UART_SIZE};
+    use crate::devices::virtio::console::{Console, VIRTIO_ID_CONSOLE};
+    use crate::devices::virtio::queue::{Queue, QUEUE_SIZE_MAX};
+    use crate::devices::virtio::{
+        VirtioMMIO, INTERRUPT_USED_BUFFER, STATUS_ACKNOWLEDGE, STATUS_DEVICE_NEEDS_RESET,
+        STATUS_DRIVER, STATUS_DRIVER_OK, STATUS_FEATURES_OK, VIRTIO_BASE, VIRTIO_MAGIC,
+        VIRTIO_SIZE,
+    };
+    use crate::elf::coredump::{ET_CORE, NT_PRSTATUS, PRSTATUS_REGISTERS, PT_NOTE, SIGILL};
+    use crate::elf::dwarf::{LineTable, Location};
+    use crate::elf::symbols::{SymbolKind, SymbolMap};
+    use crate::elf::{
+        Class, ELFError, EF_RISCV_FLOAT_ABI_DOUBLE, EF_RISCV_RVC, ELF, PT_LOAD, SHT_PROGBITS,
+        SHT_STRTAB, SHT_SYMTAB,
+    };
+    use crate::images::readmem::{self, Format};
+    use crate::images::{ihex, srec, ImageError};
+    use crate::memory::Endianness;
+    use crate::pma::{PMA, PMA_DEVICE, PMA_EXECUTE_ONLY, PMA_RAM, PMA_ROM};
+    use crate::pmp::{AccessType, PMP, PMP_L, PMP_R, PMP_W, PMP_X};
+    use crate::risc_machine::{CPUInstruction, InterruptModel, Memory, RISCMachine, Registry};
     use crate::riscv_instruction::{RISCVImmediate, RISCVInstruction};
+    use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError, SNAPSHOT_VERSION};
+    use crate::trap::{Exception, Interrupt};
 
     #[test]
     fn it_correctly_encodes_immediate() {
@@ -24,6 +73,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_correctly_encodes_u_immediates() {
+        // the top bit of a U immediate is bit 31 of the instruction
+        let imm = (rand::thread_rng().gen::<u32>() & 0xfffff000) | (1 << 31);
+        let a: u32 = 0.with_immediate(imm, &RISCVImmediate::U);
+        assert_eq!(imm, a);
+        assert_eq!(imm, a.immediate(&RISCVImmediate::U));
+    }
+
     #[test]
     fn it_correctly_decodes_add() -> Result<(), ()> {
         let add = CPUInstruction::ADD {
@@ -145,7 +203,8 @@ mod tests {
         let mut machine = RISCMachine {
             memory,
             registry,
-            program_counter: u20::new(0),
+            program_counter: 0,
+            ..Default::default()
         };
         machine.tick()?;
         assert_eq!(value_to_save, machine.memory.get_aligned(index_to_save));
@@ -170,7 +229,8 @@ mod tests {
         let mut machine = RISCMachine {
             memory,
             registry,
-            program_counter: u20::new(0),
+            program_counter: 0,
+            ..Default::default()
         };
         machine.tick()?;
         assert_eq!(add1.saturating_add(add2), machine.registry.get(rd));
@@ -195,7 +255,8 @@ mod tests {
         let mut machine = RISCMachine {
             memory,
             registry,
-            program_counter: u20::new(0),
+            program_counter: 0,
+            ..Default::default()
         };
         machine.tick()?;
         assert_eq!(lhs.saturating_sub(rhs), machine.registry.get(rd));
@@ -220,11 +281,12 @@ mod tests {
         let mut machine = RISCMachine {
             memory,
             registry: Registry::default(),
-            program_counter: start,
+            program_counter: start.into(),
+            ..Default::default()
         };
 
         machine.tick()?;
-        assert_eq!(end, machine.program_counter);
+        assert_eq!(u32::from(end), machine.program_counter);
         assert_eq!(u32::from(start) + 4, machine.registry.get(rd));
         Ok(())
     }
@@ -349,7 +411,7 @@ mod tests {
             u20::new(9 * 4),
             CPUInstruction::JAL {
                 rd: x0,
-                imm: -((5 * 4) as i32) as u32,
+                imm: -(5 * 4) as u32,
             }
             .into(),
         );
@@ -361,8 +423,3542 @@ mod tests {
 
         let mut machine = RISCMachine::default();
         machine.load_memory(memory);
-        machine.run(&|pc| -> bool { pc == halt_position })?;
+        machine.run(&|pc| -> bool { pc == u32::from(halt_position) })?;
         assert_eq!(fibonacci(n), machine.registry.get(x5));
         Ok(())
     }
+
+    #[test]
+    fn it_can_assemble_instructions() -> Result<(), ()> {
+        let index = rand::thread_rng().gen_range(0..32u8);
+        let source = format!(
+            "start:  add x{}, a0, sp
+                    sub s1, zero, t6
+                    lw s11, -4(fp)
+                    lbu a1, (a2)
+                    sh t3, 0x7ff(gp)
+                    jal start
+            inner:  jalr t0, 8(ra)   # calls
+                    JALR ra
+                    bne a0, a1, start + 4
+                    beq x1, x2, -8
+                    csrrs a0, mstatus, zero
+                    csrrw zero, 0x340, a0
+                    mret
+            end:    wfi",
+            index
+        );
+        let program = assembler::assemble(&source, 0x100).map_err(|_| ())?;
+        let (x, ra) = (u5::new, u5::new(1));
+        let expected = [
+            CPUInstruction::ADD {
+                rd: x(index),
+                rs1: x(10),
+                rs2: x(2),
+            },
+            CPUInstruction::SUB {
+                rd: x(9),
+                rs1: x(0),
+                rs2: x(31),
+            },
+            CPUInstruction::LW {
+                rd: x(27),
+                rs1: x(8),
+                imm: -4i32 as u32,
+            },
+            CPUInstruction::LBU {
+                rd: x(11),
+                rs1: x(12),
+                imm: 0,
+            },
+            CPUInstruction::SH {
+                rs1: x(3),
+                rs2: x(28),
+                imm: 0x7ff,
+            },
+            CPUInstruction::JAL {
+                rd: ra,
+                imm: -20i32 as u32,
+            },
+            CPUInstruction::JALR {
+                rd: x(5),
+                rs1: ra,
+                imm: 8,
+            },
+            CPUInstruction::JALR {
+                rd: ra,
+                rs1: ra,
+                imm: 0,
+            },
+            CPUInstruction::BNE {
+                rs1: x(10),
+                rs2: x(11),
+                imm: -28i32 as u32,
+            },
+            CPUInstruction::BEQ {
+                rs1: ra,
+                rs2: x(2),
+                imm: -8i32 as u32,
+            },
+            CPUInstruction::CSRRS {
+                rd: x(10),
+                rs1: x(0),
+                csr: u12::new(csr::MSTATUS),
+            },
+            CPUInstruction::CSRRW {
+                rd: x(0),
+                rs1: x(10),
+                csr: u12::new(csr::MSCRATCH),
+            },
+            CPUInstruction::MRET,
+            CPUInstruction::WFI,
+        ]
+        .map(u32::from);
+        assert_eq!(expected.to_vec(), program.words());
+        assert_eq!(Some(0x118), program.symbols.address_of("inner"));
+        assert_eq!(Some(0x134), program.symbols.address_of("end"));
+
+        let mut memory = Memory::default();
+        program.load(&mut memory)?;
+        let text = program.section(".text").ok_or(())?;
+        assert_eq!(0x100, text.address);
+        assert_eq!(
+            Ok(text.data.clone()),
+            memory.read_range(0x100, 4 * expected.len())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_run_assembled_programs() -> Result<(), ()> {
+        let source = "
+            # fib(n) is in t0 and fib(n + 1) in t1, with t2 counting down from n
+                lw t0, 44(zero)
+                lw t1, 44(zero)
+                lw t2, 48(zero)
+                lw ra, 44(zero)
+            loop:
+                beq zero, t2, halt
+                add tp, t0, t1
+                add t0, zero, t1
+                add t1, zero, tp
+                sub t2, t2, ra
+                jal zero, loop
+            halt:
+                wfi
+        ";
+        let program = assembler::assemble(source, 0).map_err(|_| ())?;
+        assert_eq!(11, program.words().len());
+
+        let mut machine = RISCMachine::default();
+        machine.load_program(&program)?;
+        let n = rand::thread_rng().gen_range(0..10);
+        machine.memory.set_four_byte(u20::new(44), 1);
+        machine.memory.set_four_byte(u20::new(48), n);
+        machine.run_until_symbol("halt")?;
+        assert_eq!(fibonacci(n), machine.registry.get(u5::new(5)));
+        assert_eq!("loop+0x4", machine.describe(0x14));
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_assembler_errors() -> Result<(), ()> {
+        for (source, line, column, kind) in [
+            (
+                "  add a0, a1",
+                1,
+                3,
+                AssemblerErrorKind::OperandCount {
+                    expected: 3,
+                    found: 2,
+                },
+            ),
+            (
+                "wfi\n\tfrob a0",
+                2,
+                2,
+                AssemblerErrorKind::UnknownMnemonic("frob".to_string()),
+            ),
+            (
+                "add a0, a1, x32",
+                1,
+                13,
+                AssemblerErrorKind::UnknownRegister("x32".to_string()),
+            ),
+            (
+                "lw a0, 2048(sp)",
+                1,
+                8,
+                AssemblerErrorKind::OutOfRange(2048),
+            ),
+            (
+                "beq a0, a1, missing",
+                1,
+                13,
+                AssemblerErrorKind::UndefinedSymbol("missing".to_string()),
+            ),
+            (
+                "a: wfi\n  a: wfi",
+                2,
+                3,
+                AssemblerErrorKind::DuplicateSymbol("a".to_string()),
+            ),
+            ("jal x0, 3", 1, 9, AssemblerErrorKind::Misaligned(3)),
+            (
+                "lw a0, sp",
+                1,
+                8,
+                AssemblerErrorKind::InvalidOperand("sp".to_string()),
+            ),
+            (
+                "sw a0, 4 +(sp)",
+                1,
+                8,
+                AssemblerErrorKind::InvalidOperand("4 +".to_string()),
+            ),
+            (
+                "csrrw a0, mfoo, a1",
+                1,
+                11,
+                AssemblerErrorKind::UnknownCSR("mfoo".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                Err(AssemblerError { line, column, kind }),
+                assembler::assemble(source, 0).map(|program| program.words())
+            );
+        }
+        let error = assembler::assemble("\n\n   jal a0, 4096 + 1", 0).err();
+        assert_eq!(
+            Some("3:12: Misaligned(4097)".to_string()),
+            error.map(|error| error.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_decodes_base_integer() -> Result<(), ()> {
+        let rd = u5::new(rand::thread_rng().gen_range(0..=u5::MAX.into()));
+        let rs1 = u5::new(rand::thread_rng().gen_range(0..=u5::MAX.into()));
+        let rs2 = u5::new(rand::thread_rng().gen_range(0..=u5::MAX.into()));
+        let imm = rand::thread_rng().gen_range(-2048..2048i32) as u32;
+        let upper = rand::random::<u32>() & 0xffff_f000;
+        let offset = (rand::thread_rng().gen_range(-2048..2048i32) * 2) as u32;
+        for instruction in [
+            CPUInstruction::SLTU { rd, rs1, rs2 },
+            CPUInstruction::ADDI { rd, rs1, imm },
+            CPUInstruction::SLTIU { rd, rs1, imm },
+            CPUInstruction::XORI { rd, rs1, imm },
+            CPUInstruction::LUI { rd, imm: upper },
+            CPUInstruction::AUIPC { rd, imm: upper },
+            CPUInstruction::BLT {
+                rs1,
+                rs2,
+                imm: offset,
+            },
+            CPUInstruction::BGE {
+                rs1,
+                rs2,
+                imm: offset,
+            },
+        ] {
+            let encoded: u32 = instruction.into();
+            let decoded: CPUInstruction = encoded.try_into()?;
+            assert_eq!(instruction, decoded);
+        }
+        let (a0, a1) = (u5::new(10), u5::new(11));
+        assert_eq!(
+            0x12345537,
+            u32::from(CPUInstruction::LUI {
+                rd: a0,
+                imm: 0x12345000
+            })
+        );
+        assert_eq!(
+            0xfff50513,
+            u32::from(CPUInstruction::ADDI {
+                rd: a0,
+                rs1: a0,
+                imm: u32::MAX
+            })
+        );
+        assert_eq!(
+            0xfeb55ce3,
+            u32::from(CPUInstruction::BGE {
+                rs1: a0,
+                rs2: a1,
+                imm: -8i32 as u32
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_assemble_pseudo_instructions() -> Result<(), ()> {
+        let source = "
+            start:
+                li a0, 0x12345678
+                li a1, -1
+                li a2, 0x7ff
+                li a3, 0x800
+                li a4, 0x12345000
+                li a5, -2049
+                la t0, start
+                mv a0, a1
+                not a0, a1
+                neg a0, a1
+                seqz a0, a1
+                snez a0, a1
+                j start
+                jr t0
+                call start
+                tail start
+                ret
+                nop
+                beqz a0, start
+                bnez a0, start
+                bgt a0, a1, start
+                ble a0, a1, start
+        ";
+        // the encodings produced by the GNU assembler
+        let expected = [
+            0x12345537, 0x67850513, 0xfff00593, 0x7ff00613, 0x000016b7, 0x80068693, 0x12345737,
+            0xfffff7b7, 0x7ff78793, 0x00000297, 0xfdc28293, 0x00058513, 0xfff5c513, 0x40b00533,
+            0x0015b513, 0x00b03533, 0xfc1ff06f, 0x00028067, 0x00000097, 0xfb8080e7, 0x00000317,
+            0xfb030067, 0x00008067, 0x00000013, 0xfa0500e3, 0xf8051ee3, 0xf8a5cce3, 0xf8a5dae3,
+        ];
+        let program = assembler::assemble(source, 0).map_err(|_| ())?;
+        assert_eq!(expected.to_vec(), program.words());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_assemble_sections_and_directives() -> Result<(), ()> {
+        let source = r#"
+                .equ COUNT, 5
+                .equ BIG, 0x12345678
+                .globl _start
+                .text
+            helper:
+                not a0, a0
+                ret
+                .align 4
+            _start:
+                la t0, values
+                li t1, COUNT
+                li s0, 0
+            loop:
+                lw t2, 0(t0)
+                add s0, s0, t2
+                addi t0, t0, 4
+                addi t1, t1, -1
+                bnez t1, loop
+                la t3, total
+                sw s0, (t3)
+                li a0, BIG
+                call helper
+                lui t4, %hi(inverted)
+                sw a0, %lo(inverted)(t4)
+                li a1, -3
+                li a2, 2
+                ble a1, a2, signed    # compared as signed
+                li a3, 1
+            signed:
+                seqz a4, zero
+                snez a5, a2
+                sltu a6, a2, a1
+            done:
+                wfi
+
+                .data
+            values:
+                .word 1, 2, 3, 4, COUNT + 5
+            message:
+                .asciz "hi\n"
+                .align 2
+            pointer:
+                .word message
+                .half 0xbeef, -1
+                .byte 7
+                .section .rodata
+            greeting:
+                .string "a,#b"
+                .bss
+            total:
+                .space 4
+            inverted:
+                .zero 4
+        "#;
+        let program = assembler::assemble(source, 0x1000).map_err(|_| ())?;
+        assert_eq!(
+            vec![
+                (".text", 0x1000, 0x78),
+                (".data", 0x1078, 0x21),
+                (".rodata", 0x109c, 5),
+                (".bss", 0x10a4, 8)
+            ],
+            program
+                .sections
+                .iter()
+                .map(|section| (section.name.as_str(), section.address, section.data.len()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0x1010, program.entry);
+        // the alignment of .text is padded with nops
+        assert_eq!([0x13, 0x13], program.words()[2..4]);
+        assert_eq!(Some(0x108c), program.symbols.address_of("message"));
+        assert_eq!(
+            Some(&b"a,#b\0"[..]),
+            program.section(".rodata").map(|s| &s.data[..])
+        );
+
+        let mut machine = RISCMachine::default();
+        machine.load_program(&program)?;
+        assert_eq!(0x1010, machine.program_counter);
+        machine.run_until_symbol("done")?;
+        assert_eq!(Ok(20), machine.read_symbol("total", Width::Word));
+        assert_eq!(Ok(0xedcba987), machine.read_symbol("inverted", Width::Word));
+        assert_eq!(Ok(0x108c), machine.read_symbol("pointer", Width::Word));
+        assert_eq!(Ok(b"hi\n\0".to_vec()), machine.memory.read_range(0x108c, 4));
+        assert_eq!(
+            Ok(vec![0xef, 0xbe, 0xff, 0xff, 7]),
+            machine.memory.read_range(0x1094, 5)
+        );
+        assert_eq!(
+            [0, 1, 1, 1],
+            [13, 14, 15, 16].map(|i| machine.registry.get(u5::new(i)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_directive_errors() -> Result<(), ()> {
+        for (source, line, column, kind) in [
+            (".bss\n  .word 1", 2, 3, AssemblerErrorKind::DataInBss),
+            (
+                ".bss\nx: addi a0, a0, 1",
+                2,
+                4,
+                AssemblerErrorKind::DataInBss,
+            ),
+            (".byte 256", 1, 7, AssemblerErrorKind::OutOfRange(256)),
+            (".half -32769", 1, 7, AssemblerErrorKind::OutOfRange(-32769)),
+            (
+                ".string hello",
+                1,
+                9,
+                AssemblerErrorKind::InvalidOperand("hello".to_string()),
+            ),
+            (
+                ".globl main",
+                1,
+                8,
+                AssemblerErrorKind::UndefinedSymbol("main".to_string()),
+            ),
+            (
+                ".frob 1",
+                1,
+                1,
+                AssemblerErrorKind::UnknownDirective(".frob".to_string()),
+            ),
+            (
+                ".equ X, 1\nX: wfi",
+                2,
+                1,
+                AssemblerErrorKind::DuplicateSymbol("X".to_string()),
+            ),
+            (
+                ".equ A, B\n.equ B, 1",
+                1,
+                9,
+                AssemblerErrorKind::UndefinedSymbol("B".to_string()),
+            ),
+            (
+                "li a0, 0xffffffff + 1",
+                1,
+                8,
+                AssemblerErrorKind::OutOfRange(1 << 32),
+            ),
+            (".align 32", 1, 8, AssemblerErrorKind::OutOfRange(32)),
+            (
+                "ret a0",
+                1,
+                1,
+                AssemblerErrorKind::OperandCount {
+                    expected: 0,
+                    found: 1,
+                },
+            ),
+        ] {
+            assert_eq!(
+                Err(AssemblerError { line, column, kind }),
+                assembler::assemble(source, 0).map(|program| program.words())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_decodes_system() -> Result<(), ()> {
+        let rd = u5::new(rand::thread_rng().gen_range(1..=u5::MAX.into()));
+        let rs1 = u5::new(rand::thread_rng().gen_range(1..=u5::MAX.into()));
+        let csr = u12::new(rand::thread_rng().gen_range(0..=u12::MAX.into()));
+        for instruction in [
+            CPUInstruction::CSRRW { rd, rs1, csr },
+            CPUInstruction::CSRRS { rd, rs1, csr },
+            CPUInstruction::CSRRC { rd, rs1, csr },
+            CPUInstruction::MRET,
+        ] {
+            let encoded: u32 = instruction.into();
+            let decoded: CPUInstruction = encoded.try_into()?;
+            assert_eq!(instruction, decoded);
+        }
+        assert_eq!(0x30200073, u32::from(CPUInstruction::MRET));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_swap_csr() -> Result<(), ()> {
+        let value: u32 = rand::thread_rng().gen();
+        let previous: u32 = rand::thread_rng().gen();
+        let rd = u5::new(1);
+        let rs1 = u5::new(2);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::CSRRW {
+                rd,
+                rs1,
+                csr: u12::new(csr::MSCRATCH),
+            }
+            .into(),
+        );
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(rs1, value);
+        machine.csr.mscratch = previous;
+
+        machine.tick()?;
+        assert_eq!(value, machine.csr.mscratch);
+        assert_eq!(previous, machine.registry.get(rd));
+        assert_eq!(4, machine.program_counter);
+        Ok(())
+    }
+
+    #[test]
+    fn it_traps_csr_access_from_user_mode() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let instruction = CPUInstruction::CSRRS {
+            rd: u5::new(1),
+            rs1: u5::new(0),
+            csr: u12::new(csr::MSTATUS),
+        };
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), instruction.into());
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        // give user mode access to all of memory
+        machine.csr.pmp.set_address(0, u32::MAX);
+        machine
+            .csr
+            .pmp
+            .set_config(0, PMP_R | PMP_W | PMP_X | (3 << 3));
+        machine.privilege_mode = PrivilegeMode::User;
+
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!(PrivilegeMode::Machine, machine.privilege_mode);
+        assert_eq!(Exception::IllegalInstruction.code(), machine.csr.mcause);
+        assert_eq!(u32::from(instruction), machine.csr.mtval);
+        assert_eq!(PrivilegeMode::User, machine.csr.previous_privilege());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_mret_to_user_mode() -> Result<(), ()> {
+        let user_entry = u20::new(0x200);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::MRET.into());
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mepc = user_entry.into();
+        machine.csr.mstatus = csr::MSTATUS_MPIE;
+
+        machine.tick()?;
+        assert_eq!(u32::from(user_entry), machine.program_counter);
+        assert_eq!(PrivilegeMode::User, machine.privilege_mode);
+        assert_eq!(csr::MSTATUS_MIE | csr::MSTATUS_MPIE, machine.csr.mstatus);
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_matches_pmp_regions() {
+        let mut pmp = PMP::default();
+        // TOR [0x1000, 0x2000) read only
+        pmp.set_address(0, 0x1000 >> 2);
+        pmp.set_address(1, 0x2000 >> 2);
+        pmp.set_config(1, PMP_R | (1 << 3));
+        // NA4 at 0x3000 read write
+        pmp.set_address(2, 0x3000 >> 2);
+        pmp.set_config(2, PMP_R | PMP_W | (2 << 3));
+        // NAPOT [0x0, 0x10000) execute, lower priority than the TOR entry
+        pmp.set_address(3, (0x10000 >> 3) - 1);
+        pmp.set_config(3, PMP_X | (3 << 3));
+
+        let user = PrivilegeMode::User;
+        assert!(pmp.check(0x1000, 4, AccessType::Read, user));
+        assert!(!pmp.check(0x1ffc, 4, AccessType::Write, user));
+        assert!(!pmp.check(0x1ffe, 4, AccessType::Read, user));
+        assert!(pmp.check(0x3000, 4, AccessType::Write, user));
+        assert!(!pmp.check(0x3004, 4, AccessType::Write, user));
+        assert!(pmp.check(0x4000, 4, AccessType::Execute, user));
+        assert!(!pmp.check(0x1000, 4, AccessType::Execute, user));
+        assert!(!pmp.check(0x10000, 4, AccessType::Execute, user));
+        assert!(pmp.check(0x10000, 4, AccessType::Write, PrivilegeMode::Machine));
+        assert!(pmp.check(0x1000, 4, AccessType::Write, PrivilegeMode::Machine));
+    }
+
+    #[test]
+    fn it_respects_pmp_lock() {
+        let mut pmp = PMP::default();
+        pmp.set_address(0, 0x2000 >> 2);
+        pmp.set_config(0, PMP_R | PMP_L | (1 << 3));
+
+        pmp.set_config(0, PMP_R | PMP_W | PMP_X | (1 << 3));
+        pmp.set_address(0, 0x4000 >> 2);
+        assert_eq!(PMP_R | PMP_L | (1 << 3), pmp.config(0));
+        assert_eq!(0x2000 >> 2, pmp.address(0));
+
+        assert!(pmp.check(0x0, 4, AccessType::Read, PrivilegeMode::Machine));
+        assert!(!pmp.check(0x0, 4, AccessType::Write, PrivilegeMode::Machine));
+        assert!(pmp.check(0x2000, 4, AccessType::Write, PrivilegeMode::Machine));
+    }
+
+    #[test]
+    fn it_fences_user_mode_with_pmp() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let secret = u20::new(0x8000);
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::LW {
+                rd: x1,
+                rs1: x2,
+                imm: 0,
+            }
+            .into(),
+        );
+        memory.set_four_byte(secret, rand::thread_rng().gen());
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        machine.registry.set(x2, secret.into());
+        // user code may only execute and read the first page
+        machine
+            .csr
+            .set(u12::new(csr::PMPADDR0), (0x1000 >> 3) - 1)?;
+        machine
+            .csr
+            .set(u12::new(csr::PMPCFG0), u32::from(PMP_R | PMP_X | (3 << 3)))?;
+        machine.privilege_mode = PrivilegeMode::User;
+
+        machine.tick()?;
+        assert_eq!(0, machine.registry.get(x1));
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!(Exception::LoadAccessFault.code(), machine.csr.mcause);
+        assert_eq!(u32::from(secret), machine.csr.mtval);
+        assert_eq!(0, machine.csr.mepc);
+
+        // machine mode is not restricted by unlocked entries
+        machine.program_counter = 0;
+        machine.tick()?;
+        assert_eq!(machine.memory.get_aligned(secret), machine.registry.get(x1));
+
+        // fetching outside of the region faults in user mode
+        machine.privilege_mode = PrivilegeMode::User;
+        machine.program_counter = 0x1000;
+        machine.tick()?;
+        assert_eq!(Exception::InstructionAccessFault.code(), machine.csr.mcause);
+        assert_eq!(0x1000, machine.csr.mepc);
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_take_interrupts() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        memory.set_four_byte(u20::new(4), CPUInstruction::WFI.into());
+        memory.set_four_byte(handler, CPUInstruction::MRET.into());
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineTimer.mask())?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+
+        machine.tick()?;
+        assert_eq!(4, machine.program_counter);
+
+        machine.assert_interrupt(Interrupt::MachineTimer);
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!(Interrupt::MachineTimer.cause(), machine.csr.mcause);
+        assert_eq!(4, machine.csr.mepc);
+        assert_eq!(csr::MSTATUS_MPIE | csr::MSTATUS_MPP, machine.csr.mstatus);
+        assert_eq!(
+            Interrupt::MachineTimer.mask(),
+            machine.csr.get(u12::new(csr::MIP))?
+        );
+
+        // interrupts are disabled inside of the handler
+        machine.tick()?;
+        assert_eq!(4, machine.program_counter);
+        assert_eq!(
+            csr::MSTATUS_MIE | csr::MSTATUS_MPIE,
+            machine.csr.mstatus & !csr::MSTATUS_MPP
+        );
+
+        machine.clear_interrupt(Interrupt::MachineTimer);
+        machine.tick()?;
+        assert_eq!(8, machine.program_counter);
+        Ok(())
+    }
+
+    #[test]
+    fn it_prioritises_interrupts() -> Result<(), ()> {
+        let base = u20::new(0x100);
+        let mut machine = RISCMachine::default();
+        machine
+            .csr
+            .set(u12::new(csr::MTVEC), u32::from(base) | csr::MTVEC_VECTORED)?;
+        machine.csr.set(u12::new(csr::MIE), u32::MAX)?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+
+        for interrupt in Interrupt::ALL {
+            machine.assert_interrupt(interrupt);
+        }
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineExternal.cause(), machine.csr.mcause);
+        assert_eq!(
+            u32::from(base) + 4 * Interrupt::MachineExternal.code(),
+            machine.program_counter
+        );
+
+        machine.clear_interrupt(Interrupt::MachineExternal);
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineSoftware.cause(), machine.csr.mcause);
+
+        machine.clear_interrupt(Interrupt::MachineSoftware);
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineTimer.cause(), machine.csr.mcause);
+        Ok(())
+    }
+
+    #[test]
+    fn it_masks_interrupts() -> Result<(), ()> {
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = 0x100;
+        machine.assert_interrupt(Interrupt::MachineSoftware);
+
+        // not enabled in mie
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.tick()?;
+        assert_eq!(4, machine.program_counter);
+
+        // globally disabled in machine mode
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineSoftware.mask())?;
+        machine.csr.mstatus = 0;
+        assert_eq!(None, machine.csr.pending_interrupt(PrivilegeMode::Machine));
+
+        // but always enabled for lower privileges
+        assert_eq!(
+            Some(Interrupt::MachineSoftware),
+            machine.csr.pending_interrupt(PrivilegeMode::User)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_advance_clint_time() {
+        let mut clint = CLINT::new(2);
+        clint.cycles_per_increment = 3;
+        clint.advance(7);
+        assert_eq!(2, clint.mtime);
+        clint.advance(2);
+        assert_eq!(3, clint.mtime);
+
+        clint.mtimecmp[1] = 3;
+        assert!(!clint.timer_pending(0));
+        assert!(clint.timer_pending(1));
+    }
+
+    #[test]
+    fn it_can_access_clint_registers() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+        let x4 = u5::new(4);
+        let mtimecmp: u32 = rand::thread_rng().gen();
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::SW {
+                rs1: x2,
+                rs2: x1,
+                imm: 0,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            u20::new(4),
+            CPUInstruction::LW {
+                rd: x3,
+                rs1: x4,
+                imm: 0,
+            }
+            .into(),
+        );
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(x1, mtimecmp);
+        machine.registry.set(x2, CLINT_BASE + 0x4000);
+        machine.registry.set(x4, CLINT_BASE + 0xbff8);
+
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(
+            u64::from(mtimecmp) | 0xffffffff_00000000,
+            machine.clint.mtimecmp[0]
+        );
+        assert_eq!(2, machine.registry.get(x3));
+        Ok(())
+    }
+
+    #[test]
+    fn it_raises_clint_interrupts() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let mut memory = Memory::default();
+        for i in 0..8 {
+            memory.set_four_byte(u20::new(4 * i), CPUInstruction::WFI.into());
+        }
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        machine.csr.set(
+            u12::new(csr::MIE),
+            Interrupt::MachineTimer.mask() | Interrupt::MachineSoftware.mask(),
+        )?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.clint.mtimecmp[0] = 4;
+
+        machine.run(&|pc| -> bool { pc == u32::from(handler) })?;
+        assert_eq!(4, machine.clint.mtime);
+        assert_eq!(Interrupt::MachineTimer.cause(), machine.csr.mcause);
+        assert_eq!(12, machine.csr.mepc);
+
+        machine.clint.mtimecmp[0] = u64::MAX;
+        machine.clint.write(0, 1)?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.program_counter = 0;
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineSoftware.cause(), machine.csr.mcause);
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_claim_plic_interrupts() {
+        let mut plic = PLIC::new(40, 2);
+        for source in [3, 7, 33] {
+            plic.set_enabled(0, source, true);
+        }
+        plic.set_priority(3, 2);
+        plic.set_priority(7, 2);
+        plic.set_priority(33, 5);
+        plic.set_threshold(0, 1);
+
+        plic.set_level(3, true);
+        plic.set_level(7, true);
+        plic.set_level(33, true);
+        assert!(plic.interrupt_pending(0));
+        assert!(!plic.interrupt_pending(1));
+
+        assert_eq!(33, plic.claim(0));
+        assert_eq!(3, plic.claim(0));
+        plic.set_threshold(0, 2);
+        assert_eq!(0, plic.claim(0));
+        assert!(plic.is_pending(7));
+
+        // level triggered sources are requested again once completed
+        plic.set_level(33, false);
+        plic.complete(0, 33);
+        plic.complete(0, 3);
+        assert!(!plic.is_pending(33));
+        assert!(plic.is_pending(3));
+    }
+
+    #[test]
+    fn it_raises_plic_interrupts() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let source = rand::thread_rng().gen_range(1..=31);
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        // claim and complete the interrupt
+        memory.set_four_byte(
+            handler,
+            CPUInstruction::LW {
+                rd: x1,
+                rs1: x2,
+                imm: 0,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            handler + u20::new(4),
+            CPUInstruction::SW {
+                rs1: x2,
+                rs2: x1,
+                imm: 0,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            handler + u20::new(8),
+            CPUInstruction::LW {
+                rd: x3,
+                rs1: x2,
+                imm: 0,
+            }
+            .into(),
+        );
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineExternal.mask())?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.registry.set(x2, PLIC_BASE + 0x20_0004);
+        machine.plic.set_priority(source, 1);
+        machine.plic.set_enabled(0, source, true);
+
+        machine.tick()?;
+        assert_eq!(4, machine.program_counter);
+
+        machine.plic.set_level(source, true);
+        machine.plic.set_level(source, false);
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineExternal.cause(), machine.csr.mcause);
+
+        machine.tick()?;
+        assert_eq!(source as u32, machine.registry.get(x1));
+        assert!(!machine.plic.interrupt_pending(0));
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(0, machine.registry.get(x3));
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_prioritises_clic_interrupts() {
+        let mut clic = CLIC::new(32);
+        // two level bits, leaving six priority bits
+        clic.cliccfg = 2;
+        for (id, ctl) in [(16, 0b01_000000), (17, 0b01_111111), (18, 0b10_000000)] {
+            clic.set_enabled(id, true);
+            clic.set_control(id, ctl);
+            clic.set_level(id, true);
+        }
+        assert_eq!(0b01_111111, clic.level(16));
+        assert_eq!(0b10_111111, clic.level(18));
+        assert_eq!(Some(18), clic.pending_interrupt(0));
+        assert_eq!(Some(18), clic.pending_interrupt(0b01_111111));
+        assert_eq!(None, clic.pending_interrupt(0b10_111111));
+
+        // level triggered interrupts follow their input
+        clic.set_level(18, false);
+        assert_eq!(Some(17), clic.pending_interrupt(0));
+
+        // edge triggered interrupts are latched until acknowledged
+        clic.set_attributes(20, CLICINTATTR_EDGE);
+        clic.set_enabled(20, true);
+        clic.set_control(20, 0xff);
+        clic.set_level(20, true);
+        clic.set_level(20, false);
+        assert_eq!(Some(20), clic.pending_interrupt(0));
+        clic.acknowledge(20);
+        assert!(!clic.is_pending(20));
+    }
+
+    #[test]
+    fn it_can_vector_clic_interrupts() -> Result<(), ()> {
+        let table = u20::new(0x400);
+        let handler = u20::new(0x200);
+        let id = rand::thread_rng().gen_range(16..64);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        memory.set_four_byte(u20::new(4), CPUInstruction::WFI.into());
+        memory.set_four_byte(table + u20::new(4 * id as u32), handler.into());
+        memory.set_four_byte(handler, CPUInstruction::MRET.into());
+
+        let mut clic = CLIC::new(64);
+        clic.mtvt = table.into();
+        clic.set_attributes(id, CLICINTATTR_SHV);
+        clic.set_enabled(id, true);
+        clic.set_control(id, 0xff);
+
+        let mut machine = RISCMachine {
+            memory,
+            interrupt_model: InterruptModel::CLIC(clic),
+            ..Default::default()
+        };
+        machine.csr.mtvec = 0x100 | csr::MTVEC_CLIC;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+
+        machine.tick()?;
+        if let InterruptModel::CLIC(clic) = &mut machine.interrupt_model {
+            clic.set_level(id, true);
+        }
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!((1 << 31) | id as u32, machine.csr.mcause);
+        let InterruptModel::CLIC(clic) = &mut machine.interrupt_model else {
+            panic!("Expected a CLIC")
+        };
+        assert_eq!(0xff, clic.mil);
+        clic.set_level(id, false);
+
+        machine.tick()?;
+        assert_eq!(4, machine.program_counter);
+        let InterruptModel::CLIC(clic) = &machine.interrupt_model else {
+            panic!("Expected a CLIC")
+        };
+        assert_eq!(0, clic.mil);
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_nest_clic_interrupts() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let handler = u20::new(0x100);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        memory.set_four_byte(
+            handler,
+            CPUInstruction::CSRRS {
+                rd: x1,
+                rs1: x2,
+                csr: u12::new(csr::MNXTI),
+            }
+            .into(),
+        );
+        memory.set_four_byte(handler + u20::new(4), CPUInstruction::WFI.into());
+
+        let mut clic = CLIC::new(32);
+        clic.cliccfg = 8;
+        clic.mtvt = 0x400;
+        for (id, ctl) in [(17, 0x40), (18, 0x20), (19, 0x80)] {
+            clic.set_attributes(id, CLICINTATTR_EDGE);
+            clic.set_enabled(id, true);
+            clic.set_control(id, ctl);
+        }
+        clic.set_level(17, true);
+
+        let mut machine = RISCMachine {
+            memory,
+            interrupt_model: InterruptModel::CLIC(clic),
+            ..Default::default()
+        };
+        machine.csr.mtvec = u32::from(handler) | csr::MTVEC_CLIC;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.registry.set(x2, csr::MSTATUS_MIE);
+
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!((1 << 31) | 17, machine.csr.mcause);
+
+        // claim the interrupt and re-enable interrupts
+        machine.tick()?;
+        assert_eq!(0x400 + 4 * 17, machine.registry.get(x1));
+        assert_eq!(csr::MSTATUS_MIE, machine.csr.mstatus & csr::MSTATUS_MIE);
+
+        let InterruptModel::CLIC(clic) = &mut machine.interrupt_model else {
+            panic!("Expected a CLIC")
+        };
+        assert!(!clic.is_pending(17));
+        assert_eq!(0x40, clic.mil);
+        clic.set_level(18, true);
+        machine.tick()?;
+        assert_eq!(u32::from(handler + u20::new(8)), machine.program_counter);
+
+        let InterruptModel::CLIC(clic) = &mut machine.interrupt_model else {
+            panic!("Expected a CLIC")
+        };
+        clic.set_level(19, true);
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!((1 << 31) | (0x40 << 16) | 19, machine.csr.mcause);
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_stores_words_little_endian() -> Result<(), ()> {
+        let value: u32 = rand::thread_rng().gen();
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(8), value);
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            assert_eq!(byte, memory.get(u20::new(8 + i as u32)));
+        }
+
+        // instructions written byte by byte are fetched in the same order
+        let (rd, rs1, rs2) = (u5::new(1), u5::new(2), u5::new(3));
+        let instruction: u32 = CPUInstruction::ADD { rd, rs1, rs2 }.into();
+        for (i, byte) in instruction.to_le_bytes().into_iter().enumerate() {
+            memory.set(u20::new(i as u32), byte);
+        }
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(rs1, 2);
+        machine.registry.set(rs2, 3);
+        machine.tick()?;
+        assert_eq!(5, machine.registry.get(rd));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_jump_beyond_twenty_bit_addresses() -> Result<(), ()> {
+        let handler = 0x8000_0100;
+        let entry = 0x8000_0004;
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), CPUInstruction::MRET.into());
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.set(u12::new(csr::MTVEC), handler)?;
+        machine.csr.set(u12::new(csr::MEPC), entry)?;
+        machine.csr.mstatus = csr::MSTATUS_MPP;
+        assert_eq!(Ok(handler), machine.csr.get(u12::new(csr::MTVEC)));
+
+        machine.tick()?;
+        assert_eq!(entry, machine.program_counter);
+
+        // there is no memory at the entry, so the fetch traps to the handler
+        machine.tick()?;
+        assert_eq!(handler, machine.program_counter);
+        assert_eq!(Exception::InstructionAccessFault.code(), machine.csr.mcause);
+        assert_eq!(entry, machine.csr.mepc);
+        Ok(())
+    }
+
+    #[test]
+    fn it_faults_jumps_outside_of_memory() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let handler = u20::new(0x100);
+        let instruction = CPUInstruction::JALR {
+            rd: x1,
+            rs1: x1,
+            imm: 5,
+        };
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), instruction.into());
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = handler.into();
+        machine.registry.set(x1, 0x8000_0000);
+
+        // the target is read before the link register is written
+        machine.tick()?;
+        assert_eq!(0x8000_0004, machine.program_counter);
+        assert_eq!(4, machine.registry.get(x1));
+
+        machine.tick()?;
+        assert_eq!(u32::from(handler), machine.program_counter);
+        assert_eq!(Exception::InstructionAccessFault.code(), machine.csr.mcause);
+        assert_eq!(0x8000_0004, machine.csr.mepc);
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_decodes_sub_word_memory() -> Result<(), ()> {
+        let rd = u5::new(rand::thread_rng().gen_range(1..=u5::MAX.into()));
+        let rs1 = u5::new(rand::thread_rng().gen_range(1..=u5::MAX.into()));
+        let rs2 = u5::new(rand::thread_rng().gen_range(1..=u5::MAX.into()));
+        let imm = 0b11111111111111111111100000000001;
+        for instruction in [
+            CPUInstruction::LB { rd, rs1, imm },
+            CPUInstruction::LH { rd, rs1, imm },
+            CPUInstruction::LBU { rd, rs1, imm },
+            CPUInstruction::LHU { rd, rs1, imm },
+            CPUInstruction::SB { rs1, rs2, imm },
+            CPUInstruction::SH { rs1, rs2, imm },
+        ] {
+            let encoded: u32 = instruction.into();
+            let decoded: CPUInstruction = encoded.try_into()?;
+            assert_eq!(instruction, decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_load_and_store_sub_words() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let data = u20::new(0x800);
+        let program = [
+            CPUInstruction::SB {
+                rs1: x2,
+                rs2: x1,
+                imm: 0,
+            },
+            CPUInstruction::SH {
+                rs1: x2,
+                rs2: x1,
+                imm: 2,
+            },
+            CPUInstruction::LB {
+                rd: u5::new(3),
+                rs1: x2,
+                imm: 0,
+            },
+            CPUInstruction::LBU {
+                rd: u5::new(4),
+                rs1: x2,
+                imm: 0,
+            },
+            CPUInstruction::LH {
+                rd: u5::new(5),
+                rs1: x2,
+                imm: 2,
+            },
+            CPUInstruction::LHU {
+                rd: u5::new(6),
+                rs1: x2,
+                imm: 2,
+            },
+            CPUInstruction::LW {
+                rd: u5::new(7),
+                rs1: x2,
+                imm: 0,
+            },
+            // sub words are routed to devices, and fault on the rom
+            CPUInstruction::SH {
+                rs1: u5::new(8),
+                rs2: x1,
+                imm: 6,
+            },
+            CPUInstruction::SB {
+                rs1: u5::new(9),
+                rs2: x1,
+                imm: 1,
+            },
+        ];
+        let device_base = 0x4000_0000;
+        let rom_base = 0x1000_0000;
+
+        let mut memory = Memory::default();
+        for (i, instruction) in program.iter().enumerate() {
+            memory.set_four_byte(u20::new(4 * i as u32), (*instruction).into());
+        }
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(x1, 0x1234_f680);
+        machine.registry.set(x2, data.into());
+        machine.registry.set(u5::new(8), device_base);
+        machine.registry.set(u5::new(9), rom_base);
+        machine
+            .bus
+            .attach(device_base, 0x100, RecordingDevice::default())?;
+        machine
+            .bus
+            .attach(rom_base, 4, ROM::new(vec![1, 2, 3, 4]))?;
+        machine.csr.mtvec = 0x100;
+        for _ in program {
+            machine.tick()?;
+        }
+
+        assert_eq!(0xffffff80, machine.registry.get(u5::new(3)));
+        assert_eq!(0x80, machine.registry.get(u5::new(4)));
+        assert_eq!(0xfffff680, machine.registry.get(u5::new(5)));
+        assert_eq!(0xf680, machine.registry.get(u5::new(6)));
+        assert_eq!(0xf680_0080, machine.registry.get(u5::new(7)));
+        assert_eq!(0x80, machine.memory.get(data));
+        let device = machine
+            .bus
+            .device::<RecordingDevice>(device_base)
+            .ok_or(())?;
+        assert_eq!(vec![(6, Width::Half, 0xf680)], device.writes);
+        assert_eq!(Exception::StoreAccessFault.code(), machine.csr.mcause);
+        assert_eq!(rom_base + 1, machine.csr.mtval);
+        assert_eq!(
+            Ok(0x04030201),
+            machine.bus.read(rom_base, Width::Word).ok_or(())?
+        );
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingDevice {
+        writes: Vec<(u32, Width, u64)>,
+    }
+
+    impl Device for RecordingDevice {
+        fn read(&mut self, offset: u32, _width: Width) -> Result<u64, ()> {
+            Ok(u64::from(offset) | 0xabcd_0000)
+        }
+
+        fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+            self.writes.push((offset, width, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_can_route_bus_accesses() -> Result<(), ()> {
+        let device_base = 0x4000_0000;
+        let rom_base = 0x1000_0000;
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+
+        let rom: Vec<u8> = [
+            CPUInstruction::SW {
+                rs1: x2,
+                rs2: x1,
+                imm: 4,
+            },
+            CPUInstruction::LW {
+                rd: x3,
+                rs1: x2,
+                imm: 8,
+            },
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x1,
+                imm: 0,
+            },
+        ]
+        .into_iter()
+        .flat_map(|instruction| u32::from(instruction).to_le_bytes())
+        .collect();
+
+        let mut machine = RISCMachine::default();
+        machine.bus.attach(rom_base, 16, ROM::new(rom))?;
+        machine
+            .bus
+            .attach(device_base, 0x100, RecordingDevice::default())?;
+        assert_eq!(
+            Err(()),
+            machine
+                .bus
+                .attach(device_base + 0x80, 0x100, RecordingDevice::default())
+        );
+        machine.program_counter = rom_base;
+        machine.registry.set(x1, 0xdead_beef);
+        machine.registry.set(x2, device_base);
+
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(0xabcd_0008, machine.registry.get(x3));
+        let device = machine
+            .bus
+            .device::<RecordingDevice>(device_base)
+            .ok_or(())?;
+        assert_eq!(vec![(4, Width::Word, 0xdead_beef)], device.writes);
+
+        // the rom can not be written to, but main memory is still mapped at 0
+        machine.registry.set(x2, rom_base);
+        machine.program_counter = rom_base + 8;
+        machine.tick()?;
+        assert_eq!(0xdead_beef, machine.memory.get_aligned(u20::new(0)));
+        assert!(machine.bus.device::<RecordingDevice>(rom_base).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_faults_writes_to_rom() -> Result<(), ()> {
+        let rom_base = 0x1000_0000;
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::SW {
+                rs1: u5::new(1),
+                rs2: u5::new(0),
+                imm: 0,
+            }
+            .into(),
+        );
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine
+            .bus
+            .attach(rom_base, 4, ROM::new(vec![1, 2, 3, 4]))?;
+        machine.csr.mtvec = 0x100;
+        machine.registry.set(u5::new(1), rom_base);
+
+        machine.tick()?;
+        assert_eq!(Exception::StoreAccessFault.code(), machine.csr.mcause);
+        assert_eq!(rom_base, machine.csr.mtval);
+        assert_eq!(
+            Ok(0x04030201),
+            machine.bus.read(rom_base, Width::Word).ok_or(())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_lazily_allocates_sparse_memory() {
+        let mut memory = Memory::sparse(1 << 32);
+        assert_eq!(0, memory.allocated_pages());
+        assert_eq!(Ok(0), memory.read(0xffff_fff0, Width::Double));
+
+        let address = rand::thread_rng().gen_range(0..u32::MAX - 8);
+        let value = rand::thread_rng().gen::<u64>() | 1;
+        assert_eq!(Ok(()), memory.write(address, Width::Double, value));
+        assert_eq!(Ok(value), memory.read(address, Width::Double));
+        assert!((1..=2).contains(&memory.allocated_pages()));
+
+        // zero writes to untouched pages do not allocate
+        memory.write(0x1000, Width::Word, 0).unwrap();
+        assert_eq!(Ok(0), memory.read(0x1000, Width::Word));
+        assert!(memory.allocated_pages() <= 3);
+        assert_eq!(Err(()), memory.write(0xffff_fffe, Width::Word, 1));
+    }
+
+    #[test]
+    fn it_can_run_many_sparse_machines() -> Result<(), ()> {
+        let mut machines: Vec<RISCMachine> = (0..1000)
+            .map(|i| {
+                let mut machine = RISCMachine::default();
+                machine.registry.set(u5::new(1), i);
+                machine.memory.set_four_byte(
+                    u20::new(0),
+                    CPUInstruction::SW {
+                        rs1: u5::new(0),
+                        rs2: u5::new(1),
+                        imm: 0x7fc,
+                    }
+                    .into(),
+                );
+                machine
+            })
+            .collect();
+
+        for (i, machine) in machines.iter_mut().enumerate() {
+            assert!(machine.memory.is_sparse());
+            machine.tick()?;
+            assert_eq!(i as u32, machine.memory.get_aligned(u20::new(0x7fc)));
+            assert_eq!(1, machine.memory.allocated_pages());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_transmit_over_uart() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+        let x4 = u5::new(4);
+        let program = [
+            CPUInstruction::SB {
+                rs1: x2,
+                rs2: x1,
+                imm: 0,
+            },
+            CPUInstruction::SB {
+                rs1: x2,
+                rs2: x3,
+                imm: 0,
+            },
+            CPUInstruction::LBU {
+                rd: x4,
+                rs1: x2,
+                imm: 5,
+            },
+        ];
+        let mut memory = Memory::default();
+        for (i, instruction) in program.iter().enumerate() {
+            memory.set_four_byte(u20::new(4 * i as u32), (*instruction).into());
+        }
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.bus.attach(UART_BASE, UART_SIZE, UART::default())?;
+        machine.registry.set(x1, u32::from(b'H'));
+        machine.registry.set(x2, UART_BASE);
+        machine.registry.set(x3, u32::from(b'i'));
+        for _ in program {
+            machine.tick()?;
+        }
+
+        let uart = machine.bus.device_mut::<UART>(UART_BASE).ok_or(())?;
+        assert_eq!(b"Hi", uart.transmitted());
+        assert_eq!(b"Hi".to_vec(), uart.take_transmitted());
+        assert!(uart.transmitted().is_empty());
+        assert_eq!(u32::from(LSR_THRE), machine.registry.get(x4) & 0x21);
+        Ok(())
+    }
+
+    #[test]
+    fn it_raises_uart_interrupts() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let source = rand::thread_rng().gen_range(1..=31);
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::SB {
+                rs1: x2,
+                rs2: x1,
+                imm: 1,
+            }
+            .into(),
+        );
+        memory.set_four_byte(u20::new(4), CPUInstruction::WFI.into());
+        memory.set_four_byte(
+            handler,
+            CPUInstruction::LBU {
+                rd: x3,
+                rs1: x2,
+                imm: 0,
+            }
+            .into(),
+        );
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine
+            .bus
+            .attach(UART_BASE, UART_SIZE, UART::default().with_input(receiver))?;
+        machine.bus.connect_interrupt(UART_BASE, source)?;
+        machine.csr.mtvec = handler.into();
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineExternal.mask())?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.plic.set_priority(source, 1);
+        machine.plic.set_enabled(0, source, true);
+        machine.registry.set(x1, u32::from(IER_ERBFI));
+        machine.registry.set(x2, UART_BASE);
+
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(8, machine.program_counter);
+
+        sender.send(b'a').map_err(|_| ())?;
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineExternal.cause(), machine.csr.mcause);
+        assert!(machine.plic.is_pending(source));
+
+        machine.tick()?;
+        assert_eq!(u32::from(b'a'), machine.registry.get(x3));
+        let uart = machine.bus.device_mut::<UART>(UART_BASE).ok_or(())?;
+        assert!(!uart.interrupt());
+        assert_eq!(0, uart.line_status() & LSR_DR);
+        Ok(())
+    }
+
+    fn exit_program(base: u32, value: u32) -> RISCMachine {
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::SW {
+                rs1: u5::new(2),
+                rs2: u5::new(1),
+                imm: 0,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            u20::new(4),
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: 0,
+            }
+            .into(),
+        );
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(u5::new(1), value);
+        machine.registry.set(u5::new(2), base);
+        machine
+    }
+
+    #[test]
+    fn it_can_exit_with_test_finisher() -> Result<(), ()> {
+        let code = rand::thread_rng().gen_range(1..=0xffff);
+
+        let mut machine = exit_program(TEST_FINISHER_BASE, FINISHER_FAIL | (code << 16));
+        machine.bus.attach(
+            TEST_FINISHER_BASE,
+            TEST_FINISHER_SIZE,
+            TestFinisher::default(),
+        )?;
+        assert_eq!(Some(code), machine.run(&|_| false)?);
+
+        let mut machine = exit_program(TEST_FINISHER_BASE, FINISHER_PASS);
+        machine.bus.attach(
+            TEST_FINISHER_BASE,
+            TEST_FINISHER_SIZE,
+            TestFinisher::default(),
+        )?;
+        assert_eq!(Some(0), machine.run(&|_| false)?);
+        assert_eq!(4, machine.program_counter);
+
+        // without an exit the predicate still stops the machine
+        let mut machine = exit_program(0x800, FINISHER_PASS);
+        assert_eq!(None, machine.run(&|pc| pc == 4)?);
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_exit_with_htif() -> Result<(), ()> {
+        let tohost = 0x8000_1000;
+        let code = rand::thread_rng().gen_range(1..=0x7fff_ffff);
+
+        let mut machine = exit_program(tohost, (code << 1) | 1);
+        machine.bus.attach(tohost, HTIF_SIZE, HTIF::default())?;
+        assert_eq!(Some(code), machine.run(&|_| false)?);
+        let htif = machine.bus.device::<HTIF>(tohost).ok_or(())?;
+        assert_eq!(u64::from((code << 1) | 1), htif.tohost);
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_matches_pma_regions() {
+        let mut pma = PMA::default();
+        assert_eq!(Ok(()), pma.add(0, 0x1000, PMA_ROM));
+        assert_eq!(Ok(()), pma.add(0x1000, 0x1000, PMA_RAM));
+        assert_eq!(Ok(()), pma.add(0x4000, 0x100, PMA_EXECUTE_ONLY));
+        assert_eq!(Ok(()), pma.add(0x1000_0000, 0x100, PMA_DEVICE));
+        assert_eq!(Err(()), pma.add(0xf00, 0x200, PMA_RAM));
+
+        assert!(pma.check(0x10, 4, AccessType::Read));
+        assert!(pma.check(0x10, 4, AccessType::Execute));
+        assert!(!pma.check(0x10, 4, AccessType::Write));
+        assert!(pma.check(0x1ffc, 4, AccessType::Write));
+        // accesses may not straddle two regions
+        assert!(!pma.check(0xffe, 4, AccessType::Read));
+        assert!(pma.check(0x4000, 4, AccessType::Execute));
+        assert!(!pma.check(0x4000, 4, AccessType::Read));
+        assert!(!pma.check(0x1000_0000, 4, AccessType::Execute));
+        assert!(!pma.is_cacheable(0x1000_0000));
+        assert!(pma.is_cacheable(0x1000));
+        // undeclared addresses are unchecked
+        assert!(pma.check(0x3000, 4, AccessType::Write));
+
+        pma.remove(0x10);
+        assert!(pma.check(0x10, 4, AccessType::Write));
+        assert_eq!(None, pma.attributes(0x10));
+    }
+
+    #[test]
+    fn it_faults_pma_violations() -> Result<(), ()> {
+        let handler = u20::new(0x200);
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let mut memory = Memory::default();
+        let program = [
+            // overwrite the program itself
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x1,
+                imm: 0,
+            },
+            // read from an execute only region
+            CPUInstruction::LW {
+                rd: x1,
+                rs1: x2,
+                imm: 0,
+            },
+            // jump into a device region
+            CPUInstruction::JALR {
+                rd: u5::new(0),
+                rs1: x2,
+                imm: 0x100,
+            },
+        ];
+        for (i, instruction) in program.iter().enumerate() {
+            memory.set_four_byte(u20::new(4 * i as u32), (*instruction).into());
+        }
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.pma.add(0, 0x100, PMA_ROM)?;
+        machine.pma.add(0x100, 0x1000, PMA_RAM)?;
+        machine.pma.add(0x2000, 0x100, PMA_EXECUTE_ONLY)?;
+        machine.pma.add(0x2100, 0x100, PMA_DEVICE)?;
+        machine.csr.mtvec = handler.into();
+        machine.registry.set(x1, 0xdead_beef);
+        machine.registry.set(x2, 0x2000);
+
+        let expected = [
+            Exception::StoreAccessFault,
+            Exception::LoadAccessFault,
+            Exception::InstructionAccessFault,
+        ];
+        for (i, exception) in expected.into_iter().enumerate() {
+            machine.program_counter = 4 * i as u32;
+            machine.tick()?;
+            if exception == Exception::InstructionAccessFault {
+                machine.tick()?;
+            }
+            assert_eq!(exception.code(), machine.csr.mcause);
+            assert_eq!(u32::from(handler), machine.program_counter);
+        }
+        assert_ne!(0xdead_beef, machine.memory.get_aligned(u20::new(0)));
+
+        // the host is also prevented from writing read only regions
+        assert_eq!(Err(()), machine.host_write(0, Width::Word, 0));
+        assert_eq!(Ok(()), machine.host_write(0x100, Width::Word, 1));
+        assert_eq!(Ok(1), machine.host_read(0x100, Width::Word));
+        assert_eq!(Err(()), machine.host_read(0x2000, Width::Word));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_read_from_block_devices() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let source = rand::thread_rng().gen_range(1..=31);
+        let buffer = 0x8000;
+        let image: Vec<u8> = (0..4 * SECTOR_SIZE).map(|_| rand::random()).collect();
+        let registers: Vec<u5> = (1..=8).map(u5::new).collect();
+        let program = [
+            (registers[1], 0x0),
+            (registers[2], 0x4),
+            (registers[3], 0x8),
+            (registers[4], 0x18),
+            (registers[5], 0xc),
+        ];
+
+        let mut memory = Memory::default();
+        for (i, (rs2, imm)) in program.into_iter().enumerate() {
+            memory.set_four_byte(
+                u20::new(4 * i as u32),
+                CPUInstruction::SW {
+                    rs1: registers[0],
+                    rs2,
+                    imm,
+                }
+                .into(),
+            );
+        }
+        memory.set_four_byte(u20::new(20), CPUInstruction::WFI.into());
+        // read the status then acknowledge the completion
+        memory.set_four_byte(
+            handler,
+            CPUInstruction::LW {
+                rd: registers[7],
+                rs1: registers[0],
+                imm: 0x10,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            handler + u20::new(4),
+            CPUInstruction::SW {
+                rs1: registers[0],
+                rs2: u5::new(0),
+                imm: 0x10,
+            }
+            .into(),
+        );
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine
+            .bus
+            .attach(BLOCK_BASE, BLOCK_SIZE, BlockDevice::new(image.clone()))?;
+        machine.bus.connect_interrupt(BLOCK_BASE, source)?;
+        machine.csr.mtvec = handler.into();
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineExternal.mask())?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.plic.set_priority(source, 1);
+        machine.plic.set_enabled(0, source, true);
+        for (register, value) in
+            registers
+                .iter()
+                .zip([BLOCK_BASE, 1, buffer, 2, 1, BLOCK_COMMAND_READ])
+        {
+            machine.registry.set(*register, value);
+        }
+
+        for _ in program {
+            machine.tick()?;
+        }
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineExternal.cause(), machine.csr.mcause);
+        assert_eq!(
+            image[SECTOR_SIZE..3 * SECTOR_SIZE].to_vec(),
+            machine.memory.read_range(buffer, 2 * SECTOR_SIZE)?
+        );
+
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(BLOCK_STATUS_DONE, machine.registry.get(registers[7]));
+        let device = machine.bus.device::<BlockDevice>(BLOCK_BASE).ok_or(())?;
+        assert!(!device.interrupt());
+        assert_eq!(0, device.status());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_write_to_block_devices() -> Result<(), ()> {
+        let buffer = 0x8000;
+        let sector: Vec<u8> = (0..SECTOR_SIZE).map(|_| rand::random()).collect();
+        let path = std::env::temp_dir().join(format!("riscv-sim-{}.img", rand::random::<u64>()));
+        std::fs::write(&path, vec![0; 2 * SECTOR_SIZE]).map_err(|_| ())?;
+
+        let mut machine = RISCMachine::default();
+        machine
+            .memory
+            .set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        machine.memory.set_four_byte(
+            u20::new(4),
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: -4i32 as u32,
+            }
+            .into(),
+        );
+        machine.memory.load_slice(buffer, &sector)?;
+        let device = BlockDevice::open(&path).map_err(|_| ())?;
+        assert_eq!(2, device.sectors());
+        machine.bus.attach(BLOCK_BASE, BLOCK_SIZE, device)?;
+        for (offset, value) in [
+            (0x0, 1),
+            (0x4, buffer),
+            (0x8, 1),
+            (0xc, BLOCK_COMMAND_WRITE),
+        ] {
+            machine.host_write(BLOCK_BASE + offset, Width::Word, value)?;
+        }
+        machine.tick()?;
+        assert_eq!(
+            Ok(BLOCK_STATUS_DONE),
+            machine.host_read(BLOCK_BASE + 0x10, Width::Word)
+        );
+        let written = std::fs::read(&path).map_err(|_| ())?;
+        assert_eq!(sector, written[SECTOR_SIZE..]);
+
+        // transfers past the end of the image fail
+        machine.host_write(BLOCK_BASE + 0x10, Width::Word, 0)?;
+        machine.host_write(BLOCK_BASE + 0x8, Width::Word, 2)?;
+        machine.host_write(BLOCK_BASE + 0xc, Width::Word, BLOCK_COMMAND_WRITE)?;
+        machine.tick()?;
+        assert_eq!(
+            Ok(BLOCK_STATUS_ERROR),
+            machine.host_read(BLOCK_BASE + 0x10, Width::Word)
+        );
+        std::fs::remove_file(&path).map_err(|_| ())?;
+        Ok(())
+    }
+
+    fn virtio_descriptor(address: u32, length: u32, flags: u16) -> Vec<u8> {
+        let mut bytes = u64::from(address).to_le_bytes().to_vec();
+        bytes.extend(length.to_le_bytes());
+        bytes.extend(flags.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn it_can_negotiate_virtio_features() -> Result<(), ()> {
+        let mut machine = RISCMachine::default();
+        machine.bus.attach(
+            VIRTIO_BASE,
+            VIRTIO_SIZE,
+            VirtioMMIO::new(Console::default()),
+        )?;
+        assert_eq!(
+            Ok(VIRTIO_MAGIC),
+            machine.host_read(VIRTIO_BASE, Width::Word)
+        );
+        assert_eq!(Ok(2), machine.host_read(VIRTIO_BASE + 0x4, Width::Word));
+        assert_eq!(
+            Ok(VIRTIO_ID_CONSOLE),
+            machine.host_read(VIRTIO_BASE + 0x8, Width::Word)
+        );
+
+        let driver = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver)?;
+        machine.host_write(VIRTIO_BASE + 0x14, Width::Word, 1)?;
+        assert_eq!(Ok(1), machine.host_read(VIRTIO_BASE + 0x10, Width::Word));
+
+        // legacy drivers which do not accept VIRTIO_F_VERSION_1 are refused
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver | STATUS_FEATURES_OK)?;
+        assert_eq!(
+            Ok(driver),
+            machine.host_read(VIRTIO_BASE + 0x70, Width::Word)
+        );
+
+        machine.host_write(VIRTIO_BASE + 0x24, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x20, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver | STATUS_FEATURES_OK)?;
+        assert_eq!(
+            Ok(driver | STATUS_FEATURES_OK),
+            machine.host_read(VIRTIO_BASE + 0x70, Width::Word)
+        );
+
+        // writing zero resets the device
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, 0)?;
+        let device = machine
+            .bus
+            .device::<VirtioMMIO<Console>>(VIRTIO_BASE)
+            .ok_or(())?;
+        assert_eq!(0, device.status());
+        assert_eq!(0, device.driver_features());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_use_virtio_console() -> Result<(), ()> {
+        let source = rand::thread_rng().gen_range(1..=31);
+        let receive_queue = 0x10000;
+        let transmit_queue = 0x11000;
+        let transmit_buffer = 0x12000;
+        let receive_buffer = 0x12100;
+
+        let mut machine = RISCMachine::default();
+        machine
+            .memory
+            .set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        machine.memory.set_four_byte(
+            u20::new(4),
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: -4i32 as u32,
+            }
+            .into(),
+        );
+        machine.bus.attach(
+            VIRTIO_BASE,
+            VIRTIO_SIZE,
+            VirtioMMIO::new(Console::default()),
+        )?;
+        machine.bus.connect_interrupt(VIRTIO_BASE, source)?;
+        machine.plic.set_priority(source, 1);
+        machine.plic.set_enabled(0, source, true);
+
+        let driver = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        machine.host_write(VIRTIO_BASE + 0x24, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x20, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver)?;
+        for (index, queue) in [(0, receive_queue), (1, transmit_queue)] {
+            machine.host_write(VIRTIO_BASE + 0x30, Width::Word, index)?;
+            assert_eq!(Ok(256), machine.host_read(VIRTIO_BASE + 0x34, Width::Word));
+            machine.host_write(VIRTIO_BASE + 0x38, Width::Word, 8)?;
+            machine.host_write(VIRTIO_BASE + 0x80, Width::Word, queue)?;
+            machine.host_write(VIRTIO_BASE + 0x90, Width::Word, queue + 0x100)?;
+            machine.host_write(VIRTIO_BASE + 0xa0, Width::Word, queue + 0x200)?;
+            machine.host_write(VIRTIO_BASE + 0x44, Width::Word, 1)?;
+        }
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver | STATUS_DRIVER_OK)?;
+
+        // transmit a message split over two descriptors
+        machine.memory.load_slice(transmit_buffer, b"hello world")?;
+        let mut descriptors = virtio_descriptor(transmit_buffer, 6, 1);
+        descriptors[14] = 1;
+        descriptors.extend(virtio_descriptor(transmit_buffer + 6, 5, 0));
+        machine.memory.load_slice(transmit_queue, &descriptors)?;
+        machine
+            .memory
+            .load_slice(transmit_queue + 0x100, &[0, 0, 1, 0, 0, 0])?;
+        machine.host_write(VIRTIO_BASE + 0x50, Width::Word, 1)?;
+        machine.tick()?;
+
+        let device = machine
+            .bus
+            .device_mut::<VirtioMMIO<Console>>(VIRTIO_BASE)
+            .ok_or(())?;
+        assert_eq!(b"hello world", device.device().transmitted());
+        device.device_mut().receive(b"abc");
+        assert_eq!(
+            vec![1, 0, 0, 0, 0, 0, 0, 0],
+            machine.memory.read_range(transmit_queue + 0x202, 8)?
+        );
+        assert_eq!(
+            Ok(INTERRUPT_USED_BUFFER),
+            machine.host_read(VIRTIO_BASE + 0x60, Width::Word)
+        );
+        assert!(machine.plic.is_pending(source));
+        machine.host_write(VIRTIO_BASE + 0x64, Width::Word, INTERRUPT_USED_BUFFER)?;
+
+        // received bytes fill the buffers made available by the driver
+        machine
+            .memory
+            .load_slice(receive_queue, &virtio_descriptor(receive_buffer, 16, 2))?;
+        machine
+            .memory
+            .load_slice(receive_queue + 0x100, &[0, 0, 1, 0, 0, 0])?;
+        machine.tick()?;
+        assert_eq!(
+            b"abc".to_vec(),
+            machine.memory.read_range(receive_buffer, 3)?
+        );
+        assert_eq!(
+            vec![1, 0, 0, 0, 0, 0, 3, 0, 0, 0],
+            machine.memory.read_range(receive_queue + 0x202, 10)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_draw_to_framebuffer() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::SW {
+                rs1: x2,
+                rs2: x1,
+                imm: 12,
+            }
+            .into(),
+        );
+        let framebuffer = Framebuffer::new(2, 2, PixelFormat::XRGB8888);
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine
+            .bus
+            .attach(FRAMEBUFFER_BASE, framebuffer.size(), framebuffer)?;
+        machine.registry.set(x1, 0x00ff_8010);
+        machine.registry.set(x2, FRAMEBUFFER_BASE);
+        machine.tick()?;
+
+        let framebuffer = machine
+            .bus
+            .device::<Framebuffer>(FRAMEBUFFER_BASE)
+            .ok_or(())?;
+        assert_eq!(Some([0xff, 0x80, 0x10]), framebuffer.pixel(1, 1));
+        assert_eq!(Some([0, 0, 0]), framebuffer.pixel(1, 0));
+        assert_eq!([0x10, 0x80, 0xff, 0x00], framebuffer.as_slice()[12..]);
+
+        let mut ppm = b"P6\n2 2\n255\n".to_vec();
+        ppm.extend([0; 9]);
+        ppm.extend([0xff, 0x80, 0x10]);
+        assert_eq!(ppm, framebuffer.to_ppm());
+
+        let png = framebuffer.to_png();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!([0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0], png[16..29]);
+        // the crc of an empty IEND chunk is fixed
+        assert_eq!(
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82],
+            png[png.len() - 12..]
+        );
+        let idat = &png[33..];
+        assert_eq!(b"IDAT", &idat[4..8]);
+        // zlib header, then a single stored block holding both scanlines
+        assert_eq!([0x78, 0x01, 1, 14, 0, !14, 0xff], idat[8..15]);
+        assert_eq!(
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0x80, 0x10],
+            idat[15..29]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_converts_pixel_formats() -> Result<(), ()> {
+        let mut framebuffer = Framebuffer::new(3, 1, PixelFormat::RGB565);
+        framebuffer.write(0, Width::Half, 0xf800)?;
+        framebuffer.write(2, Width::Half, 0x07e0)?;
+        framebuffer.write(4, Width::Half, 0xffff)?;
+        assert_eq!(
+            vec![0xff, 0, 0, 0, 0xff, 0, 0xff, 0xff, 0xff],
+            framebuffer.to_rgb()
+        );
+        assert_eq!(Err(()), framebuffer.write(6, Width::Byte, 0));
+
+        let mut framebuffer = Framebuffer::new(1, 1, PixelFormat::Gray8);
+        framebuffer.write(0, Width::Byte, 0x42)?;
+        assert_eq!(Some([0x42; 3]), framebuffer.pixel(0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_drive_gpio_pins() -> Result<(), ()> {
+        let handler = u20::new(0x100);
+        let source = rand::thread_rng().gen_range(1..=31);
+        let pin = rand::thread_rng().gen_range(1..32);
+        let x1 = u5::new(1);
+        let x2 = u5::new(2);
+        let x3 = u5::new(3);
+        let x4 = u5::new(4);
+
+        let mut memory = Memory::default();
+        let program = [(x2, 0x8), (x2, 0x4), (x3, 0xc)];
+        for (i, (rs2, imm)) in program.into_iter().enumerate() {
+            memory.set_four_byte(
+                u20::new(4 * i as u32),
+                CPUInstruction::SW { rs1: x1, rs2, imm }.into(),
+            );
+        }
+        memory.set_four_byte(u20::new(12), CPUInstruction::WFI.into());
+        memory.set_four_byte(
+            u20::new(16),
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: -4i32 as u32,
+            }
+            .into(),
+        );
+        // read then clear the pending pins
+        memory.set_four_byte(
+            handler,
+            CPUInstruction::LW {
+                rd: x4,
+                rs1: x1,
+                imm: 0x10,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            handler + u20::new(4),
+            CPUInstruction::SW {
+                rs1: x1,
+                rs2: x4,
+                imm: 0x10,
+            }
+            .into(),
+        );
+
+        let changes = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut gpio = GPIO::default();
+        let recorded = changes.clone();
+        gpio.on_output_change(move |pin, level| recorded.borrow_mut().push((pin, level)));
+        gpio.schedule_input(6, pin, true);
+
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.bus.attach(GPIO_BASE, GPIO_SIZE, gpio)?;
+        machine.bus.connect_interrupt(GPIO_BASE, source)?;
+        machine.csr.mtvec = handler.into();
+        machine
+            .csr
+            .set(u12::new(csr::MIE), Interrupt::MachineExternal.mask())?;
+        machine.csr.mstatus = csr::MSTATUS_MIE;
+        machine.plic.set_priority(source, 1);
+        machine.plic.set_enabled(0, source, true);
+        machine.registry.set(x1, GPIO_BASE);
+        machine.registry.set(x2, 1);
+        machine.registry.set(x3, 1 << pin);
+
+        for _ in 0..5 {
+            machine.tick()?;
+        }
+        assert_eq!(vec![(0, true)], *changes.borrow());
+        assert_ne!(u32::from(handler), machine.program_counter);
+
+        machine.tick()?;
+        assert_eq!(Interrupt::MachineExternal.cause(), machine.csr.mcause);
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(1 << pin, machine.registry.get(x4));
+        let gpio = machine.bus.device_mut::<GPIO>(GPIO_BASE).ok_or(())?;
+        assert!(!gpio.interrupt());
+        assert!(gpio.level(pin));
+        assert!(gpio.level(0));
+        assert_eq!(8, gpio.time());
+
+        // only changes in level are reported
+        gpio.set_input(pin, true);
+        assert!(!gpio.interrupt());
+        gpio.write(0x4, Width::Word, 0)?;
+        assert_eq!(vec![(0, true), (0, false)], *changes.borrow());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_access_memory_with_explicit_endianness() -> Result<(), ()> {
+        for mut memory in [Memory::new(0x100), Memory::sparse(0x100)] {
+            let value = rand::thread_rng().gen::<u64>();
+            memory.write_u64(0x10, value, Endianness::Little)?;
+            assert_eq!(Ok(value), memory.read_u64(0x10, Endianness::Little));
+            assert_eq!(
+                Ok(value.swap_bytes()),
+                memory.read_u64(0x10, Endianness::Big)
+            );
+            assert_eq!(Ok(value as u32), memory.read_u32(0x10, Endianness::Little));
+            assert_eq!(Ok(value as u16), memory.read_u16(0x10, Endianness::Little));
+            assert_eq!(Ok(value as u8), memory.read_u8(0x10));
+
+            memory.write_u32(0x20, 0x1234_5678, Endianness::Big)?;
+            assert_eq!(Ok(vec![0x12, 0x34, 0x56, 0x78]), memory.read_range(0x20, 4));
+            memory.write_u16(0x24, 0xabcd, Endianness::Big)?;
+            memory.write_u8(0x26, 0xef)?;
+            assert_eq!(Ok(0xabcd_ef00), memory.read_u32(0x24, Endianness::Big));
+            assert_eq!(0x7856_3412, memory.get_aligned(u20::new(0x20)));
+
+            memory.load_slice(0x30, b"hello")?;
+            assert_eq!(b'e', memory.get(u20::new(0x31)));
+            assert_eq!(Ok(b"hello".to_vec()), memory.read_range(0x30, 5));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_out_of_range_memory_access() {
+        let mut memory = Memory::new(0x100);
+        assert_eq!(Err(()), memory.read_u32(0xfe, Endianness::Little));
+        assert_eq!(Err(()), memory.write_u64(0xf9, 0, Endianness::Big));
+        assert_eq!(Err(()), memory.read_u8(u32::MAX));
+        assert_eq!(Err(()), memory.load_slice(0xff, &[1, 2]));
+        assert_eq!(Err(()), memory.read_range(u32::MAX, usize::MAX));
+        // a failed access writes nothing
+        assert_eq!(Ok(0), memory.read_u8(0xff));
+        assert_eq!(Ok(()), memory.write_u16(0xfe, 0xffff, Endianness::Little));
+    }
+
+    fn push_word(bytes: &mut Vec<u8>, class: Class, value: u64) {
+        match class {
+            Class::ELF32 => bytes.extend((value as u32).to_le_bytes()),
+            Class::ELF64 => bytes.extend(value.to_le_bytes()),
+        }
+    }
+
+    // An executable with a PT_LOAD segment for each (address, data, memory size)
+    fn build_elf(
+        class: Class,
+        flags: u32,
+        entry: u64,
+        segments: &[(u64, Vec<u8>, u64)],
+    ) -> Vec<u8> {
+        let (header_size, program_header_size) = match class {
+            Class::ELF32 => (52u16, 32u16),
+            Class::ELF64 => (64, 56),
+        };
+        let mut elf = b"\x7fELF".to_vec();
+        elf.extend([if class == Class::ELF32 { 1 } else { 2 }, 1, 1]);
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes());
+        elf.extend(243u16.to_le_bytes());
+        elf.extend(1u32.to_le_bytes());
+        push_word(&mut elf, class, entry);
+        push_word(&mut elf, class, header_size.into());
+        push_word(&mut elf, class, 0);
+        elf.extend(flags.to_le_bytes());
+        for value in [
+            header_size,
+            program_header_size,
+            segments.len() as u16,
+            0,
+            0,
+            0,
+        ] {
+            elf.extend(value.to_le_bytes());
+        }
+
+        let mut offset =
+            u64::from(header_size) + segments.len() as u64 * u64::from(program_header_size);
+        for (address, data, memory_size) in segments {
+            elf.extend(1u32.to_le_bytes());
+            if class == Class::ELF64 {
+                elf.extend(7u32.to_le_bytes());
+            }
+            for value in [offset, *address, *address, data.len() as u64, *memory_size] {
+                push_word(&mut elf, class, value);
+            }
+            if class == Class::ELF32 {
+                elf.extend(7u32.to_le_bytes());
+            }
+            push_word(&mut elf, class, 4);
+            offset += data.len() as u64;
+        }
+        for (_, data, _) in segments {
+            elf.extend(data);
+        }
+        elf
+    }
+
+    // Append a symbol table for the (name, address, size, type) symbols,
+    // along with the string tables and section headers describing it
+    fn add_symbols(elf: &mut Vec<u8>, class: Class, symbols: &[(&str, u64, u64, u8)]) {
+        let mut strings = vec![0];
+        let mut table = vec![0; if class == Class::ELF32 { 16 } else { 24 }];
+        for (name, address, size, kind) in symbols {
+            let name_offset = strings.len() as u32;
+            strings.extend(name.bytes().chain([0]));
+            table.extend(name_offset.to_le_bytes());
+            if class == Class::ELF64 {
+                table.extend([*kind, 0, 1, 0]);
+            }
+            push_word(&mut table, class, *address);
+            push_word(&mut table, class, *size);
+            if class == Class::ELF32 {
+                table.extend([*kind, 0, 1, 0]);
+            }
+        }
+        add_sections(
+            elf,
+            class,
+            vec![
+                (".symtab", SHT_SYMTAB, table, 2),
+                (".strtab", SHT_STRTAB, strings, 0),
+            ],
+        );
+    }
+
+    // Append the sections followed by a section name table, replacing any
+    // section headers of the file
+    fn add_sections(elf: &mut Vec<u8>, class: Class, sections: Vec<(&str, u32, Vec<u8>, u32)>) {
+        let (shift, section_header_size) = match class {
+            Class::ELF32 => (0, 40u16),
+            Class::ELF64 => (12, 64),
+        };
+        let mut names = vec![0];
+        let mut headers = vec![(0, 0, 0, 0, 0)];
+        let section_count = sections.len() as u16 + 2;
+        for (name, kind, data, link) in
+            sections
+                .into_iter()
+                .chain([(".shstrtab", SHT_STRTAB, Vec::new(), 0)])
+        {
+            let name_offset = names.len() as u32;
+            names.extend(name.bytes().chain([0]));
+            let data = if name == ".shstrtab" {
+                names.clone()
+            } else {
+                data
+            };
+            headers.push((name_offset, kind, elf.len() as u64, data.len() as u64, link));
+            elf.extend(data);
+        }
+
+        let section_headers = elf.len() as u64;
+        for (name, kind, offset, size, link) in headers {
+            elf.extend(name.to_le_bytes());
+            elf.extend(kind.to_le_bytes());
+            for value in [0, 0, offset, size] {
+                push_word(elf, class, value);
+            }
+            elf.extend(link.to_le_bytes());
+            elf.extend(0u32.to_le_bytes());
+            push_word(elf, class, 0);
+            push_word(elf, class, 0);
+        }
+        let mut header = Vec::new();
+        push_word(&mut header, class, section_headers);
+        let start = if class == Class::ELF32 { 32 } else { 40 };
+        elf[start..start + header.len()].copy_from_slice(&header);
+        for (offset, value) in [
+            (46, section_header_size),
+            (48, section_count),
+            (50, section_count - 1),
+        ] {
+            elf[offset + shift..offset + shift + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn it_can_load_elf_executables() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let text: Vec<u8> = [
+            CPUInstruction::LW {
+                rd: x1,
+                rs1: u5::new(0),
+                imm: 0x700,
+            },
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x1,
+                imm: 0x7fc,
+            },
+        ]
+        .into_iter()
+        .flat_map(|instruction| u32::from(instruction).to_le_bytes())
+        .collect();
+        let bytes = build_elf(
+            Class::ELF32,
+            0,
+            0x104,
+            &[(0x100, text, 0x10), (0x700, vec![1, 2, 3, 4], 0x10)],
+        );
+
+        let mut machine = RISCMachine::default();
+        machine.memory.load_slice(0x700, &[0xff; 0x10])?;
+        let elf = ELF::parse(&bytes).map_err(|_| ())?;
+        assert_eq!(2, elf.loadable_segments().count());
+        assert_eq!(Ok(()), machine.load_elf(&elf));
+        assert_eq!(0x104, machine.program_counter);
+        // the remainder of each segment is zeroed
+        assert_eq!(
+            Ok(vec![1, 2, 3, 4, 0, 0, 0, 0]),
+            machine.memory.read_range(0x700, 8)
+        );
+        assert_eq!(Ok(0), machine.memory.read_u32(0x10c, Endianness::Little));
+
+        machine.registry.set(x1, 0x1234);
+        machine.tick()?;
+        assert_eq!(0x1234, machine.memory.get_aligned(u20::new(0x7fc)));
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_elf_executables() {
+        let mut machine = RISCMachine::default();
+        let segments = [(0x100, vec![0; 4], 4)];
+
+        let bytes = build_elf(Class::ELF64, 0, 0x1_0000_0100, &segments);
+        let elf = ELF::parse(&bytes).unwrap();
+        assert_eq!(Class::ELF64, elf.class);
+        assert_eq!(0x1_0000_0100, elf.entry);
+        assert_eq!(0x100, elf.segments[0].physical_address);
+        assert_eq!(Err(ELFError::UnsupportedClass), machine.load_elf(&elf));
+
+        for flags in [EF_RISCV_RVC, EF_RISCV_FLOAT_ABI_DOUBLE] {
+            let elf = ELF::parse(&build_elf(Class::ELF32, flags, 0x100, &segments)).unwrap();
+            assert_eq!(
+                Err(ELFError::UnsupportedFlags(flags)),
+                machine.load_elf(&elf)
+            );
+        }
+
+        let mut bytes = build_elf(Class::ELF32, 0, 0x100, &segments);
+        bytes[18] = 62;
+        let elf = ELF::parse(&bytes).unwrap();
+        assert_eq!(
+            Err(ELFError::UnsupportedMachine(62)),
+            machine.load_elf(&elf)
+        );
+
+        let bytes = build_elf(Class::ELF32, 0, 0x100, &[(0xffffc, vec![0; 4], 8)]);
+        let elf = ELF::parse(&bytes).unwrap();
+        assert_eq!(Err(ELFError::OutOfRange), machine.load_elf(&elf));
+
+        let bytes = build_elf(Class::ELF32, 0, 0x100, &segments);
+        assert_eq!(
+            Err(ELFError::Truncated),
+            ELF::parse(&bytes[..bytes.len() - 1])
+        );
+        assert_eq!(Err(ELFError::BadMagic), ELF::parse(b"\x7fELV"));
+        assert_eq!(0, machine.program_counter);
+    }
+
+    #[test]
+    fn it_can_resolve_elf_symbols() -> Result<(), ()> {
+        let ra = u5::new(1);
+        let x2 = u5::new(2);
+        let text: Vec<u8> = [
+            // _start calls main, then loops
+            CPUInstruction::JAL { rd: ra, imm: 8 },
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: 0,
+            },
+            // main copies input into result
+            CPUInstruction::LW {
+                rd: x2,
+                rs1: u5::new(0),
+                imm: 0x700,
+            },
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x2,
+                imm: 0x704,
+            },
+            CPUInstruction::JALR {
+                rd: u5::new(0),
+                rs1: ra,
+                imm: 0,
+            },
+        ]
+        .into_iter()
+        .flat_map(|instruction| u32::from(instruction).to_le_bytes())
+        .collect();
+        let input: u32 = rand::random();
+
+        for class in [Class::ELF32, Class::ELF64] {
+            let mut bytes = build_elf(
+                class,
+                0,
+                0x100,
+                &[
+                    (0x100, text.clone(), 0x14),
+                    (0x700, input.to_le_bytes().to_vec(), 8),
+                ],
+            );
+            add_symbols(
+                &mut bytes,
+                class,
+                &[
+                    ("_start", 0x100, 8, 2),
+                    ("loop", 0x104, 0, 0),
+                    ("main", 0x108, 12, 2),
+                    ("input", 0x700, 4, 1),
+                    ("result", 0x704, 4, 1),
+                    // section symbols are skipped
+                    ("", 0x100, 0, 3),
+                ],
+            );
+            let elf = ELF::parse(&bytes).map_err(|_| ())?;
+            assert_eq!(Some(SHT_SYMTAB), elf.section(".symtab").map(|s| s.kind));
+            let symbols = SymbolMap::from_elf(&elf).map_err(|_| ())?;
+            assert_eq!(5, symbols.len());
+            assert_eq!(Some(0x108), symbols.address_of("main"));
+            assert_eq!(
+                Some(SymbolKind::Object),
+                symbols.lookup("result").map(|s| s.kind)
+            );
+            assert_eq!("main+0x4", symbols.describe(0x10c));
+            assert_eq!("_start+0x4", symbols.describe(0x104));
+            assert_eq!("input", symbols.describe(0x700));
+            assert_eq!("0x800", symbols.describe(0x800));
+            assert_eq!(None, symbols.resolve(0x80));
+        }
+
+        let mut machine = RISCMachine::default();
+        let mut bytes = build_elf(
+            Class::ELF32,
+            0,
+            0x100,
+            &[
+                (0x100, text, 0x14),
+                (0x700, input.to_le_bytes().to_vec(), 8),
+            ],
+        );
+        add_symbols(
+            &mut bytes,
+            Class::ELF32,
+            &[("main", 0x108, 12, 2), ("result", 0x704, 4, 1)],
+        );
+        machine
+            .load_elf(&ELF::parse(&bytes).map_err(|_| ())?)
+            .map_err(|_| ())?;
+        assert_eq!(Ok(0), machine.read_symbol("result", Width::Word));
+        assert_eq!(Ok(None), machine.run_until_return("main"));
+        assert_eq!(0x104, machine.program_counter);
+        assert_eq!(Ok(input), machine.read_symbol("result", Width::Word));
+        assert_eq!(Err(()), machine.read_symbol("missing", Width::Word));
+        Ok(())
+    }
+
+    // A line number program unit, where the header runs from the minimum
+    // instruction length to the end of the file names
+    fn line_unit(version: u16, header: &[u8], program: &[u8]) -> Vec<u8> {
+        let mut body = version.to_le_bytes().to_vec();
+        if version >= 5 {
+            // the address and segment selector sizes
+            body.extend([4, 0]);
+        }
+        body.extend((header.len() as u32).to_le_bytes());
+        body.extend(header);
+        body.extend(program);
+        let mut unit = (body.len() as u32).to_le_bytes().to_vec();
+        unit.extend(body);
+        unit
+    }
+
+    // The minimum instruction length, maximum operations, default is_stmt,
+    // line base, line range, opcode base and standard opcode lengths
+    const LINE_PARAMETERS: [u8; 18] = [1, 1, 1, 0xfb, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+    fn debug_line_v4() -> Vec<u8> {
+        let mut header = LINE_PARAMETERS.to_vec();
+        header.extend(b"src\0\0");
+        header.extend(b"main.c\0\x01\0\0util.h\0\0\0\0\0");
+        let program = [
+            // set the address to 0x100 and advance to line 42
+            &[0, 5, 2, 0x00, 0x01, 0, 0, 3, 41, 1][..],
+            // a special opcode advancing the address by 4 and the line by 1
+            &[75],
+            // file 2, column 7, address 0x10c and line 10
+            &[4, 2, 5, 7, 2, 8, 3, 0x5f, 1],
+            // end the sequence at 0x110
+            &[2, 4, 0, 1, 1],
+        ]
+        .concat();
+        line_unit(4, &header, &program)
+    }
+
+    #[test]
+    fn it_can_parse_dwarf_line_tables() -> Result<(), ()> {
+        let mut header = LINE_PARAMETERS.to_vec();
+        // the directories are paths in .debug_line_str
+        header.extend([1, 1, 0x1f, 2, 0, 0, 0, 0, 11, 0, 0, 0]);
+        // the files are an inline path, a directory index and an MD5
+        header.extend([3, 1, 0x08, 2, 0x0f, 5, 0x1e, 1]);
+        header.extend(b"fib.c\0\x01");
+        header.extend(rand::random::<[u8; 16]>());
+        let program = [0, 5, 2, 0x00, 0x02, 0, 0, 4, 0, 3, 6, 1, 2, 4, 0, 1, 1];
+        let debug_line = [debug_line_v4(), line_unit(5, &header, &program)].concat();
+
+        let table = LineTable::parse(&debug_line, b"/home/user\0lib\0", &[]).map_err(|_| ())?;
+        assert!(!table.is_empty());
+        assert_eq!(
+            Some(&Location {
+                path: "src/main.c".to_string(),
+                line: 42,
+                column: 0
+            }),
+            table.lookup(0x100)
+        );
+        assert_eq!(
+            Some("main.c:43".to_string()),
+            table.lookup(0x108).map(|location| location.to_string())
+        );
+        assert_eq!(
+            Some(("util.h", 10, 7)),
+            table.lookup(0x10c).map(|location| (
+                location.path.as_str(),
+                location.line,
+                location.column
+            ))
+        );
+        assert_eq!(None, table.lookup(0x110));
+        assert_eq!(None, table.lookup(0xfc));
+        assert_eq!(
+            Some(("lib/fib.c", 7)),
+            table
+                .lookup(0x202)
+                .map(|location| (location.path.as_str(), location.line))
+        );
+
+        assert_eq!(
+            Err(ELFError::UnsupportedDWARFVersion(6)),
+            LineTable::parse(&line_unit(6, &header, &program), &[], &[])
+        );
+        assert!(LineTable::parse(&debug_line[..debug_line.len() - 1], &[], &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_describe_addresses_with_source_lines() -> Result<(), ()> {
+        let text: Vec<u8> = (0..4)
+            .flat_map(|_| u32::from(CPUInstruction::WFI).to_le_bytes())
+            .collect();
+        let mut symbols = vec![0; 16];
+        for value in [1u32, 0x100, 0x10] {
+            symbols.extend(value.to_le_bytes());
+        }
+        symbols.extend([2, 0, 1, 0]);
+        let mut bytes = build_elf(Class::ELF32, 0, 0x100, &[(0x100, text, 0x10)]);
+        add_sections(
+            &mut bytes,
+            Class::ELF32,
+            vec![
+                (".debug_line", SHT_PROGBITS, debug_line_v4(), 0),
+                (".symtab", SHT_SYMTAB, symbols, 3),
+                (".strtab", SHT_STRTAB, b"\0main\0".to_vec(), 0),
+            ],
+        );
+
+        let mut machine = RISCMachine::default();
+        machine
+            .load_elf(&ELF::parse(&bytes).map_err(|_| ())?)
+            .map_err(|_| ())?;
+        assert_eq!("main (main.c:42)", machine.describe(0x100));
+        assert_eq!("main+0x4 (main.c:43)", machine.describe(0x104));
+        assert_eq!("main+0xc (util.h:10)", machine.describe(0x10c));
+        assert_eq!("0x200", machine.describe(0x200));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_load_intel_hex() -> Result<(), ()> {
+        let mut memory = Memory::default();
+        let text = ":0400100001020304E2\n\
+                    :020000040001F9\n\
+                    :02000000AABB99\n\
+                    :0400000500000100F6\n\
+                    :00000001FF\n";
+        assert_eq!(Ok(Some(0x100)), ihex::load(&mut memory, text));
+        assert_eq!(Ok(vec![1, 2, 3, 4]), memory.read_range(0x10, 4));
+        assert_eq!(Ok(vec![0xaa, 0xbb]), memory.read_range(0x1_0000, 2));
+
+        assert_eq!(
+            Err(ImageError::Checksum(2)),
+            ihex::load(&mut memory, ":020000040001F9\n:0400100001020304E3\n")
+        );
+        assert_eq!(
+            Err(ImageError::Syntax(1)),
+            ihex::load(&mut memory, ":0400100001020304\n")
+        );
+        assert_eq!(
+            Err(ImageError::UnsupportedRecord(1)),
+            ihex::load(&mut memory, ":00000006FA\n")
+        );
+        assert_eq!(
+            Err(ImageError::OutOfRange(1)),
+            ihex::load(&mut Memory::new(0x10), ":0400100001020304E2\n")
+        );
+
+        let mut machine = RISCMachine::default();
+        machine.load_ihex(text).map_err(|_| ())?;
+        assert_eq!(0x100, machine.program_counter);
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_load_srec() -> Result<(), ()> {
+        let mut memory = Memory::default();
+        let text = "S00600004844521B\n\
+                    S107001001020304DE\n\
+                    S20601000055663D\n\
+                    S5030002FA\n\
+                    S9030100FB\n";
+        assert_eq!(Ok(Some(0x100)), srec::load(&mut memory, text));
+        assert_eq!(Ok(vec![1, 2, 3, 4]), memory.read_range(0x10, 4));
+        assert_eq!(Ok(vec![0x55, 0x66]), memory.read_range(0x1_0000, 2));
+
+        assert_eq!(
+            Err(ImageError::Checksum(1)),
+            srec::load(&mut memory, "S107001001020304DF\n")
+        );
+        assert_eq!(
+            Err(ImageError::Syntax(2)),
+            srec::load(&mut memory, "S107001001020304DE\nS5030002FA\n")
+        );
+        assert_eq!(
+            Err(ImageError::UnsupportedRecord(1)),
+            srec::load(&mut memory, "S4030000FC\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_export_hex_and_srec() -> Result<(), ()> {
+        let mut rng = rand::thread_rng();
+        for (address, length) in [(0x20, 40), (0xfff8, 0x20), (0x1_0000, 0x100)] {
+            let mut memory = Memory::default();
+            let data: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            memory.load_slice(address, &data)?;
+            let start = Some(rng.gen_range(0..0x10_0000));
+
+            let text = ihex::write(&memory, address, length, start)?;
+            let mut loaded = Memory::default();
+            assert_eq!(Ok(start), ihex::load(&mut loaded, &text));
+            assert_eq!(Ok(data.clone()), loaded.read_range(address, length));
+
+            let text = srec::write(&memory, address, length, start)?;
+            let mut loaded = Memory::default();
+            assert_eq!(Ok(start), srec::load(&mut loaded, &text));
+            assert_eq!(Ok(data), loaded.read_range(address, length));
+        }
+        let text = srec::write(&Memory::default(), 0x10, 4, None)?;
+        assert_eq!(
+            "S0030000FC\nS107001000000000E8\nS5030001FB\nS9030000FC\n",
+            text
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_load_readmem_images() -> Result<(), ()> {
+        let mut memory = Memory::default();
+        let text = "// a comment\n\
+                    dead_beef 0000_0001 /* a comment\n\
+                    spanning lines */ 12\n\
+                    @10 ffffffff\n";
+        let format = Format::hex(Width::Word, Endianness::Little);
+        assert_eq!(Ok(()), readmem::load(&mut memory, text, 0x100, format));
+        assert_eq!(Ok(0xdead_beef), memory.read_u32(0x100, Endianness::Little));
+        assert_eq!(Ok(1), memory.read_u32(0x104, Endianness::Little));
+        assert_eq!(Ok(0x12), memory.read_u32(0x108, Endianness::Little));
+        assert_eq!(Ok(u32::MAX), memory.read_u32(0x140, Endianness::Little));
+
+        let format = Format::binary(Width::Half, Endianness::Big);
+        readmem::load(&mut memory, "0000000100000010", 0x200, format).map_err(|_| ())?;
+        assert_eq!(Ok(vec![1, 2]), memory.read_range(0x200, 2));
+
+        let format = Format::hex(Width::Byte, Endianness::Little);
+        assert_eq!(
+            Err(ImageError::Syntax(2)),
+            readmem::load(&mut memory, "00\n100", 0, format)
+        );
+        assert_eq!(
+            Err(ImageError::Syntax(1)),
+            readmem::load(&mut memory, "0g", 0, format)
+        );
+        assert_eq!(
+            Err(ImageError::OutOfRange(1)),
+            readmem::load(&mut Memory::new(0x10), "@10 00", 0, format)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_export_readmem_images() -> Result<(), ()> {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..0x40).map(|_| rng.gen()).collect();
+        let mut memory = Memory::default();
+        memory.load_slice(0x100, &data)?;
+
+        for width in [Width::Byte, Width::Half, Width::Word, Width::Double] {
+            for endianness in [Endianness::Little, Endianness::Big] {
+                for format in [
+                    Format::hex(width, endianness),
+                    Format::binary(width, endianness),
+                ] {
+                    let text = readmem::write(&memory, 0x100, data.len(), format)?;
+                    assert_eq!(data.len() / width.bytes() as usize, text.lines().count());
+                    let mut loaded = Memory::default();
+                    readmem::load(&mut loaded, &text, 0x100, format).map_err(|_| ())?;
+                    assert_eq!(Ok(data.clone()), loaded.read_range(0x100, data.len()));
+                }
+            }
+        }
+
+        memory.write_u32(0x0, 0x1234_5678, Endianness::Little)?;
+        let format = Format::hex(Width::Word, Endianness::Big);
+        assert_eq!(
+            Ok("78563412\n".to_string()),
+            readmem::write(&memory, 0, 4, format)
+        );
+        assert_eq!(Err(()), readmem::write(&memory, 0, 6, format));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_build_machines_from_binary_images() -> Result<(), ()> {
+        let x1 = u5::new(1);
+        let value: u32 = rand::thread_rng().gen_range(0..0x1000);
+        let image: Vec<u8> = [
+            CPUInstruction::LW {
+                rd: x1,
+                rs1: u5::new(0),
+                imm: 0x40c,
+            },
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x1,
+                imm: 0x7fc,
+            },
+            CPUInstruction::WFI,
+        ]
+        .into_iter()
+        .flat_map(|instruction| u32::from(instruction).to_le_bytes())
+        .chain(value.to_le_bytes())
+        .collect();
+
+        let mut machine = RISCMachine::builder().binary(&image).base(0x400).build()?;
+        assert_eq!(0x400, machine.program_counter);
+        machine.tick()?;
+        machine.tick()?;
+        assert_eq!(
+            Ok(value),
+            machine.memory.read_u32(0x7fc, Endianness::Little)
+        );
+
+        let path = std::env::temp_dir().join(format!("riscv-sim-{}.bin", std::process::id()));
+        std::fs::write(&path, &image).map_err(|_| ())?;
+        let machine = RISCMachine::builder()
+            .memory(Memory::new(0x800))
+            .file(&path)
+            .map_err(|_| ())?
+            .base(0x400)
+            .entry(0x404)
+            .build();
+        std::fs::remove_file(&path).map_err(|_| ())?;
+        assert_eq!(0x404, machine?.program_counter);
+
+        // the image must fit in memory
+        let builder = RISCMachine::builder().memory(Memory::new(0x800));
+        assert!(builder.binary(&image).base(0x7f8).build().is_err());
+        let builder = RISCMachine::builder().memory(Memory::new(0x800));
+        assert!(builder.binary(&image).entry(0x800).build().is_err());
+        Ok(())
+    }
+
+    // A machine counting in a loop, storing the count to memory and the
+    // framebuffer, with some state in each of its devices
+    fn counting_machine() -> Result<RISCMachine, ()> {
+        let (x1, x2, x3) = (u5::new(1), u5::new(2), u5::new(3));
+        let program = [
+            CPUInstruction::ADD {
+                rd: x1,
+                rs1: x1,
+                rs2: x2,
+            },
+            CPUInstruction::SW {
+                rs1: u5::new(0),
+                rs2: x1,
+                imm: 0x700,
+            },
+            CPUInstruction::SW {
+                rs1: x3,
+                rs2: x1,
+                imm: 0,
+            },
+            CPUInstruction::JAL {
+                rd: u5::new(0),
+                imm: -12i32 as u32,
+            },
+        ];
+        let mut machine = RISCMachine::default();
+        for (i, instruction) in program.into_iter().enumerate() {
+            machine
+                .memory
+                .write_u32(4 * i as u32, instruction.into(), Endianness::Little)?;
+        }
+        machine.registry.set(x2, 1);
+        machine.registry.set(x3, FRAMEBUFFER_BASE);
+        machine.interrupt_model = InterruptModel::CLIC(CLIC::default());
+        machine.pma.add(0, 0x1000, PMA_RAM)?;
+
+        let framebuffer = Framebuffer::new(2, 2, PixelFormat::XRGB8888);
+        machine
+            .bus
+            .attach(FRAMEBUFFER_BASE, framebuffer.size(), framebuffer)?;
+        let mut gpio = GPIO::default();
+        gpio.schedule_input(15, 3, true);
+        machine.bus.attach(GPIO_BASE, GPIO_SIZE, gpio)?;
+        let mut uart = UART::default();
+        uart.receive(b"hello");
+        machine.bus.attach(UART_BASE, UART_SIZE, uart)?;
+        Ok(machine)
+    }
+
+    #[test]
+    fn it_can_save_and_restore_snapshots() -> Result<(), ()> {
+        let ticks = rand::thread_rng().gen_range(1..20);
+        let mut machine = counting_machine()?;
+        for _ in 0..ticks {
+            machine.tick()?;
+        }
+        let snapshot = machine.snapshot();
+        // only the non-zero pages of memory are saved
+        assert!(snapshot.len() < 2 * crate::memory::PAGE_SIZE);
+        for _ in 0..20 {
+            machine.tick()?;
+        }
+
+        let mut restored = counting_machine()?;
+        assert_eq!(Ok(()), restored.restore_snapshot(&snapshot));
+        assert_eq!(snapshot, restored.snapshot());
+        for _ in 0..20 {
+            restored.tick()?;
+        }
+        assert_eq!(machine.snapshot(), restored.snapshot());
+        assert_eq!(machine.program_counter, restored.program_counter);
+        assert_eq!(
+            machine.memory.read_u32(0x700, Endianness::Little),
+            restored.memory.read_u32(0x700, Endianness::Little)
+        );
+        let gpio = restored.bus.device::<GPIO>(GPIO_BASE).ok_or(())?;
+        assert!(gpio.level(3));
+
+        let path = std::env::temp_dir().join(format!("riscv-sim-{}.snap", std::process::id()));
+        machine.save_snapshot(&path).map_err(|_| ())?;
+        let mut loaded = counting_machine()?;
+        let result = loaded.load_snapshot(&path);
+        std::fs::remove_file(&path).map_err(|_| ())?;
+        assert_eq!(Ok(()), result);
+        assert_eq!(machine.snapshot(), loaded.snapshot());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_snapshots() -> Result<(), ()> {
+        let snapshot = counting_machine()?.snapshot();
+        let mut machine = counting_machine()?;
+        assert_eq!(
+            Err(SnapshotError::BadMagic),
+            machine.restore_snapshot(b"RVSNAP")
+        );
+
+        let mut newer = snapshot.clone();
+        newer[8..12].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1)),
+            machine.restore_snapshot(&newer)
+        );
+        assert_eq!(
+            Err(SnapshotError::Truncated),
+            machine.restore_snapshot(&snapshot[..snapshot.len() - 1])
+        );
+        let mut extended = snapshot.clone();
+        extended.push(0);
+        assert_eq!(
+            Err(SnapshotError::Invalid),
+            machine.restore_snapshot(&extended)
+        );
+        assert_eq!(
+            Err(SnapshotError::MissingDevice(FRAMEBUFFER_BASE)),
+            RISCMachine::default().restore_snapshot(&snapshot)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_write_core_dumps() -> Result<(), ()> {
+        let mut rng = rand::thread_rng();
+        let x1 = u5::new(1);
+        let mut machine = RISCMachine::default();
+        machine.memory.write_u32(
+            0,
+            CPUInstruction::ADD {
+                rd: x1,
+                rs1: x1,
+                rs2: x1,
+            }
+            .into(),
+            Endianness::Little,
+        )?;
+        // not a valid instruction
+        machine.memory.write_u32(4, u32::MAX, Endianness::Little)?;
+        let data: Vec<u8> = (0..0x1800).map(|_| rng.gen()).collect();
+        machine.memory.load_slice(0x3800, &data)?;
+        for i in 1..32 {
+            machine
+                .registry
+                .set(u5::new(i), rng.gen_range(0..0x1000_0000));
+        }
+
+        let path = std::env::temp_dir().join(format!("riscv-sim-{}.core", std::process::id()));
+        let result = machine.run_with_core_dump(&|_| false, &path);
+        let core = std::fs::read(&path).map_err(|_| ());
+        std::fs::remove_file(&path).map_err(|_| ())?;
+        assert_eq!(Err(()), result);
+        assert_eq!(4, machine.program_counter);
+
+        let core = ELF::parse(&core?).map_err(|_| ())?;
+        assert_eq!(ET_CORE, core.kind);
+        assert_eq!(Class::ELF32, core.class);
+        let note = core.segments.first().ok_or(())?;
+        assert_eq!(PT_NOTE, note.kind);
+        let note = core.segment_data(note);
+        assert_eq!(NT_PRSTATUS.to_le_bytes(), note[8..12]);
+        assert_eq!(b"CORE\0", &note[12..17]);
+        let status = &note[20..];
+        assert_eq!(SIGILL.to_le_bytes(), status[..4]);
+        for i in 0..32 {
+            let offset = PRSTATUS_REGISTERS + 4 * i;
+            let value = u32::from_le_bytes(status[offset..offset + 4].try_into().unwrap());
+            match i {
+                0 => assert_eq!(machine.program_counter, value),
+                _ => assert_eq!(machine.registry.get(u5::new(i as u8)), value),
+            }
+        }
+
+        // the non-zero pages, with neighbouring pages merged
+        let segments: Vec<_> = core.segments[1..].iter().collect();
+        assert!(segments.iter().all(|segment| segment.kind == PT_LOAD));
+        let ranges: Vec<_> = segments
+            .iter()
+            .map(|segment| (segment.virtual_address, segment.memory_size))
+            .collect();
+        assert_eq!(vec![(0, 0x1000), (0x3000, 0x2000)], ranges);
+        for segment in segments {
+            let address = segment.virtual_address as u32;
+            let length = segment.file_size as usize;
+            assert_eq!(
+                Ok(core.segment_data(segment).to_vec()),
+                machine.memory.read_range(address, length)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_missing_plic_sources_and_contexts() {
+        let mut plic = PLIC::new(8, 1);
+        for source in [0, 9, usize::MAX] {
+            plic.set_priority(source, 3);
+            plic.set_enabled(0, source, true);
+            assert!(!plic.is_pending(source));
+            assert_eq!(0, plic.priority(source));
+            assert!(!plic.is_enabled(0, source));
+        }
+
+        plic.set_enabled(1, 4, true);
+        plic.set_threshold(1, 3);
+        plic.complete(1, 4);
+        assert!(!plic.is_enabled(1, 4));
+        assert_eq!(0, plic.threshold(1));
+        assert!(!plic.interrupt_pending(1));
+        assert_eq!(0, plic.claim(1));
+    }
+
+    #[test]
+    fn it_ignores_missing_gpio_pins() {
+        let mut gpio = GPIO::default();
+        gpio.set_input(GPIO_PINS, true);
+        gpio.schedule_input(1, usize::MAX, true);
+        gpio.schedule_input(1, 3, true);
+        assert!(!gpio.level(GPIO_PINS));
+
+        gpio.tick(&mut Memory::default());
+        assert_eq!(1 << 3, gpio.levels());
+        assert!(gpio.level(3));
+    }
+
+    #[test]
+    fn it_reports_readmem_addresses_out_of_range() {
+        let mut memory = Memory::default();
+        for (text, base) in [
+            ("@ffffffffffffffff 00", 0),
+            ("\n@8000000000000000 00", 0),
+            ("@ffffffff 00 00", 0),
+            ("@1 00", u32::MAX),
+        ] {
+            let format = Format::hex(Width::Word, Endianness::Little);
+            assert_eq!(
+                Err(ImageError::OutOfRange(text.lines().count())),
+                readmem::load(&mut memory, text, base, format)
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_snapshots_of_a_different_memory_size() -> Result<(), ()> {
+        let mut memory = Memory::new(0x100);
+        memory.write_u8(0x10, rand::thread_rng().gen())?;
+        let mut encoder = Encoder::new();
+        memory.save(&mut encoder);
+        let mut bytes = encoder.into_bytes();
+
+        let mut restored = Memory::new(0x100);
+        assert_eq!(Ok(()), restored.restore(&mut Decoder::new(&bytes)));
+        assert_eq!(memory.read_u8(0x10), restored.read_u8(0x10));
+
+        // the size is checked before anything is allocated
+        for size in [0x80_u64, 0x200, 1 << 40, u64::MAX] {
+            bytes[..8].copy_from_slice(&size.to_le_bytes());
+            assert_eq!(
+                Err(SnapshotError::Invalid),
+                restored.restore(&mut Decoder::new(&bytes))
+            );
+        }
+
+        let snapshot = counting_machine()?.snapshot();
+        let mut machine = counting_machine()?;
+        machine.memory = Memory::sparse(0x1000);
+        assert_eq!(
+            Err(SnapshotError::Invalid),
+            machine.restore_snapshot(&snapshot)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_snapshots_of_invalid_queues() {
+        let encode = |size: u16, ready: bool| {
+            let mut encoder = Encoder::new();
+            encoder.u16(size);
+            encoder.bool(ready);
+            [0x1000, 0x2000, 0x3000]
+                .into_iter()
+                .for_each(|area| encoder.u64(area));
+            encoder.u16(5);
+            encoder.into_bytes()
+        };
+
+        let mut queue = Queue::default();
+        assert_eq!(Ok(()), queue.restore(&mut Decoder::new(&encode(8, true))));
+        assert_eq!(
+            (8, true, 0x2000),
+            (queue.size, queue.ready, queue.driver_area)
+        );
+        assert_eq!(
+            Ok(()),
+            Queue::default().restore(&mut Decoder::new(&encode(0, false)))
+        );
+
+        // a ready queue of size zero would divide by zero when popped
+        for (size, ready) in [(0, true), (3, false), (2 * QUEUE_SIZE_MAX, true)] {
+            assert_eq!(
+                Err(SnapshotError::Invalid),
+                queue.restore(&mut Decoder::new(&encode(size, ready)))
+            );
+            assert_eq!(8, queue.size);
+        }
+    }
+
+    #[test]
+    fn it_can_restore_exit_codes_from_snapshots() -> Result<(), ()> {
+        let tohost = 0x8000_1000;
+        let code = rand::thread_rng().gen_range(1..=0xffff);
+        for (base, value) in [
+            (TEST_FINISHER_BASE, FINISHER_FAIL | (code << 16)),
+            (tohost, (code << 1) | 1),
+        ] {
+            let exit_machine = || -> Result<RISCMachine, ()> {
+                let mut machine = exit_program(base, value);
+                machine.bus.attach(
+                    TEST_FINISHER_BASE,
+                    TEST_FINISHER_SIZE,
+                    TestFinisher::default(),
+                )?;
+                machine.bus.attach(tohost, HTIF_SIZE, HTIF::default())?;
+                Ok(machine)
+            };
+            let mut machine = exit_machine()?;
+            let before = machine.snapshot();
+            assert_eq!(Some(code), machine.run(&|_| false)?);
+            let after = machine.snapshot();
+
+            // an earlier snapshot does not keep the exit code
+            assert_eq!(Ok(()), machine.restore_snapshot(&before));
+            assert_eq!(None, machine.bus.exit_code());
+            assert_eq!(0, machine.program_counter);
+
+            let mut restored = exit_machine()?;
+            assert_eq!(Ok(()), restored.restore_snapshot(&after));
+            assert_eq!(Some(code), restored.bus.exit_code());
+            assert_eq!(Some(code), restored.run(&|_| false)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_sections_beyond_the_address_space() -> Result<(), ()> {
+        for (source, base, line, column, end) in [
+            ("nop", 0xffff_fffe, 1, 1, 0x1_0000_0004),
+            (".space 0xfffffffe\n.data\n.word 1", 0, 3, 1, 0x1_0000_0004),
+            (".space 0xfffffff0", 0x100, 1, 8, 0x1_0000_00f0),
+            ("nop\n.data", 0xffff_fffc, 2, 1, 0x1_0000_0000),
+        ] {
+            assert_eq!(
+                Err(AssemblerError {
+                    line,
+                    column,
+                    kind: AssemblerErrorKind::OutOfRange(end),
+                }),
+                assembler::assemble(source, base).map(|program| program.words())
+            );
+        }
+
+        // a section may end at the very top of memory
+        let program = assembler::assemble("nop\n.data\n.byte 1", 0xffff_fff8).map_err(|_| ())?;
+        assert_eq!(0xffff_fff8, program.entry);
+        assert_eq!(
+            Some(0xffff_fffc),
+            program.section(".data").map(|data| data.address)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_execute_base_integer() -> Result<(), ()> {
+        let x = |i| u5::new(i);
+        let (x0, x1, x2) = (x(0), x(1), x(2));
+        let program = [
+            CPUInstruction::ADDI {
+                rd: x(3),
+                rs1: x1,
+                imm: 7,
+            },
+            CPUInstruction::ADDI {
+                rd: x(4),
+                rs1: x2,
+                imm: -4i32 as u32,
+            },
+            // the immediate is sign extended, then compared unsigned
+            CPUInstruction::SLTIU {
+                rd: x(5),
+                rs1: x2,
+                imm: -1i32 as u32,
+            },
+            CPUInstruction::SLTIU {
+                rd: x(6),
+                rs1: x1,
+                imm: 5,
+            },
+            CPUInstruction::XORI {
+                rd: x(7),
+                rs1: x2,
+                imm: -1i32 as u32,
+            },
+            CPUInstruction::SLTU {
+                rd: x(8),
+                rs1: x2,
+                rs2: x1,
+            },
+            CPUInstruction::SLTU {
+                rd: x(9),
+                rs1: x1,
+                rs2: x2,
+            },
+            CPUInstruction::LUI {
+                rd: x(10),
+                imm: 0x8000_0000,
+            },
+            CPUInstruction::AUIPC {
+                rd: x(11),
+                imm: 0x1000,
+            },
+            // -5 < 3 when compared signed, so both branches are taken
+            CPUInstruction::BLT {
+                rs1: x1,
+                rs2: x2,
+                imm: 8,
+            },
+            CPUInstruction::ADDI {
+                rd: x(12),
+                rs1: x0,
+                imm: 1,
+            },
+            CPUInstruction::BGE {
+                rs1: x2,
+                rs2: x1,
+                imm: 8,
+            },
+            CPUInstruction::ADDI {
+                rd: x(13),
+                rs1: x0,
+                imm: 1,
+            },
+            CPUInstruction::BGE {
+                rs1: x1,
+                rs2: x2,
+                imm: 8,
+            },
+            CPUInstruction::BLT {
+                rs1: x2,
+                rs2: x1,
+                imm: 8,
+            },
+        ];
+
+        let mut memory = Memory::default();
+        for (i, instruction) in program.iter().enumerate() {
+            memory.set_four_byte(u20::new(4 * i as u32), (*instruction).into());
+        }
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.registry.set(x1, -5i32 as u32);
+        machine.registry.set(x2, 3);
+        for _ in 0..13 {
+            machine.tick()?;
+        }
+
+        assert_eq!(60, machine.program_counter);
+        let expected = [
+            2,
+            u32::MAX,
+            1,
+            0,
+            0xffff_fffc,
+            1,
+            0,
+            0x8000_0000,
+            0x1020,
+            0,
+            0,
+        ];
+        for (i, value) in expected.into_iter().enumerate() {
+            assert_eq!(value, machine.registry.get(x(3 + i as u8)), "x{}", 3 + i);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_resets_virtio_queues_with_overflowing_rings() -> Result<(), ()> {
+        let mut machine = RISCMachine::default();
+        machine
+            .memory
+            .set_four_byte(u20::new(0), CPUInstruction::WFI.into());
+        machine.bus.attach(
+            VIRTIO_BASE,
+            VIRTIO_SIZE,
+            VirtioMMIO::new(Console::default()),
+        )?;
+        let driver = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        machine.host_write(VIRTIO_BASE + 0x24, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x20, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver)?;
+        machine.host_write(VIRTIO_BASE + 0x30, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x38, Width::Word, 8)?;
+        machine.host_write(VIRTIO_BASE + 0x90, Width::Word, u32::MAX)?;
+        machine.host_write(VIRTIO_BASE + 0x94, Width::Word, u32::MAX)?;
+        machine.host_write(VIRTIO_BASE + 0x44, Width::Word, 1)?;
+        machine.host_write(VIRTIO_BASE + 0x70, Width::Word, driver | STATUS_DRIVER_OK)?;
+        machine.host_write(VIRTIO_BASE + 0x50, Width::Word, 1)?;
+        machine.tick()?;
+        assert_eq!(
+            Ok(driver | STATUS_DRIVER_OK | STATUS_DEVICE_NEEDS_RESET),
+            machine.host_read(VIRTIO_BASE + 0x70, Width::Word)
+        );
+
+        // the descriptor table and used ring are checked in the same way
+        let mut memory = Memory::default();
+        memory.load_slice(0x102, &[1, 0])?;
+        let mut queue = Queue::default();
+        queue.size = 8;
+        queue.ready = true;
+        queue.descriptor_table = u64::MAX - 8;
+        queue.driver_area = 0x100;
+        queue.device_area = u64::MAX - 2;
+        assert_eq!(Err(()), queue.pop(&memory));
+        assert_eq!(Err(()), queue.push(&mut memory, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn it_correctly_mirrors_mstatus_in_clic_mcause() -> Result<(), ()> {
+        let mcause = u12::new(csr::MCAUSE);
+        let mut memory = Memory::default();
+        memory.set_four_byte(
+            u20::new(0),
+            CPUInstruction::CSRRW {
+                rd: u5::new(1),
+                rs1: u5::new(2),
+                csr: mcause,
+            }
+            .into(),
+        );
+        memory.set_four_byte(
+            u20::new(4),
+            CPUInstruction::CSRRS {
+                rd: u5::new(3),
+                rs1: u5::new(0),
+                csr: mcause,
+            }
+            .into(),
+        );
+        let mut machine = RISCMachine {
+            memory,
+            interrupt_model: InterruptModel::CLIC(CLIC::new(32)),
+            ..Default::default()
+        };
+        machine.csr.mtvec = csr::MTVEC_CLIC;
+        machine.csr.mstatus = csr::MSTATUS_MPP | csr::MSTATUS_MPIE;
+        machine.csr.mcause = (0x12 << 16) | 11;
+        // mpp is clear, mpie is set and mpil is 0x34
+        let value = (1 << 31) | (1 << 27) | (0x34 << 16) | 7;
+        machine.registry.set(u5::new(2), value);
+
+        machine.tick()?;
+        assert_eq!(0x3812_000b, machine.registry.get(u5::new(1)));
+        assert_eq!(csr::MSTATUS_MPIE, machine.csr.mstatus);
+
+        machine.tick()?;
+        assert_eq!(value, machine.registry.get(u5::new(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_elf_headers_beyond_the_address_space() {
+        let segments = [(0x100, vec![0; 4], 4), (0x104, vec![0; 4], 4)];
+        let bytes = build_elf(Class::ELF64, 0, 0x100, &segments);
+
+        let mut program_headers = bytes.clone();
+        program_headers[32..40].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        assert_eq!(Err(ELFError::Truncated), ELF::parse(&program_headers));
+
+        let mut section_headers = bytes;
+        section_headers[40..48].copy_from_slice(&u64::MAX.to_le_bytes());
+        section_headers[58..60].copy_from_slice(&64u16.to_le_bytes());
+        section_headers[60..62].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(Err(ELFError::Truncated), ELF::parse(&section_headers));
+    }
+
+    #[test]
+    fn it_ignores_missing_clic_interrupts() {
+        let mut clic = CLIC::new(32);
+        for id in [32, usize::MAX] {
+            clic.set_attributes(id, CLICINTATTR_EDGE);
+            clic.set_pending(id, true);
+            clic.set_enabled(id, true);
+            clic.set_control(id, 0xff);
+            clic.acknowledge(id);
+            assert!(!clic.is_pending(id));
+            assert!(!clic.is_enabled(id));
+            assert_eq!(0, clic.attributes(id));
+            assert_eq!(0, clic.control(id));
+            assert!(!clic.is_vectored(id));
+        }
+        assert_eq!(None, clic.pending_interrupt(0));
+    }
+
+    #[test]
+    fn it_reports_overflowing_expressions() {
+        let mut source = ".equ v0, 1\n".to_string();
+        for i in 1..64 {
+            source += &format!(".equ v{i}, v{} + v{}\n", i - 1, i - 1);
+        }
+        assert_eq!(
+            Err(AssemblerError {
+                line: 64,
+                column: 17,
+                kind: AssemblerErrorKind::OutOfRange(1 << 62),
+            }),
+            assembler::assemble(&source, 0).map(|program| program.words())
+        );
+    }
+
+    #[test]
+    fn it_correctly_handles_symbols_beyond_the_address_space() {
+        let mut bytes = build_elf(Class::ELF64, 0, 0x100, &[(0x100, vec![0; 4], 4)]);
+        add_symbols(
+            &mut bytes,
+            Class::ELF64,
+            &[("main", 0x100, 4, 2), ("huge", 0x200, u64::MAX, 1)],
+        );
+        let symbols = SymbolMap::from_elf(&ELF::parse(&bytes).unwrap()).unwrap();
+        assert_eq!("huge+0x100", symbols.describe(0x300));
+        assert_eq!("huge+0xfffffffffffffd00", symbols.describe(u64::MAX - 0xff));
+
+        // the string table of the symbols is placed at the end of the address
+        // space
+        let section_headers = u64::from_le_bytes(bytes[40..48].try_into().unwrap()) as usize;
+        let strings = section_headers + 2 * 64 + 24;
+        bytes[strings..strings + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            Err(ELFError::Truncated),
+            SymbolMap::from_elf(&ELF::parse(&bytes).unwrap())
+        );
+    }
+
+    #[test]
+    fn it_ignores_missing_framebuffer_pixels() {
+        let framebuffer = Framebuffer::new(2, 3, PixelFormat::RGB565);
+        assert_eq!(Some([0, 0, 0]), framebuffer.pixel(1, 2));
+        for (x, y) in [(2, 0), (0, 3), (u32::MAX, u32::MAX)] {
+            assert_eq!(None, framebuffer.pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn it_traps_undecodable_instructions() -> Result<(), ()> {
+        let instruction = u32::MAX;
+        let mut memory = Memory::default();
+        memory.set_four_byte(u20::new(0), instruction);
+        let mut machine = RISCMachine::default();
+        machine.load_memory(memory);
+        machine.csr.mtvec = 0x100;
+
+        machine.tick()?;
+        assert_eq!(0x100, machine.program_counter);
+        assert_eq!(Exception::IllegalInstruction.code(), machine.csr.mcause);
+        assert_eq!(instruction, machine.csr.mtval);
+        assert_eq!(0, machine.csr.mepc);
+        Ok(())
+    }
 }
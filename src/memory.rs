@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+
+use ux::u20;
+
+use crate::bus::{Device, Width};
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone)]
+enum Backing {
+    Dense(Vec<u8>),
+    // Pages are allocated on the first non-zero write, untouched pages read
+    // as zero
+    Sparse(BTreeMap<u32, Box<[u8; PAGE_SIZE]>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Memory {
+    size: usize,
+    backing: Backing,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::sparse(u32::from(u20::MAX) as usize)
+    }
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        Memory {
+            size,
+            backing: Backing::Dense(vec![0; size]),
+        }
+    }
+
+    pub fn sparse(size: usize) -> Self {
+        Memory {
+            size,
+            backing: Backing::Sparse(BTreeMap::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.backing, Backing::Sparse(_))
+    }
+
+    // The number of pages currently backed by an allocation
+    pub fn allocated_pages(&self) -> usize {
+        match &self.backing {
+            Backing::Dense(bytes) => bytes.len().div_ceil(PAGE_SIZE),
+            Backing::Sparse(pages) => pages.len(),
+        }
+    }
+
+    fn read_byte(&self, address: usize) -> u8 {
+        assert!(address < self.size, "memory access out of bounds");
+        match &self.backing {
+            Backing::Dense(bytes) => bytes[address],
+            Backing::Sparse(pages) => pages
+                .get(&((address / PAGE_SIZE) as u32))
+                .map_or(0, |page| page[address % PAGE_SIZE]),
+        }
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) {
+        assert!(address < self.size, "memory access out of bounds");
+        match &mut self.backing {
+            Backing::Dense(bytes) => bytes[address] = value,
+            Backing::Sparse(pages) => {
+                let number = (address / PAGE_SIZE) as u32;
+                if value == 0 && !pages.contains_key(&number) {
+                    return;
+                }
+                pages
+                    .entry(number)
+                    .or_insert_with(|| Box::new([0; PAGE_SIZE]))[address % PAGE_SIZE] = value;
+            }
+        }
+    }
+
+    // The index based accessors panic when out of range, while the typed
+    // accessors below return an error
+    pub fn set(&mut self, index: u20, value: u8) {
+        self.write_byte(u32::from(index) as usize, value);
+    }
+
+    pub fn get(&self, index: u20) -> u8 {
+        self.read_byte(u32::from(index) as usize)
+    }
+
+    // Words are stored little endian, matching the rest of the bus
+    pub fn set_four_byte(&mut self, index: u20, value: u32) {
+        let start = u32::from(index) as usize;
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_byte(start + i, byte);
+        }
+    }
+
+    pub fn get_aligned(&self, index: u20) -> u32 {
+        let start = u32::from(index) as usize;
+        (0..4).fold(0, |acc, i| {
+            acc | (u32::from(self.read_byte(start + i)) << (8 * i))
+        })
+    }
+
+    // The index and contents of each page holding a non-zero byte, where the
+    // last page is cut short if the size is not a whole number of pages
+    pub fn pages(&self) -> impl Iterator<Item = (usize, Vec<u8>)> + '_ {
+        (0..self.size.div_ceil(PAGE_SIZE))
+            .filter(|page| match &self.backing {
+                Backing::Dense(_) => true,
+                Backing::Sparse(pages) => pages.contains_key(&(*page as u32)),
+            })
+            .map(|page| {
+                let start = page * PAGE_SIZE;
+                let end = self.size.min(start + PAGE_SIZE);
+                let bytes = (start..end).map(|address| self.read_byte(address));
+                (page, bytes.collect::<Vec<u8>>())
+            })
+            .filter(|(_, bytes)| bytes.iter().any(|byte| *byte != 0))
+    }
+
+    // The start of the range if it lies within memory
+    fn check_range(&self, address: u32, length: usize) -> Result<usize, ()> {
+        let start = address as usize;
+        match start.checked_add(length) {
+            Some(end) if end <= self.size => Ok(start),
+            _ => Err(()),
+        }
+    }
+
+    // Bytes are returned in little endian order
+    fn read_array<const N: usize>(
+        &self,
+        address: u32,
+        endianness: Endianness,
+    ) -> Result<[u8; N], ()> {
+        let start = self.check_range(address, N)?;
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_byte(start + i);
+        }
+        if endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn write_array<const N: usize>(
+        &mut self,
+        address: u32,
+        mut bytes: [u8; N],
+        endianness: Endianness,
+    ) -> Result<(), ()> {
+        let start = self.check_range(address, N)?;
+        if endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.write_byte(start + i, byte);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read_u8(&self, address: u32) -> Result<u8, ()> {
+        self.read_array::<1>(address, Endianness::Little)
+            .map(|[byte]| byte)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read_u16(&self, address: u32, endianness: Endianness) -> Result<u16, ()> {
+        self.read_array(address, endianness).map(u16::from_le_bytes)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read_u32(&self, address: u32, endianness: Endianness) -> Result<u32, ()> {
+        self.read_array(address, endianness).map(u32::from_le_bytes)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read_u64(&self, address: u32, endianness: Endianness) -> Result<u64, ()> {
+        self.read_array(address, endianness).map(u64::from_le_bytes)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write_u8(&mut self, address: u32, value: u8) -> Result<(), ()> {
+        self.write_array(address, [value], Endianness::Little)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write_u16(
+        &mut self,
+        address: u32,
+        value: u16,
+        endianness: Endianness,
+    ) -> Result<(), ()> {
+        self.write_array(address, value.to_le_bytes(), endianness)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write_u32(
+        &mut self,
+        address: u32,
+        value: u32,
+        endianness: Endianness,
+    ) -> Result<(), ()> {
+        self.write_array(address, value.to_le_bytes(), endianness)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn write_u64(
+        &mut self,
+        address: u32,
+        value: u64,
+        endianness: Endianness,
+    ) -> Result<(), ()> {
+        self.write_array(address, value.to_le_bytes(), endianness)
+    }
+
+    // Copy bytes into memory starting at the address, failing without
+    // writing anything if they do not fit
+    #[allow(clippy::result_unit_err)]
+    pub fn load_slice(&mut self, address: u32, bytes: &[u8]) -> Result<(), ()> {
+        let start = self.check_range(address, bytes.len())?;
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_byte(start + i, *byte);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn read_range(&self, address: u32, length: usize) -> Result<Vec<u8>, ()> {
+        let start = self.check_range(address, length)?;
+        Ok((start..start + length)
+            .map(|address| self.read_byte(address))
+            .collect())
+    }
+}
+
+// Only pages holding a non-zero byte are saved
+impl Snapshot for Memory {
+    fn save(&self, encoder: &mut Encoder) {
+        let pages: Vec<(usize, Vec<u8>)> = self.pages().collect();
+        encoder.length(self.size);
+        encoder.bool(self.is_sparse());
+        encoder.length(pages.len());
+        for (page, bytes) in pages {
+            encoder.u32(page as u32);
+            encoder.raw(&bytes);
+        }
+    }
+
+    // The size is taken from the snapshot, so it must match the memory being
+    // restored rather than being trusted for an allocation
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let size = decoder.u64()?;
+        if size != self.size as u64 {
+            return Err(SnapshotError::Invalid);
+        }
+        let size = self.size;
+        let mut memory = match decoder.bool()? {
+            true => Memory::sparse(size),
+            false => Memory::new(size),
+        };
+        for _ in 0..decoder.length()? {
+            let start = decoder.u32()? as usize * PAGE_SIZE;
+            if start >= size {
+                return Err(SnapshotError::Invalid);
+            }
+            let bytes = decoder.raw(size.min(start + PAGE_SIZE) - start)?;
+            memory
+                .load_slice(start as u32, bytes)
+                .map_err(|_| SnapshotError::Invalid)?;
+        }
+        *self = memory;
+        Ok(())
+    }
+}
+
+impl Device for Memory {
+    fn read(&mut self, offset: u32, width: Width) -> Result<u64, ()> {
+        match width {
+            Width::Byte => self.read_u8(offset).map(u64::from),
+            Width::Half => self.read_u16(offset, Endianness::Little).map(u64::from),
+            Width::Word => self.read_u32(offset, Endianness::Little).map(u64::from),
+            Width::Double => self.read_u64(offset, Endianness::Little),
+        }
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u64) -> Result<(), ()> {
+        match width {
+            Width::Byte => self.write_u8(offset, value as u8),
+            Width::Half => self.write_u16(offset, value as u16, Endianness::Little),
+            Width::Word => self.write_u32(offset, value as u32, Endianness::Little),
+            Width::Double => self.write_u64(offset, value, Endianness::Little),
+        }
+    }
+
+    fn save_state(&self, encoder: &mut Encoder) {
+        self.save(encoder);
+    }
+
+    fn restore_state(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        self.restore(decoder)
+    }
+}
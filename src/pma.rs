@@ -0,0 +1,117 @@
+use crate::pmp::AccessType;
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const PMA_R: u8 = 1 << 0;
+pub const PMA_W: u8 = 1 << 1;
+pub const PMA_X: u8 = 1 << 2;
+// Cacheable main memory, as opposed to I/O regions with side effects
+pub const PMA_C: u8 = 1 << 3;
+
+pub const PMA_ROM: u8 = PMA_R | PMA_X | PMA_C;
+pub const PMA_RAM: u8 = PMA_R | PMA_W | PMA_X | PMA_C;
+pub const PMA_EXECUTE_ONLY: u8 = PMA_X | PMA_C;
+pub const PMA_DEVICE: u8 = PMA_R | PMA_W;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    base: u32,
+    size: u32,
+    attributes: u8,
+}
+
+impl Region {
+    fn end(&self) -> u64 {
+        u64::from(self.base) + u64::from(self.size)
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        (u64::from(self.base)..self.end()).contains(&u64::from(address))
+    }
+}
+
+// Physical memory attributes, fixed by the platform rather than software.
+// Addresses outside every declared region are not checked
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PMA {
+    regions: Vec<Region>,
+}
+
+impl PMA {
+    #[allow(clippy::result_unit_err)]
+    pub fn add(&mut self, base: u32, size: u32, attributes: u8) -> Result<(), ()> {
+        let region = Region {
+            base,
+            size,
+            attributes,
+        };
+        if size == 0 || region.end() > 1 << 32 {
+            return Err(());
+        }
+        let overlaps = self
+            .regions
+            .iter()
+            .any(|other| u64::from(base) < other.end() && u64::from(other.base) < region.end());
+        if overlaps {
+            return Err(());
+        }
+        self.regions.push(region);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, address: u32) {
+        self.regions.retain(|region| !region.contains(address));
+    }
+
+    pub fn attributes(&self, address: u32) -> Option<u8> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(address))
+            .map(|region| region.attributes)
+    }
+
+    pub fn is_cacheable(&self, address: u32) -> bool {
+        self.attributes(address)
+            .is_none_or(|attributes| attributes & PMA_C != 0)
+    }
+
+    pub fn check(&self, address: u32, size: u32, access: AccessType) -> bool {
+        let start = u64::from(address);
+        let end = start + u64::from(size);
+        let permission = match access {
+            AccessType::Read => PMA_R,
+            AccessType::Write => PMA_W,
+            AccessType::Execute => PMA_X,
+        };
+        self.regions.iter().all(|region| {
+            if end <= u64::from(region.base) || start >= region.end() {
+                return true;
+            }
+            // an access may not straddle the boundary of a region
+            start >= u64::from(region.base)
+                && end <= region.end()
+                && region.attributes & permission != 0
+        })
+    }
+}
+
+impl Snapshot for PMA {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.length(self.regions.len());
+        for region in self.regions.iter() {
+            encoder.u32(region.base);
+            encoder.u32(region.size);
+            encoder.u8(region.attributes);
+        }
+    }
+
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let mut pma = PMA::default();
+        for _ in 0..decoder.length()? {
+            let (base, size, attributes) = (decoder.u32()?, decoder.u32()?, decoder.u8()?);
+            pma.add(base, size, attributes)
+                .map_err(|_| SnapshotError::Invalid)?;
+        }
+        *self = pma;
+        Ok(())
+    }
+}
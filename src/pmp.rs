@@ -0,0 +1,171 @@
+use crate::csr::PrivilegeMode;
+use crate::snapshot::{Decoder, Encoder, Snapshot, SnapshotError};
+
+pub const PMP_ENTRIES: usize = 16;
+
+pub const PMP_R: u8 = 1 << 0;
+pub const PMP_W: u8 = 1 << 1;
+pub const PMP_X: u8 = 1 << 2;
+pub const PMP_A: u8 = 0b11 << 3;
+pub const PMP_L: u8 = 1 << 7;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AddressMatching {
+    OFF = 0,
+    TOR = 1,
+    NA4 = 2,
+    NAPOT = 3,
+}
+
+impl From<u8> for AddressMatching {
+    fn from(config: u8) -> Self {
+        match (config & PMP_A) >> 3 {
+            1 => AddressMatching::TOR,
+            2 => AddressMatching::NA4,
+            3 => AddressMatching::NAPOT,
+            _ => AddressMatching::OFF,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PMP {
+    config: [u8; PMP_ENTRIES],
+    address: [u32; PMP_ENTRIES],
+}
+
+impl PMP {
+    pub fn config(&self, entry: usize) -> u8 {
+        self.config[entry]
+    }
+
+    pub fn set_config(&mut self, entry: usize, value: u8) {
+        if self.is_locked(entry) {
+            return;
+        }
+        // R = 0, W = 1 is reserved, drop the write permission
+        let mut value = value & (PMP_R | PMP_W | PMP_X | PMP_A | PMP_L);
+        if value & PMP_R == 0 {
+            value &= !PMP_W;
+        }
+        self.config[entry] = value;
+    }
+
+    pub fn address(&self, entry: usize) -> u32 {
+        self.address[entry]
+    }
+
+    pub fn set_address(&mut self, entry: usize, value: u32) {
+        if self.is_locked(entry) {
+            return;
+        }
+        // a locked TOR entry also locks the address below it
+        if entry + 1 < PMP_ENTRIES
+            && self.is_locked(entry + 1)
+            && AddressMatching::from(self.config[entry + 1]) == AddressMatching::TOR
+        {
+            return;
+        }
+        self.address[entry] = value;
+    }
+
+    // pmpcfg registers pack four entries each on RV32
+    pub fn config_register(&self, index: usize) -> u32 {
+        (0..4).fold(0, |acc, i| {
+            acc | (u32::from(self.config[index * 4 + i]) << (8 * i))
+        })
+    }
+
+    pub fn set_config_register(&mut self, index: usize, value: u32) {
+        for i in 0..4 {
+            self.set_config(index * 4 + i, (value >> (8 * i)) as u8);
+        }
+    }
+
+    pub fn is_locked(&self, entry: usize) -> bool {
+        self.config[entry] & PMP_L != 0
+    }
+
+    // Byte range [start, end) covered by the entry, pmpaddr holds bits 33:2
+    fn range(&self, entry: usize) -> Option<(u64, u64)> {
+        let address = u64::from(self.address[entry]);
+        match AddressMatching::from(self.config[entry]) {
+            AddressMatching::OFF => None,
+            AddressMatching::TOR => {
+                let lower = if entry == 0 {
+                    0
+                } else {
+                    u64::from(self.address[entry - 1]) << 2
+                };
+                Some((lower, address << 2))
+            }
+            AddressMatching::NA4 => Some((address << 2, (address << 2) + 4)),
+            AddressMatching::NAPOT => {
+                let trailing = address.trailing_ones() as u64;
+                let base = (address & !((1 << trailing) - 1)) << 2;
+                Some((base, base + (1 << (trailing + 3))))
+            }
+        }
+    }
+
+    pub fn check(
+        &self,
+        address: u32,
+        size: u32,
+        access: AccessType,
+        privilege: PrivilegeMode,
+    ) -> bool {
+        let start = u64::from(address);
+        let end = start + u64::from(size);
+
+        // the lowest numbered entry matching any byte of the access decides
+        for entry in 0..PMP_ENTRIES {
+            let Some((lower, upper)) = self.range(entry) else {
+                continue;
+            };
+            if lower >= upper || end <= lower || start >= upper {
+                continue;
+            }
+            if start < lower || end > upper {
+                return false;
+            }
+            if privilege == PrivilegeMode::Machine && !self.is_locked(entry) {
+                return true;
+            }
+            let permission = match access {
+                AccessType::Read => PMP_R,
+                AccessType::Write => PMP_W,
+                AccessType::Execute => PMP_X,
+            };
+            return self.config[entry] & permission != 0;
+        }
+
+        privilege == PrivilegeMode::Machine
+    }
+}
+
+impl Snapshot for PMP {
+    fn save(&self, encoder: &mut Encoder) {
+        encoder.raw(&self.config);
+        self.address
+            .iter()
+            .for_each(|address| encoder.u32(*address));
+    }
+
+    // Restored directly, as locked entries cannot be written
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let config = decoder.raw(PMP_ENTRIES)?;
+        self.config.copy_from_slice(config);
+        for address in self.address.iter_mut() {
+            *address = decoder.u32()?;
+        }
+        Ok(())
+    }
+}
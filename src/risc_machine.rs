@@ -1,8 +1,30 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::assembler::Program;
+use crate::bus::{Bus, Device, Width};
+use crate::csr::{self, CSRFile, PrivilegeMode};
+use crate::devices::clic::{CLIC, CLIC_BASE, CLIC_SIZE};
+use crate::devices::clint::{CLINT, CLINT_BASE, CLINT_SIZE};
+use crate::devices::plic::{PLIC, PLIC_BASE, PLIC_SIZE};
+use crate::elf::coredump::{self, SIGILL};
+use crate::elf::dwarf::LineTable;
+use crate::elf::symbols::SymbolMap;
+use crate::elf::{ELFError, ELF};
+use crate::images::{ihex, srec, ImageError};
+pub use crate::memory::Memory;
+use crate::pma::PMA;
+use crate::pmp::AccessType;
 use crate::riscv_instruction::{
     ITypeIImmediateInstruction, RTypeInstructionFormat, STypeBImmediateInstruction,
-    STypeSImmediateInstruction, UTypeJImmediateInstruction,
+    STypeSImmediateInstruction, UTypeJImmediateInstruction, UTypeUImmediateInstruction,
+};
+use crate::snapshot::{
+    Decoder, Encoder, Snapshot, SnapshotError, SNAPSHOT_MAGIC, SNAPSHOT_VERSION,
 };
-use ux::{u20, u3, u5, u7};
+use crate::trap::{Exception, Interrupt};
+use ux::{u12, u3, u5, u7};
 
 trait RISCVInstruction {
     fn get_opcode(self) -> u7;
@@ -38,30 +60,61 @@ impl TryFrom<u32> for CPUInstruction {
                         });
                     }
                 }
+                if parsed.funct3 == u3::new(0b011) && parsed.funct7 == u7::new(0b0000000) {
+                    return Ok(CPUInstruction::SLTU {
+                        rd: parsed.rd,
+                        rs1: parsed.rs1,
+                        rs2: parsed.rs2,
+                    });
+                }
                 Err(())
             }
-            0b0100011 => {
-                let parsed = STypeSImmediateInstruction::from(value);
-                if parsed.funct3 != u3::new(0b010) {
-                    return Err(());
+            0b0010011 => {
+                let parsed = ITypeIImmediateInstruction::from(value);
+                let (rd, rs1, imm) = (parsed.rd, parsed.rs1, parsed.imm);
+                match u8::from(parsed.funct3) {
+                    0b000 => Ok(CPUInstruction::ADDI { rd, rs1, imm }),
+                    0b011 => Ok(CPUInstruction::SLTIU { rd, rs1, imm }),
+                    0b100 => Ok(CPUInstruction::XORI { rd, rs1, imm }),
+                    _ => Err(()),
                 }
-                Ok(CPUInstruction::SW {
-                    rs1: parsed.rs1,
-                    rs2: parsed.rs2,
+            }
+            0b0110111 => {
+                let parsed = UTypeUImmediateInstruction::from(value);
+                Ok(CPUInstruction::LUI {
+                    rd: parsed.rd,
                     imm: parsed.imm,
                 })
             }
-            0b0000011 => {
-                let parsed = ITypeIImmediateInstruction::from(value);
-                if parsed.funct3 != u3::new(0b010) {
-                    return Err(());
-                }
-                Ok(CPUInstruction::LW {
+            0b0010111 => {
+                let parsed = UTypeUImmediateInstruction::from(value);
+                Ok(CPUInstruction::AUIPC {
                     rd: parsed.rd,
-                    rs1: parsed.rs1,
                     imm: parsed.imm,
                 })
             }
+            0b0100011 => {
+                let parsed = STypeSImmediateInstruction::from(value);
+                let (rs1, rs2, imm) = (parsed.rs1, parsed.rs2, parsed.imm);
+                match u8::from(parsed.funct3) {
+                    0b000 => Ok(CPUInstruction::SB { rs1, rs2, imm }),
+                    0b001 => Ok(CPUInstruction::SH { rs1, rs2, imm }),
+                    0b010 => Ok(CPUInstruction::SW { rs1, rs2, imm }),
+                    _ => Err(()),
+                }
+            }
+            0b0000011 => {
+                let parsed = ITypeIImmediateInstruction::from(value);
+                let (rd, rs1, imm) = (parsed.rd, parsed.rs1, parsed.imm);
+                match u8::from(parsed.funct3) {
+                    0b000 => Ok(CPUInstruction::LB { rd, rs1, imm }),
+                    0b001 => Ok(CPUInstruction::LH { rd, rs1, imm }),
+                    0b010 => Ok(CPUInstruction::LW { rd, rs1, imm }),
+                    0b100 => Ok(CPUInstruction::LBU { rd, rs1, imm }),
+                    0b101 => Ok(CPUInstruction::LHU { rd, rs1, imm }),
+                    _ => Err(()),
+                }
+            }
             0b1101111 => {
                 let parsed = UTypeJImmediateInstruction::from(value);
                 Ok(CPUInstruction::JAL {
@@ -93,9 +146,55 @@ impl TryFrom<u32> for CPUInstruction {
                         imm: parsed.imm,
                     });
                 }
+                if parsed.funct3 == u3::new(0b100) {
+                    return Ok(CPUInstruction::BLT {
+                        rs1: parsed.rs1,
+                        rs2: parsed.rs2,
+                        imm: parsed.imm,
+                    });
+                }
+                if parsed.funct3 == u3::new(0b101) {
+                    return Ok(CPUInstruction::BGE {
+                        rs1: parsed.rs1,
+                        rs2: parsed.rs2,
+                        imm: parsed.imm,
+                    });
+                }
 
                 Err(())
             }
+            0b1110011 => {
+                let parsed = ITypeIImmediateInstruction::from(value);
+                let csr = u12::new((parsed.imm & 0xfff) as u16);
+                if parsed.funct3 == u3::new(0b001) {
+                    return Ok(CPUInstruction::CSRRW {
+                        rd: parsed.rd,
+                        rs1: parsed.rs1,
+                        csr,
+                    });
+                }
+                if parsed.funct3 == u3::new(0b010) {
+                    return Ok(CPUInstruction::CSRRS {
+                        rd: parsed.rd,
+                        rs1: parsed.rs1,
+                        csr,
+                    });
+                }
+                if parsed.funct3 == u3::new(0b011) {
+                    return Ok(CPUInstruction::CSRRC {
+                        rd: parsed.rd,
+                        rs1: parsed.rs1,
+                        csr,
+                    });
+                }
+                if value == u32::from(CPUInstruction::MRET) {
+                    return Ok(CPUInstruction::MRET);
+                }
+                if value == u32::from(CPUInstruction::WFI) {
+                    return Ok(CPUInstruction::WFI);
+                }
+                Err(())
+            }
             _ => Err(()),
         }
     }
@@ -122,6 +221,51 @@ impl From<CPUInstruction> for u32 {
                 rs2,
             }
             .into(),
+            CPUInstruction::SLTU { rd, rs1, rs2 } => RTypeInstructionFormat {
+                opcode: u7::new(0b0110011),
+                rd,
+                funct3: u3::new(0b011),
+                rs1,
+                funct7: u7::new(0b0000000),
+                rs2,
+            }
+            .into(),
+            CPUInstruction::ADDI { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0010011),
+                rd,
+                funct3: u3::new(0b000),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::SLTIU { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0010011),
+                rd,
+                funct3: u3::new(0b011),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::XORI { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0010011),
+                rd,
+                funct3: u3::new(0b100),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::LUI { rd, imm } => UTypeUImmediateInstruction {
+                opcode: u7::new(0b0110111),
+                rd,
+                imm,
+            }
+            .into(),
+            CPUInstruction::AUIPC { rd, imm } => UTypeUImmediateInstruction {
+                opcode: u7::new(0b0010111),
+                rd,
+                imm,
+            }
+            .into(),
             CPUInstruction::LW { rd, rs1, imm } => ITypeIImmediateInstruction {
                 opcode: u7::new(0b0000011),
                 rd,
@@ -138,6 +282,54 @@ impl From<CPUInstruction> for u32 {
                 imm,
             }
             .into(),
+            CPUInstruction::LB { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0000011),
+                rd,
+                funct3: u3::new(0b000),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::LH { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0000011),
+                rd,
+                funct3: u3::new(0b001),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::LBU { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0000011),
+                rd,
+                funct3: u3::new(0b100),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::LHU { rd, rs1, imm } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b0000011),
+                rd,
+                funct3: u3::new(0b101),
+                rs1,
+                imm,
+            }
+            .into(),
+            CPUInstruction::SB { rs1, rs2, imm } => STypeSImmediateInstruction {
+                opcode: u7::new(0b0100011),
+                funct3: u3::new(0b000),
+                rs1,
+                rs2,
+                imm,
+            }
+            .into(),
+            CPUInstruction::SH { rs1, rs2, imm } => STypeSImmediateInstruction {
+                opcode: u7::new(0b0100011),
+                funct3: u3::new(0b001),
+                rs1,
+                rs2,
+                imm,
+            }
+            .into(),
             CPUInstruction::JAL { rd, imm } => UTypeJImmediateInstruction {
                 opcode: u7::new(0b1101111),
                 rd,
@@ -168,6 +360,64 @@ impl From<CPUInstruction> for u32 {
                 imm,
             }
             .into(),
+            CPUInstruction::BLT { rs1, rs2, imm } => STypeBImmediateInstruction {
+                opcode: u7::new(0b1100011),
+                funct3: u3::new(0b100),
+                rs1,
+                rs2,
+                imm,
+            }
+            .into(),
+            CPUInstruction::BGE { rs1, rs2, imm } => STypeBImmediateInstruction {
+                opcode: u7::new(0b1100011),
+                funct3: u3::new(0b101),
+                rs1,
+                rs2,
+                imm,
+            }
+            .into(),
+            CPUInstruction::CSRRW { rd, rs1, csr } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b1110011),
+                rd,
+                funct3: u3::new(0b001),
+                rs1,
+                imm: csr.into(),
+            }
+            .into(),
+            CPUInstruction::CSRRS { rd, rs1, csr } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b1110011),
+                rd,
+                funct3: u3::new(0b010),
+                rs1,
+                imm: csr.into(),
+            }
+            .into(),
+            CPUInstruction::CSRRC { rd, rs1, csr } => ITypeIImmediateInstruction {
+                opcode: u7::new(0b1110011),
+                rd,
+                funct3: u3::new(0b011),
+                rs1,
+                imm: csr.into(),
+            }
+            .into(),
+            CPUInstruction::MRET => RTypeInstructionFormat {
+                opcode: u7::new(0b1110011),
+                rd: u5::new(0),
+                funct3: u3::new(0b000),
+                rs1: u5::new(0),
+                rs2: u5::new(0b00010),
+                funct7: u7::new(0b0011000),
+            }
+            .into(),
+            CPUInstruction::WFI => RTypeInstructionFormat {
+                opcode: u7::new(0b1110011),
+                rd: u5::new(0),
+                funct3: u3::new(0b000),
+                rs1: u5::new(0),
+                rs2: u5::new(0b00101),
+                funct7: u7::new(0b0001000),
+            }
+            .into(),
         }
     }
 }
@@ -176,12 +426,31 @@ impl From<CPUInstruction> for u32 {
 pub enum CPUInstruction {
     ADD { rd: u5, rs1: u5, rs2: u5 },
     SUB { rd: u5, rs1: u5, rs2: u5 },
+    SLTU { rd: u5, rs1: u5, rs2: u5 },
+    ADDI { rd: u5, rs1: u5, imm: u32 },
+    SLTIU { rd: u5, rs1: u5, imm: u32 },
+    XORI { rd: u5, rs1: u5, imm: u32 },
+    LUI { rd: u5, imm: u32 },
+    AUIPC { rd: u5, imm: u32 },
     LW { rd: u5, rs1: u5, imm: u32 },
     SW { rs1: u5, rs2: u5, imm: u32 },
+    LB { rd: u5, rs1: u5, imm: u32 },
+    LH { rd: u5, rs1: u5, imm: u32 },
+    LBU { rd: u5, rs1: u5, imm: u32 },
+    LHU { rd: u5, rs1: u5, imm: u32 },
+    SB { rs1: u5, rs2: u5, imm: u32 },
+    SH { rs1: u5, rs2: u5, imm: u32 },
     JAL { rd: u5, imm: u32 },
     JALR { rd: u5, rs1: u5, imm: u32 },
     BEQ { rs1: u5, rs2: u5, imm: u32 },
     BNE { rs1: u5, rs2: u5, imm: u32 },
+    BLT { rs1: u5, rs2: u5, imm: u32 },
+    BGE { rs1: u5, rs2: u5, imm: u32 },
+    CSRRW { rd: u5, rs1: u5, csr: u12 },
+    CSRRS { rd: u5, rs1: u5, csr: u12 },
+    CSRRC { rd: u5, rs1: u5, csr: u12 },
+    MRET,
+    WFI,
 }
 
 #[derive(Default, Debug)]
@@ -200,55 +469,297 @@ impl Registry {
     }
 }
 
-#[derive(Debug)]
-pub struct Memory(Vec<u8>);
+#[derive(Debug, Default)]
+pub enum InterruptModel {
+    // Interrupts are delivered through mie and mip, with the CLINT and PLIC
+    // driving the local interrupts
+    #[default]
+    CLINT,
+    // Interrupts are delivered through a core-local interrupt controller, with
+    // the local interrupts driving CLIC interrupts 3, 7 and 11
+    CLIC(CLIC),
+}
 
-impl Default for Memory {
-    fn default() -> Self {
-        Memory(vec![0; u32::from(u20::MAX) as usize])
-    }
+#[derive(Debug, Default)]
+pub struct RISCMachine {
+    pub memory: Memory,
+    pub registry: Registry,
+    pub program_counter: u32,
+    pub bus: Bus,
+    pub csr: CSRFile,
+    pub pma: PMA,
+    pub privilege_mode: PrivilegeMode,
+    pub interrupt_lines: u32,
+    pub clint: CLINT,
+    pub plic: PLIC,
+    pub interrupt_model: InterruptModel,
+    pub symbols: SymbolMap,
+    pub lines: LineTable,
 }
 
-impl Memory {
-    pub fn set(&mut self, index: u20, value: u8) {
-        self.0[u32::from(index) as usize] = value;
+// Builds a machine running a flat binary image, such as one produced by
+// objcopy -O binary, loaded at the base address
+#[derive(Debug, Default)]
+pub struct MachineBuilder {
+    memory: Memory,
+    image: Vec<u8>,
+    base: u32,
+    // defaults to the base address
+    entry: Option<u32>,
+}
+
+impl MachineBuilder {
+    pub fn memory(mut self, memory: Memory) -> Self {
+        self.memory = memory;
+        self
     }
 
-    pub fn get(&mut self, index: u20) -> u8 {
-        self.0[u32::from(index) as usize]
+    pub fn binary(mut self, bytes: &[u8]) -> Self {
+        self.image = bytes.to_vec();
+        self
+    }
+
+    pub fn file(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.binary(&fs::read(path)?))
+    }
+
+    pub fn base(mut self, base: u32) -> Self {
+        self.base = base;
+        self
     }
 
-    pub fn set_four_byte(&mut self, index: u20, value: u32) {
-        let start = u32::from(index) as usize;
-        self.0[start] = ((value >> 24) & 0xff) as u8;
-        self.0[start + 1] = ((value >> 16) & 0xff) as u8;
-        self.0[start + 2] = ((value >> 8) & 0xff) as u8;
-        self.0[start + 3] = ((value) & 0xff) as u8;
+    pub fn entry(mut self, entry: u32) -> Self {
+        self.entry = Some(entry);
+        self
     }
 
-    pub fn get_aligned(&self, index: u20) -> u32 {
-        let mut out: u32 = 0;
-        let start = u32::from(index) as usize;
-        out |= (self.0[start] as u32) << 24;
-        out |= (self.0[start + 1] as u32) << 16;
-        out |= (self.0[start + 2] as u32) << 8;
-        out |= self.0[start + 3] as u32;
-        out
+    #[allow(clippy::result_unit_err)]
+    pub fn build(self) -> Result<RISCMachine, ()> {
+        let mut machine = RISCMachine {
+            memory: self.memory,
+            ..RISCMachine::default()
+        };
+        machine.load_binary(&self.image, self.base, self.entry.unwrap_or(self.base))?;
+        Ok(machine)
     }
 }
 
-#[derive(Debug, Default)]
-pub struct RISCMachine {
-    pub memory: Memory,
-    pub registry: Registry,
-    pub program_counter: u20,
+// The symbols are not part of the state, and are kept from the loaded program
+impl Snapshot for RISCMachine {
+    fn save(&self, encoder: &mut Encoder) {
+        self.registry.0.iter().for_each(|value| encoder.u32(*value));
+        encoder.u32(self.program_counter);
+        encoder.u8(self.privilege_mode as u8);
+        encoder.u32(self.interrupt_lines);
+        self.csr.save(encoder);
+        self.pma.save(encoder);
+        self.clint.save(encoder);
+        self.plic.save(encoder);
+        match &self.interrupt_model {
+            InterruptModel::CLINT => encoder.u8(0),
+            InterruptModel::CLIC(clic) => {
+                encoder.u8(1);
+                clic.save(encoder);
+            }
+        }
+        self.memory.save(encoder);
+        self.bus.save(encoder);
+    }
+
+    // The bus is restored last, so that only the state of its devices can be
+    // left partially restored by a corrupt snapshot
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError> {
+        let mut registry = Registry::default();
+        for value in registry.0.iter_mut() {
+            *value = decoder.u32()?;
+        }
+        let program_counter = decoder.u32()?;
+        let privilege_mode = match decoder.u8()? {
+            0 => PrivilegeMode::User,
+            3 => PrivilegeMode::Machine,
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let interrupt_lines = decoder.u32()?;
+        let mut csr = self.csr.clone();
+        csr.restore(decoder)?;
+        let mut pma = PMA::default();
+        pma.restore(decoder)?;
+        let mut clint = CLINT::default();
+        clint.restore(decoder)?;
+        let mut plic = PLIC::default();
+        plic.restore(decoder)?;
+        let interrupt_model = match decoder.u8()? {
+            0 => InterruptModel::CLINT,
+            1 => {
+                let mut clic = CLIC::default();
+                clic.restore(decoder)?;
+                InterruptModel::CLIC(clic)
+            }
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let mut memory = Memory::sparse(self.memory.len());
+        memory.restore(decoder)?;
+        self.bus.restore(decoder)?;
+
+        self.registry = registry;
+        self.program_counter = program_counter;
+        self.privilege_mode = privilege_mode;
+        self.interrupt_lines = interrupt_lines;
+        self.csr = csr;
+        self.pma = pma;
+        self.clint = clint;
+        self.plic = plic;
+        self.interrupt_model = interrupt_model;
+        self.memory = memory;
+        Ok(())
+    }
 }
 
 impl RISCMachine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
     pub fn load_memory(&mut self, memory: Memory) {
         self.memory = memory;
     }
 
+    // Copy a flat binary image into main memory at the base address, and jump
+    // to the entry point
+    #[allow(clippy::result_unit_err)]
+    pub fn load_binary(&mut self, bytes: &[u8], base: u32, entry: u32) -> Result<(), ()> {
+        if entry as usize >= self.memory.len() {
+            return Err(());
+        }
+        self.memory.load_slice(base, bytes)?;
+        self.program_counter = entry;
+        Ok(())
+    }
+
+    // Copy the sections of an assembled program into main memory, keeping its
+    // labels as symbols, and jump to the entry point
+    #[allow(clippy::result_unit_err)]
+    pub fn load_program(&mut self, program: &Program) -> Result<(), ()> {
+        program.load(&mut self.memory)?;
+        self.symbols = program.symbols.clone();
+        self.program_counter = program.entry;
+        Ok(())
+    }
+
+    // Copy the loadable segments of an executable into main memory at their
+    // physical addresses, zeroing the remainder of each segment, and jump to
+    // the entry point
+    pub fn load_elf(&mut self, elf: &ELF) -> Result<(), ELFError> {
+        let misa = self.csr.get(u12::new(csr::MISA)).unwrap_or_default();
+        elf.validate(misa)?;
+        for segment in elf.loadable_segments() {
+            if segment.file_size > segment.memory_size {
+                return Err(ELFError::InvalidSegment);
+            }
+            let end = segment.physical_address + segment.memory_size;
+            if end > self.memory.len() as u64 {
+                return Err(ELFError::OutOfRange);
+            }
+        }
+        let entry = u32::try_from(elf.entry).map_err(|_| ELFError::OutOfRange)?;
+
+        for segment in elf.loadable_segments() {
+            let address = segment.physical_address as u32;
+            let data = elf.segment_data(segment);
+            let bss = vec![0; (segment.memory_size - segment.file_size) as usize];
+            self.memory
+                .load_slice(address, data)
+                .and_then(|_| {
+                    self.memory
+                        .load_slice(address + segment.file_size as u32, &bss)
+                })
+                .map_err(|_| ELFError::OutOfRange)?;
+        }
+        self.symbols = SymbolMap::from_elf(elf)?;
+        self.lines = LineTable::from_elf(elf)?;
+        self.program_counter = entry;
+        Ok(())
+    }
+
+    // Load an Intel HEX file, jumping to its start address if it has one
+    pub fn load_ihex(&mut self, text: &str) -> Result<(), ImageError> {
+        if let Some(start) = ihex::load(&mut self.memory, text)? {
+            self.program_counter = start;
+        }
+        Ok(())
+    }
+
+    // Load a Motorola S-record file, jumping to its start address if it has
+    // one
+    pub fn load_srec(&mut self, text: &str) -> Result<(), ImageError> {
+        if let Some(start) = srec::load(&mut self.memory, text)? {
+            self.program_counter = start;
+        }
+        Ok(())
+    }
+
+    // A versioned snapshot of the machine state, which can be restored onto a
+    // machine with the same memory size and devices attached
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.raw(SNAPSHOT_MAGIC);
+        encoder.u32(SNAPSHOT_VERSION);
+        self.save(&mut encoder);
+        encoder.into_bytes()
+    }
+
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut decoder = Decoder::new(bytes);
+        if decoder.raw(SNAPSHOT_MAGIC.len()) != Ok(SNAPSHOT_MAGIC) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = decoder.u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        self.restore(&mut decoder)?;
+        if !decoder.is_empty() {
+            return Err(SnapshotError::Invalid);
+        }
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        Ok(fs::write(path, self.snapshot())?)
+    }
+
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        self.restore_snapshot(&fs::read(path)?)
+    }
+
+    // An ELF core file of the machine, which can be opened with gdb
+    pub fn core_dump(&self, signal: u32) -> Vec<u8> {
+        coredump::write(&self.memory, &self.registry.0, self.program_counter, signal)
+    }
+
+    // The symbol and source location of an address, such as main+0x10
+    // (main.c:42), for use in traces and errors
+    pub fn describe(&self, address: u32) -> String {
+        let symbol = self.symbols.describe(address.into());
+        match self.lines.lookup(address.into()) {
+            Some(location) => format!("{} ({})", symbol, location),
+            None => symbol,
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn symbol_address(&self, name: &str) -> Result<u32, ()> {
+        let address = self.symbols.address_of(name).ok_or(())?;
+        u32::try_from(address).map_err(|_| ())
+    }
+
+    // Read the value of a global variable
+    #[allow(clippy::result_unit_err)]
+    pub fn read_symbol(&mut self, name: &str, width: Width) -> Result<u32, ()> {
+        let address = self.symbol_address(name)?;
+        self.host_read(address, width)
+    }
+
     fn execute(&mut self, instruction: CPUInstruction) -> Result<(), ()> {
         match instruction {
             CPUInstruction::ADD { rd, rs1, rs2 } => {
@@ -257,7 +768,7 @@ impl RISCMachine {
                     .get(rs1)
                     .saturating_add(self.registry.get(rs2));
                 self.registry.set(rd, value);
-                self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                self.program_counter = self.program_counter.wrapping_add(4);
                 Ok(())
             }
             CPUInstruction::SUB { rd, rs1, rs2 } => {
@@ -266,84 +777,579 @@ impl RISCMachine {
                     .get(rs1)
                     .saturating_sub(self.registry.get(rs2));
                 self.registry.set(rd, value);
-                self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            CPUInstruction::SLTU { rd, rs1, rs2 } => {
+                let value = self.registry.get(rs1) < self.registry.get(rs2);
+                self.registry.set(rd, value.into());
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            CPUInstruction::ADDI { rd, rs1, imm } => {
+                self.registry
+                    .set(rd, self.registry.get(rs1).wrapping_add(imm));
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            // The immediate is sign extended, then compared as unsigned
+            CPUInstruction::SLTIU { rd, rs1, imm } => {
+                let value = self.registry.get(rs1) < imm;
+                self.registry.set(rd, value.into());
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            CPUInstruction::XORI { rd, rs1, imm } => {
+                self.registry.set(rd, self.registry.get(rs1) ^ imm);
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            CPUInstruction::LUI { rd, imm } => {
+                self.registry.set(rd, imm);
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
+            CPUInstruction::AUIPC { rd, imm } => {
+                self.registry
+                    .set(rd, self.program_counter.wrapping_add(imm));
+                self.program_counter = self.program_counter.wrapping_add(4);
                 Ok(())
             }
             CPUInstruction::LW { rd, rs1, imm } => {
-                let value = self.memory.get_aligned(u20::new(u32::from(rs1) + imm));
-                self.registry.set(rd, value);
-                self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                self.execute_load(rd, rs1, imm, Width::Word, |value| value);
+                Ok(())
+            }
+            CPUInstruction::LH { rd, rs1, imm } => {
+                self.execute_load(rd, rs1, imm, Width::Half, |value| {
+                    value as u16 as i16 as u32
+                });
+                Ok(())
+            }
+            CPUInstruction::LHU { rd, rs1, imm } => {
+                self.execute_load(rd, rs1, imm, Width::Half, |value| value);
+                Ok(())
+            }
+            CPUInstruction::LB { rd, rs1, imm } => {
+                self.execute_load(rd, rs1, imm, Width::Byte, |value| value as u8 as i8 as u32);
+                Ok(())
+            }
+            CPUInstruction::LBU { rd, rs1, imm } => {
+                self.execute_load(rd, rs1, imm, Width::Byte, |value| value);
                 Ok(())
             }
             CPUInstruction::SW { rs1, rs2, imm } => {
-                let value = self.registry.get(rs2);
-                let index = self.registry.get(rs1) + imm;
-                self.memory.set_four_byte(u20::new(index), value);
-                self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                self.execute_store(rs1, rs2, imm, Width::Word);
+                Ok(())
+            }
+            CPUInstruction::SH { rs1, rs2, imm } => {
+                self.execute_store(rs1, rs2, imm, Width::Half);
+                Ok(())
+            }
+            CPUInstruction::SB { rs1, rs2, imm } => {
+                self.execute_store(rs1, rs2, imm, Width::Byte);
                 Ok(())
             }
             CPUInstruction::JAL { rd, imm } => {
-                self.registry.set(
-                    rd,
-                    u32::from(self.program_counter.wrapping_add(u20::new(4))),
-                );
+                self.registry.set(rd, self.program_counter.wrapping_add(4));
 
-                self.program_counter =
-                    u20::new(((u32::from(self.program_counter) as i32) + imm as i32) as u32);
+                self.program_counter = self.program_counter.wrapping_add(imm);
                 Ok(())
             }
             CPUInstruction::JALR { rd, rs1, imm } => {
-                self.registry.set(
-                    rd,
-                    u32::from(self.program_counter.wrapping_add(u20::new(4))),
-                );
+                let value = self.registry.get(rs1).wrapping_add(imm) & 0xfffffffe;
+                self.registry.set(rd, self.program_counter.wrapping_add(4));
 
-                let value = (self.registry.get(rs1) + imm) & 0xfffffffe;
-                if value > u32::from(u20::MAX) {
-                    return Err(());
-                }
-                self.program_counter = u20::new(value);
+                self.program_counter = value;
                 Ok(())
             }
             CPUInstruction::BEQ { rs1, rs2, imm } => {
                 if self.registry.get(rs1) == self.registry.get(rs2) {
-                    self.program_counter =
-                        u20::new(((u32::from(self.program_counter) as i32) + imm as i32) as u32);
+                    self.program_counter = self.program_counter.wrapping_add(imm);
                 } else {
-                    self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                    self.program_counter = self.program_counter.wrapping_add(4);
                 }
                 Ok(())
             }
             CPUInstruction::BNE { rs1, rs2, imm } => {
                 if self.registry.get(rs1) != self.registry.get(rs2) {
-                    self.program_counter =
-                        u20::new(((u32::from(self.program_counter) as i32) + imm as i32) as u32);
+                    self.program_counter = self.program_counter.wrapping_add(imm);
                 } else {
-                    self.program_counter = self.program_counter.wrapping_add(u20::new(4));
+                    self.program_counter = self.program_counter.wrapping_add(4);
                 }
                 Ok(())
             }
+            CPUInstruction::BLT { rs1, rs2, imm } => {
+                if (self.registry.get(rs1) as i32) < (self.registry.get(rs2) as i32) {
+                    self.program_counter = self.program_counter.wrapping_add(imm);
+                } else {
+                    self.program_counter = self.program_counter.wrapping_add(4);
+                }
+                Ok(())
+            }
+            CPUInstruction::BGE { rs1, rs2, imm } => {
+                if (self.registry.get(rs1) as i32) >= (self.registry.get(rs2) as i32) {
+                    self.program_counter = self.program_counter.wrapping_add(imm);
+                } else {
+                    self.program_counter = self.program_counter.wrapping_add(4);
+                }
+                Ok(())
+            }
+            CPUInstruction::CSRRW { rd, rs1, csr } => {
+                let value = self.registry.get(rs1);
+                self.update_csr(instruction, rd, csr, true, |_| value);
+                Ok(())
+            }
+            CPUInstruction::CSRRS { rd, rs1, csr } => {
+                let mask = self.registry.get(rs1);
+                self.update_csr(instruction, rd, csr, u32::from(rs1) != 0, |old| old | mask);
+                Ok(())
+            }
+            CPUInstruction::CSRRC { rd, rs1, csr } => {
+                let mask = self.registry.get(rs1);
+                self.update_csr(instruction, rd, csr, u32::from(rs1) != 0, |old| old & !mask);
+                Ok(())
+            }
+            CPUInstruction::MRET => {
+                if self.privilege_mode != PrivilegeMode::Machine {
+                    self.raise_exception(Exception::IllegalInstruction, instruction.into());
+                    return Ok(());
+                }
+                self.privilege_mode = self.csr.return_from_trap();
+                if let InterruptModel::CLIC(clic) = &mut self.interrupt_model {
+                    clic.mil = (self.csr.mcause >> 16) as u8;
+                }
+                self.program_counter = self.csr.mepc;
+                Ok(())
+            }
+            // Waiting until an interrupt is pending is not required, so this
+            // is treated as a hint
+            CPUInstruction::WFI => {
+                self.program_counter = self.program_counter.wrapping_add(4);
+                Ok(())
+            }
         }
     }
 
-    fn get_next_instruction(&self) -> u32 {
-        self.memory.get_aligned(self.program_counter)
+    fn execute_load(
+        &mut self,
+        rd: u5,
+        rs1: u5,
+        imm: u32,
+        width: Width,
+        extend: impl Fn(u32) -> u32,
+    ) {
+        let index = self.registry.get(rs1).wrapping_add(imm);
+        match self.load(index, width) {
+            Ok(value) => {
+                self.registry.set(rd, extend(value));
+                self.program_counter = self.program_counter.wrapping_add(4);
+            }
+            Err(exception) => self.raise_exception(exception, index),
+        }
     }
 
-    pub fn tick(&mut self) -> Result<(), ()> {
-        let instruction = self.get_next_instruction();
-        self.execute(instruction.try_into()?)?;
+    fn execute_store(&mut self, rs1: u5, rs2: u5, imm: u32, width: Width) {
+        let value = self.registry.get(rs2);
+        let index = self.registry.get(rs1).wrapping_add(imm);
+        match self.store(index, width, value) {
+            Ok(()) => {
+                self.program_counter = self.program_counter.wrapping_add(4);
+            }
+            Err(exception) => self.raise_exception(exception, index),
+        }
+    }
+
+    fn update_csr(
+        &mut self,
+        instruction: CPUInstruction,
+        rd: u5,
+        csr: u12,
+        write: bool,
+        update: impl Fn(u32) -> u32,
+    ) {
+        if !csr::is_accessible(csr, self.privilege_mode) || (write && csr::is_read_only(csr)) {
+            self.raise_exception(Exception::IllegalInstruction, instruction.into());
+            return;
+        }
+        if u16::from(csr) == csr::MNXTI {
+            self.update_mnxti(instruction, rd, write, update);
+            return;
+        }
+        let Ok(old) = self.read_csr(csr) else {
+            self.raise_exception(Exception::IllegalInstruction, instruction.into());
+            return;
+        };
+        if write && self.write_csr(csr, update(old)).is_err() {
+            self.raise_exception(Exception::IllegalInstruction, instruction.into());
+            return;
+        }
+        self.registry.set(rd, old);
+        self.program_counter = self.program_counter.wrapping_add(4);
+    }
+
+    // The mnxti write updates mstatus, while the read claims the next
+    // interrupt and returns a pointer to its vector table entry
+    fn update_mnxti(
+        &mut self,
+        instruction: CPUInstruction,
+        rd: u5,
+        write: bool,
+        update: impl Fn(u32) -> u32,
+    ) {
+        let InterruptModel::CLIC(clic) = &mut self.interrupt_model else {
+            self.raise_exception(Exception::IllegalInstruction, instruction.into());
+            return;
+        };
+        let previous_level = (self.csr.mcause >> 16) as u8;
+        let value = match clic.next_interrupt(previous_level) {
+            Some(id) => {
+                self.csr.mcause = (self.csr.mcause & !0xfff) | id as u32;
+                clic.mtvt + 4 * id as u32
+            }
+            None => 0,
+        };
+        if write {
+            self.csr.mstatus = update(self.csr.mstatus);
+        }
+        self.registry.set(rd, value);
+        self.program_counter = self.program_counter.wrapping_add(4);
+    }
+
+    fn read_csr(&self, csr: u12) -> Result<u32, ()> {
+        let InterruptModel::CLIC(clic) = &self.interrupt_model else {
+            return self.csr.get(csr);
+        };
+        match u16::from(csr) {
+            csr::MTVT => Ok(clic.mtvt),
+            csr::MINTTHRESH => Ok(clic.mintthresh.into()),
+            csr::MINTSTATUS => Ok(u32::from(clic.mil) << 24),
+            // mcause.mpp (29:28) and mcause.mpie (27) mirror mstatus
+            csr::MCAUSE => {
+                let mirrored = ((self.csr.mstatus & csr::MSTATUS_MPP) << 17)
+                    | ((self.csr.mstatus & csr::MSTATUS_MPIE) << 20);
+                Ok((self.csr.mcause & !(0b111 << 27)) | mirrored)
+            }
+            // mie and mip are hardwired to zero in CLIC mode
+            csr::MIE | csr::MIP => Ok(0),
+            _ => self.csr.get(csr),
+        }
+    }
+
+    fn write_csr(&mut self, csr: u12, value: u32) -> Result<(), ()> {
+        let InterruptModel::CLIC(clic) = &mut self.interrupt_model else {
+            return self.csr.set(csr, value);
+        };
+        match u16::from(csr) {
+            // the vector table must be 64 byte aligned
+            csr::MTVT => clic.mtvt = value & !0b111111,
+            csr::MINTTHRESH => clic.mintthresh = value as u8,
+            csr::MTVEC => self.csr.mtvec = (value & !0b111111) | csr::MTVEC_CLIC,
+            csr::MCAUSE => {
+                let mirrored =
+                    ((value >> 17) & csr::MSTATUS_MPP) | ((value >> 20) & csr::MSTATUS_MPIE);
+                self.csr.mstatus =
+                    (self.csr.mstatus & !(csr::MSTATUS_MPP | csr::MSTATUS_MPIE)) | mirrored;
+                self.csr.mcause = value & !(0b111 << 27);
+            }
+            csr::MIE | csr::MIP => {}
+            _ => return self.csr.set(csr, value),
+        }
         Ok(())
     }
 
-    pub fn run(&mut self, until: &dyn Fn(u20) -> bool) -> Result<(), ()> {
+    fn trap(&mut self, cause: u32, tval: u32) {
+        self.csr
+            .enter_trap(self.privilege_mode, self.program_counter, cause, tval);
+        self.privilege_mode = PrivilegeMode::Machine;
+        self.program_counter = self.csr.trap_vector(cause);
+    }
+
+    pub fn raise_exception(&mut self, exception: Exception, tval: u32) {
+        let mut cause = exception.code();
+        // the interrupt level is saved in mcause.mpil on any trap in CLIC mode
+        if let InterruptModel::CLIC(clic) = &self.interrupt_model {
+            cause |= u32::from(clic.mil) << 16;
+        }
+        self.trap(cause, tval);
+    }
+
+    pub fn assert_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_lines |= interrupt.mask();
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_lines &= !interrupt.mask();
+    }
+
+    fn update_interrupts(&mut self) {
+        for (source, level) in self.bus.interrupts() {
+            self.plic.set_level(source, level);
+            // platform interrupts are numbered from 16 in the CLIC
+            if let InterruptModel::CLIC(clic) = &mut self.interrupt_model {
+                clic.set_level(15 + source, level);
+            }
+        }
+
+        let mut pending = self.interrupt_lines;
+        if self.clint.timer_pending(0) {
+            pending |= Interrupt::MachineTimer.mask();
+        }
+        if self.clint.software_pending(0) {
+            pending |= Interrupt::MachineSoftware.mask();
+        }
+        if self.plic.interrupt_pending(0) {
+            pending |= Interrupt::MachineExternal.mask();
+        }
+        self.csr.mip = pending;
+        if let InterruptModel::CLIC(clic) = &mut self.interrupt_model {
+            for interrupt in Interrupt::ALL {
+                clic.set_level(interrupt.code() as usize, pending & interrupt.mask() != 0);
+            }
+        }
+    }
+
+    // Take the highest priority interrupt if any, returning if it was taken
+    fn take_interrupt(&mut self) -> bool {
+        let globally_enabled = self.privilege_mode < PrivilegeMode::Machine
+            || self.csr.mstatus & csr::MSTATUS_MIE != 0;
+        let clic = match &mut self.interrupt_model {
+            InterruptModel::CLINT => {
+                let Some(interrupt) = self.csr.pending_interrupt(self.privilege_mode) else {
+                    return false;
+                };
+                self.trap(interrupt.cause(), 0);
+                return true;
+            }
+            InterruptModel::CLIC(clic) => clic,
+        };
+        if !globally_enabled {
+            return false;
+        }
+
+        // the threshold only applies to interrupts of the current privilege
+        let threshold = if self.privilege_mode == PrivilegeMode::Machine {
+            clic.mil.max(clic.mintthresh)
+        } else {
+            0
+        };
+        let Some(id) = clic.pending_interrupt(threshold) else {
+            return false;
+        };
+        let previous_level = clic.mil;
+        clic.mil = clic.level(id);
+        let vectored = clic.is_vectored(id);
+        let entry = clic.mtvt + 4 * id as u32;
+        if vectored {
+            clic.acknowledge(id);
+        }
+
+        self.trap((1 << 31) | (u32::from(previous_level) << 16) | id as u32, 0);
+        // selective hardware vectoring jumps through the vector table
+        if vectored {
+            match self.fetch(entry) {
+                Ok(target) => {
+                    self.program_counter = target & !1;
+                }
+                Err(exception) => self.raise_exception(exception, entry),
+            }
+        }
+        true
+    }
+
+    // Route a physical access to the interrupt controllers, then the devices
+    // on the bus, then main memory
+    fn read_physical(&mut self, address: u32, width: Width) -> Result<u32, ()> {
+        if (CLINT_BASE..CLINT_BASE + CLINT_SIZE).contains(&address) {
+            if width != Width::Word {
+                return Err(());
+            }
+            return self.clint.read(address - CLINT_BASE);
+        }
+        if (PLIC_BASE..PLIC_BASE + PLIC_SIZE).contains(&address) {
+            if width != Width::Word {
+                return Err(());
+            }
+            return self.plic.read(address - PLIC_BASE);
+        }
+        if let InterruptModel::CLIC(clic) = &self.interrupt_model {
+            if (CLIC_BASE..CLIC_BASE + CLIC_SIZE).contains(&address) {
+                if width != Width::Word {
+                    return Err(());
+                }
+                return clic.read(address - CLIC_BASE);
+            }
+        }
+        if let Some(result) = self.bus.read(address, width) {
+            return result.map(|value| value as u32);
+        }
+        self.memory.read(address, width).map(|value| value as u32)
+    }
+
+    fn write_physical(&mut self, address: u32, width: Width, value: u32) -> Result<(), ()> {
+        if (CLINT_BASE..CLINT_BASE + CLINT_SIZE).contains(&address) {
+            if width != Width::Word {
+                return Err(());
+            }
+            return self.clint.write(address - CLINT_BASE, value);
+        }
+        if (PLIC_BASE..PLIC_BASE + PLIC_SIZE).contains(&address) {
+            if width != Width::Word {
+                return Err(());
+            }
+            return self.plic.write(address - PLIC_BASE, value);
+        }
+        if let InterruptModel::CLIC(clic) = &mut self.interrupt_model {
+            if (CLIC_BASE..CLIC_BASE + CLIC_SIZE).contains(&address) {
+                if width != Width::Word {
+                    return Err(());
+                }
+                return clic.write(address - CLIC_BASE, value);
+            }
+        }
+        if let Some(result) = self.bus.write(address, width, value.into()) {
+            return result;
+        }
+        self.memory.write(address, width, value.into())
+    }
+
+    // Both the physical memory attributes and the physical memory protection
+    // must permit an access
+    fn is_permitted(&self, index: u32, size: u32, access: AccessType) -> bool {
+        self.pma.check(index, size, access)
+            && self.csr.pmp.check(index, size, access, self.privilege_mode)
+    }
+
+    // Access memory from the host, respecting the physical memory attributes
+    #[allow(clippy::result_unit_err)]
+    pub fn host_read(&mut self, address: u32, width: Width) -> Result<u32, ()> {
+        if !self.pma.check(address, width.bytes(), AccessType::Read) {
+            return Err(());
+        }
+        self.read_physical(address, width)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn host_write(&mut self, address: u32, width: Width, value: u32) -> Result<(), ()> {
+        if !self.pma.check(address, width.bytes(), AccessType::Write) {
+            return Err(());
+        }
+        self.write_physical(address, width, value)
+    }
+
+    fn load(&mut self, index: u32, width: Width) -> Result<u32, Exception> {
+        if !self.is_permitted(index, width.bytes(), AccessType::Read) {
+            return Err(Exception::LoadAccessFault);
+        }
+        self.read_physical(index, width)
+            .map_err(|_| Exception::LoadAccessFault)
+    }
+
+    fn store(&mut self, index: u32, width: Width, value: u32) -> Result<(), Exception> {
+        if !self.is_permitted(index, width.bytes(), AccessType::Write) {
+            return Err(Exception::StoreAccessFault);
+        }
+        self.write_physical(index, width, value)
+            .map_err(|_| Exception::StoreAccessFault)
+    }
+
+    fn fetch(&mut self, index: u32) -> Result<u32, Exception> {
+        if !self.is_permitted(index, 4, AccessType::Execute) {
+            return Err(Exception::InstructionAccessFault);
+        }
+        self.read_physical(index, Width::Word)
+            .map_err(|_| Exception::InstructionAccessFault)
+    }
+
+    fn get_next_instruction(&mut self) -> Result<u32, Exception> {
+        self.fetch(self.program_counter)
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn tick(&mut self) -> Result<(), ()> {
+        self.clint.advance(1);
+        self.bus.tick(&mut self.memory);
+        self.update_interrupts();
+        if self.take_interrupt() {
+            return Ok(());
+        }
+
+        let instruction = match self.get_next_instruction() {
+            Ok(instruction) => instruction,
+            Err(exception) => {
+                self.raise_exception(exception, self.program_counter);
+                return Ok(());
+            }
+        };
+        match instruction.try_into() {
+            Ok(decoded) => self.execute(decoded),
+            Err(()) => {
+                self.raise_exception(Exception::IllegalInstruction, instruction);
+                Ok(())
+            }
+        }
+    }
+
+    // Run until the predicate holds on the program counter, or until a device
+    // requests an exit in which case the exit code is returned
+    #[allow(clippy::result_unit_err)]
+    pub fn run(&mut self, until: &dyn Fn(u32) -> bool) -> Result<Option<u32>, ()> {
+        self.run_checked(until, &|_| Ok(()))
+    }
+
+    // Run as with run, stopping with an error if the check fails before a tick
+    fn run_checked(
+        &mut self,
+        until: &dyn Fn(u32) -> bool,
+        check: &dyn Fn(&mut RISCMachine) -> Result<(), ()>,
+    ) -> Result<Option<u32>, ()> {
         loop {
+            check(self)?;
             self.tick()?;
+            if let Some(exit_code) = self.bus.exit_code() {
+                return Ok(Some(exit_code));
+            }
             if until(self.program_counter) {
-                break;
+                return Ok(None);
             }
         }
+    }
 
-        Ok(())
+    // Run as with run, writing a core file to the path if an instruction cannot
+    // be executed. An instruction which cannot be decoded would trap, so the
+    // run stops before it instead
+    #[allow(clippy::result_unit_err)]
+    pub fn run_with_core_dump(
+        &mut self,
+        until: &dyn Fn(u32) -> bool,
+        path: impl AsRef<Path>,
+    ) -> Result<Option<u32>, ()> {
+        let decodable = |machine: &mut RISCMachine| {
+            let instruction = machine.host_read(machine.program_counter, Width::Word);
+            match instruction.map(CPUInstruction::try_from) {
+                Ok(Err(())) => Err(()),
+                _ => Ok(()),
+            }
+        };
+        self.run_checked(until, &decodable).inspect_err(|_| {
+            // the run has already failed, so an error writing the core is
+            // not reported
+            let _ = fs::write(path, self.core_dump(SIGILL));
+        })
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn run_until_symbol(&mut self, name: &str) -> Result<Option<u32>, ()> {
+        let address = self.symbol_address(name)?;
+        self.run(&|pc| pc == address)
+    }
+
+    // Run until the function is called and then returns to its caller
+    #[allow(clippy::result_unit_err)]
+    pub fn run_until_return(&mut self, name: &str) -> Result<Option<u32>, ()> {
+        if let Some(exit_code) = self.run_until_symbol(name)? {
+            return Ok(Some(exit_code));
+        }
+        let return_address = self.registry.get(u5::new(1));
+        self.run(&|pc| pc == return_address)
     }
 }
@@ -130,7 +130,7 @@ where
                 ret |= (u32::from(bool::from(self.im_20()))) << 20;
                 ret |= (u32::from(self.im_21_24())) << 21;
                 ret |= (u32::from(self.im_25_30())) << 25;
-                ret |= (u32::from(bool::from(self.im_20()))) << 31;
+                ret |= (u32::from(bool::from(self.im_31()))) << 31;
                 ret
             }
             RISCVImmediate::J => {
@@ -393,3 +393,28 @@ impl From<UTypeJImmediateInstruction> for u32 {
             .with_immediate(value.imm, &RISCVImmediate::J)
     }
 }
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct UTypeUImmediateInstruction {
+    pub opcode: u7,
+    pub rd: u5,
+    pub imm: u32,
+}
+
+impl From<u32> for UTypeUImmediateInstruction {
+    fn from(value: u32) -> Self {
+        Self {
+            opcode: value.opcode(),
+            rd: value.rd(),
+            imm: value.immediate(&RISCVImmediate::U),
+        }
+    }
+}
+
+impl From<UTypeUImmediateInstruction> for u32 {
+    fn from(value: UTypeUImmediateInstruction) -> Self {
+        0.with_opcode(value.opcode)
+            .with_rd(value.rd)
+            .with_immediate(value.imm, &RISCVImmediate::U)
+    }
+}
@@ -0,0 +1,192 @@
+use std::io;
+
+// A snapshot starts with the magic and the version of the format, followed by
+// the state of each part of the machine in a fixed order. Integers are little
+// endian and variable length fields are preceded by their length
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"RVSNAP\0\0";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    // a field holds a value which cannot be restored
+    Invalid,
+    // the snapshot holds the state of a device which is not attached at the
+    // given base address
+    MissingDevice(u32),
+    Io(io::ErrorKind),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error.kind())
+    }
+}
+
+// State which can be saved into a snapshot, and later restored in place
+pub trait Snapshot {
+    fn save(&self, encoder: &mut Encoder);
+    fn restore(&mut self, decoder: &mut Decoder) -> Result<(), SnapshotError>;
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value.into());
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    // A flag for whether the value is present, followed by the value
+    pub fn optional_u32(&mut self, value: Option<u32>) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            self.u32(value);
+        }
+    }
+
+    pub fn length(&mut self, length: usize) {
+        self.u64(length as u64);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.length(bytes.len());
+        self.raw(bytes);
+    }
+
+    // Bytes whose length is known when restoring
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    pub fn bools(&mut self, values: &[bool]) {
+        self.length(values.len());
+        values.iter().for_each(|value| self.bool(*value));
+    }
+
+    pub fn u32s(&mut self, values: &[u32]) {
+        self.length(values.len());
+        values.iter().for_each(|value| self.u32(*value));
+    }
+
+    pub fn u64s(&mut self, values: &[u64]) {
+        self.length(values.len());
+        values.iter().for_each(|value| self.u64(*value));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn raw(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        if length > self.bytes.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(length);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        self.raw(N)?
+            .try_into()
+            .map_err(|_| SnapshotError::Truncated)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Invalid),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn optional_u32(&mut self) -> Result<Option<u32>, SnapshotError> {
+        match self.bool()? {
+            true => Ok(Some(self.u32()?)),
+            false => Ok(None),
+        }
+    }
+
+    // A length which cannot exceed the remaining bytes, so that a corrupt
+    // snapshot cannot request a huge allocation
+    pub fn length(&mut self) -> Result<usize, SnapshotError> {
+        let length = usize::try_from(self.u64()?).map_err(|_| SnapshotError::Truncated)?;
+        if length > self.bytes.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        Ok(length)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let length = self.length()?;
+        self.raw(length)
+    }
+
+    pub fn bools(&mut self) -> Result<Vec<bool>, SnapshotError> {
+        (0..self.length()?).map(|_| self.bool()).collect()
+    }
+
+    pub fn u32s(&mut self) -> Result<Vec<u32>, SnapshotError> {
+        (0..self.length()?).map(|_| self.u32()).collect()
+    }
+
+    pub fn u64s(&mut self) -> Result<Vec<u64>, SnapshotError> {
+        (0..self.length()?).map(|_| self.u64()).collect()
+    }
+}
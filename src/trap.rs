@@ -0,0 +1,41 @@
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Exception {
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    LoadAccessFault = 5,
+    StoreAccessFault = 7,
+}
+
+impl Exception {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+// Listed in decreasing priority
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Interrupt {
+    MachineExternal = 11,
+    MachineSoftware = 3,
+    MachineTimer = 7,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 3] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+    ];
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn mask(self) -> u32 {
+        1 << self.code()
+    }
+
+    pub fn cause(self) -> u32 {
+        (1 << 31) | self.code()
+    }
+}